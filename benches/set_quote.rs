@@ -0,0 +1,150 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use market_data_aggregator::solutions::fast::*;
+use market_data_aggregator::solutions::slow_for_comparisons::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+const RAW_LEVELS: usize = 1000;
+const FIRST_PRICE: u64 = 10_000;
+/// Raw levels are 2 apart, so that odd prices are free for inserts
+const PRICE_STEP: u64 = 2;
+const MAX_DEPTH: usize = 300;
+const UPDATES: usize = 4096;
+
+fn level_price(index: usize) -> u64 {
+    FIRST_PRICE + index as u64 * PRICE_STEP
+}
+
+fn initial_amounts() -> Vec<Amount> {
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    (0..RAW_LEVELS).map(|_| rng.gen_range(1..=100)).collect()
+}
+
+fn rule_tables() -> Vec<(&'static str, SubscriptionRules)> {
+    vec![
+        ("fine", SubscriptionRules::new(vec![20, 50], 100, MAX_DEPTH)),
+        ("coarse", SubscriptionRules::new(vec![1000, 3000], 5000, MAX_DEPTH)),
+        (
+            "capped",
+            SubscriptionRules::new(vec![200], 500, MAX_DEPTH).with_maximum_schedule(ThresholdSchedule::Table {
+                amounts: vec![300],
+                fallback: 800,
+            }),
+        ),
+    ]
+}
+
+/// Every change is followed by its undo, so the book stays the same on average however long it runs
+fn scenario_updates(scenario: &str, amounts: &[Amount]) -> Vec<(u64, Amount)> {
+    let mut rng = ChaCha8Rng::seed_from_u64(2);
+    let mut updates = Vec::with_capacity(UPDATES);
+    while updates.len() < UPDATES {
+        let (price, amount, restored_amount) = match scenario {
+            "touch" => {
+                let index = rng.gen_range(0..5);
+                (level_price(index), rng.gen_range(1..=100), amounts[index])
+            }
+            "deep" => {
+                let index = rng.gen_range(50..RAW_LEVELS);
+                (level_price(index), rng.gen_range(1..=100), amounts[index])
+            }
+            // removing a level near the touch makes every following level short of its threshold
+            "deletion_cascade" => {
+                let index = rng.gen_range(0..3);
+                (level_price(index), 0, amounts[index])
+            }
+            // a new level right before `max_depth` pushes the last raw level out of the depth
+            "depth_churn" => {
+                let index = rng.gen_range(MAX_DEPTH - 10..MAX_DEPTH);
+                (level_price(index) + 1, rng.gen_range(1..=100), 0)
+            }
+            _ => unreachable!(),
+        };
+        updates.push((price, amount));
+        updates.push((price, restored_amount));
+    }
+    updates
+}
+
+fn bench_solution<Solution: AgregatedL2Trait<AskKey>>(
+    criterion: &mut Criterion,
+    implementation: &str,
+) {
+    let amounts = initial_amounts();
+    for scenario in ["touch", "deep", "deletion_cascade", "depth_churn"] {
+        let updates = scenario_updates(scenario, &amounts);
+        let mut group = criterion.benchmark_group(format!("set_quote/{}", scenario));
+        for (rules_name, rules) in rule_tables() {
+            let mut solution = Solution::new(rules);
+            for (index, &amount) in amounts.iter().enumerate() {
+                solution.set_quote(level_price(index), amount);
+            }
+            let mut position = 0;
+            group.bench_function(BenchmarkId::new(implementation, rules_name), |bencher| {
+                bencher.iter(|| {
+                    let (price, amount) = updates[position];
+                    position = (position + 1) % updates.len();
+                    solution.set_quote(black_box(price), black_box(amount));
+                })
+            });
+        }
+        group.finish();
+    }
+}
+
+fn bench_set_quote(criterion: &mut Criterion) {
+    bench_solution::<AggregatedL2<AskKey>>(criterion, "fast");
+    bench_solution::<SlowAggregatedL2ForComparisons<AskKey>>(criterion, "slow");
+}
+
+/// Building the whole book from a snapshot, level by level against the bulk load
+fn bench_load(criterion: &mut Criterion) {
+    let levels: Vec<(u64, Amount)> =
+        initial_amounts().into_iter().enumerate().map(|(index, amount)| (level_price(index), amount)).collect();
+    for (rules_name, rules) in rule_tables() {
+        let mut group = criterion.benchmark_group(format!("load/{}", rules_name));
+        group.bench_function("set_quote", |bencher| {
+            bencher.iter(|| {
+                let mut solution = AggregatedL2::<AskKey>::new(rules.clone());
+                for &(price, amount) in black_box(&levels).iter() {
+                    solution.set_quote(price, amount);
+                }
+                solution
+            })
+        });
+        group.bench_function("from_sorted_levels", |bencher| {
+            bencher.iter(|| AggregatedL2::<AskKey>::from_sorted_levels(rules.clone(), black_box(&levels).iter().copied()))
+        });
+        group.finish();
+    }
+}
+
+/// With maximum amounts a change at the touch rebuilds the tail of the ladder, so its cost grows with the book
+fn bench_capped_tail(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("capped_tail/touch");
+    for raw_levels in [100, 1000, 10000] {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let rules = SubscriptionRules::new(vec![200], 500, raw_levels).with_maximum_schedule(ThresholdSchedule::Table {
+            amounts: vec![300],
+            fallback: 800,
+        });
+        let mut solution = AggregatedL2::<AskKey>::new(rules);
+        for index in 0..raw_levels {
+            solution.set_quote(level_price(index), rng.gen_range(1..=100));
+        }
+        let updates: Vec<(u64, Amount)> =
+            (0..UPDATES).map(|_| (level_price(rng.gen_range(0..5)), rng.gen_range(1..=100))).collect();
+        let mut position = 0;
+        group.bench_function(BenchmarkId::from_parameter(raw_levels), |bencher| {
+            bencher.iter(|| {
+                let (price, amount) = updates[position];
+                position = (position + 1) % updates.len();
+                solution.set_quote(black_box(price), black_box(amount));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_set_quote, bench_load, bench_capped_tail);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Builds the seed corpus of the fuzz targets from `l2.json`:
+//! `cargo +nightly run --example build_fuzz_corpus`
+use market_data_aggregator::feed::*;
+use market_data_aggregator::fuzzing::*;
+
+use std::collections::BTreeSet;
+use std::fs;
+
+const TARGETS: [&str; 2] = ["fast_vs_slow", "cumulative"];
+const CHUNK_SIZE: usize = 600;
+
+/// Different depths, schedule kinds and maximum amounts, see `FuzzInput::decode`
+const RULES_HEADERS: [[u8; RULES_HEADER_SIZE]; 4] = [
+    [29, 0b0000_0000, 3, 10, 2, 0, 0, 0],
+    [4, 0b0000_0100, 1, 32, 40, 0, 0, 0],
+    [63, 0b0010_1010, 5, 3, 0, 20, 1, 6],
+    [10, 0b0111_1110, 2, 7, 30, 1, 16, 0],
+];
+
+fn main() {
+    let trades = read_trades("l2.json");
+    for side in [Side::Ask, Side::Bid] {
+        let side_trades: Vec<&Trade> = trades.iter().filter(|trade| trade.side == side).collect();
+        // prices are replaced by their rank to fit into u16, amounts are rounded to 0.01
+        let prices: BTreeSet<u64> = side_trades.iter().map(|trade| trade.scaled_price()).collect();
+        let prices: Vec<u64> = prices.into_iter().collect();
+        let quotes: Vec<u8> = side_trades
+            .iter()
+            .flat_map(|trade| {
+                let rank = prices.binary_search(&trade.scaled_price()).unwrap() as u16;
+                let amount = trade.scaled_amount().div_ceil(1_000_000).min(u16::MAX as u64) as u16;
+                encode_quote(rank, amount)
+            })
+            .collect();
+
+        for (header_index, header) in RULES_HEADERS.iter().enumerate() {
+            let mut header = *header;
+            header[1] |= (side == Side::Bid) as u8;
+            for (chunk_index, chunk) in quotes.chunks(CHUNK_SIZE * QUOTE_SIZE).enumerate() {
+                let mut seed = header.to_vec();
+                seed.extend_from_slice(chunk);
+                for target in TARGETS {
+                    let directory = format!("fuzz/corpus/{}", target);
+                    fs::create_dir_all(&directory).expect("Cannot create corpus directory");
+                    let path = format!("{}/l2_{:?}_{}_{}", directory, side, header_index, chunk_index);
+                    fs::write(path, &seed).expect("Cannot write corpus file");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use market_data_aggregator::common::*;
+use market_data_aggregator::feed::Side;
+use market_data_aggregator::fuzzing::*;
+use market_data_aggregator::solutions::fast::*;
+use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+fn run<Price: OrderKey>(input: &FuzzInput)
+where
+    u64: From<Price>,
+{
+    let mut fast_solution = AggregatedL2::<Price>::new(input.rules.clone());
+    fast_solution.enable_cumulative_index();
+    let mut slow_solution = SlowAggregatedL2ForComparisons::<Price>::new(input.rules.clone());
+    for operation in input.operations.iter() {
+        let FuzzOperation::SetQuote { price, amount } = *operation else {
+            continue;
+        };
+        fast_solution.set_quote(price, amount);
+        slow_solution.set_quote(price, amount);
+        // the update itself gives query points both inside and outside of the book
+        assert_eq!(
+            fast_solution.cumulative_amount_up_to(price),
+            slow_solution.cumulative_amount_up_to(price)
+        );
+        assert_eq!(
+            fast_solution.price_for_cumulative_amount(amount * 8),
+            slow_solution.price_for_cumulative_amount(amount * 8)
+        );
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some(input) = FuzzInput::decode(data) else {
+        return;
+    };
+    match input.side {
+        Side::Ask => run::<AskKey>(&input),
+        Side::Bid => run::<BidKey>(&input),
+    }
+});
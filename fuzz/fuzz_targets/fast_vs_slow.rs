@@ -0,0 +1,44 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use market_data_aggregator::common::*;
+use market_data_aggregator::feed::Side;
+use market_data_aggregator::fuzzing::*;
+use market_data_aggregator::solutions::fast::*;
+use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+fn run<Price: OrderKey>(input: &FuzzInput)
+where
+    u64: From<Price>,
+{
+    let mut fast_solution = AggregatedL2::<Price>::new(input.rules.clone());
+    let mut slow_solution = SlowAggregatedL2ForComparisons::<Price>::new(input.rules.clone());
+    for operation in input.operations.iter() {
+        match operation {
+            FuzzOperation::SetQuote { price, amount } => {
+                fast_solution.set_quote(*price, *amount);
+                slow_solution.set_quote(*price, *amount);
+            }
+            FuzzOperation::SetRules(rules) => {
+                fast_solution.set_subscription_rules(rules.clone());
+                slow_solution.set_subscription_rules(rules.clone());
+            }
+        }
+        assert!(fast_solution.raw_levels().eq(slow_solution.raw_levels()));
+        assert_eq!(fast_solution.get_aggregated_levels(), slow_solution.get_aggregated_levels());
+        assert!(fast_solution.get_max_depth_price() == slow_solution.get_max_depth_price());
+        if let Err(report) = fast_solution.check_invariants() {
+            panic!("{}", report);
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some(input) = FuzzInput::decode(data) else {
+        return;
+    };
+    match input.side {
+        Side::Ask => run::<AskKey>(&input),
+        Side::Bid => run::<BidKey>(&input),
+    }
+});
@@ -0,0 +1,35 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::http::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+use std::sync::Arc;
+
+const USAGE: &str = "usage:
+    market_data_http <address> <rules.json> <instrument>=<feed>...   serve books of the feeds (- for stdin) over http";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 3 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    let rules_text = std::fs::read_to_string(&args[1]).expect("Cannot open rules file");
+    let rules: BookRules = serde_json::from_str(&rules_text).unwrap_or_else(|error| {
+        eprintln!("Invalid rules: {}", error);
+        std::process::exit(1);
+    });
+    let registry = Arc::new(BookRegistry::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new());
+    for arg in args[2..].iter() {
+        let Some((instrument, path)) = arg.split_once('=') else {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        };
+        registry.spawn_feed(instrument, rules.clone(), open_feed(path).expect("Cannot open file"));
+    }
+    let server = tiny_http::Server::http(&args[0]).unwrap_or_else(|error| {
+        eprintln!("Cannot listen: {}", error);
+        std::process::exit(1);
+    });
+    // keeps serving the last state after the feeds end
+    run_http_server(&server, &registry);
+}
@@ -0,0 +1,33 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::server::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+const USAGE: &str = "usage:
+    market_data_server <address> <rules.json> [feed]   serve aggregated books of the feed (stdin by default) over tcp";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 2 && args.len() != 3 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    let rules_text = std::fs::read_to_string(&args[1]).expect("Cannot open rules file");
+    let rules: BookRules = serde_json::from_str(&rules_text).unwrap_or_else(|error| {
+        eprintln!("Invalid rules: {}", error);
+        std::process::exit(1);
+    });
+    let listener = TcpListener::bind(&args[0]).expect("Cannot listen");
+    let result = run_tcp_server::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+        listener,
+        open_feed(args.get(2).map_or("-", String::as_str)).expect("Cannot open file"),
+        rules,
+        Duration::from_millis(10),
+    );
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
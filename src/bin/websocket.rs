@@ -0,0 +1,30 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::websocket::*;
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+const USAGE: &str = "usage:
+    market_data_websocket <address> <instrument>=<feed>...   serve books of the feeds (- for stdin) over websocket";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(2);
+    }
+    let mut feeds = Vec::new();
+    for arg in args[1..].iter() {
+        let Some((instrument, path)) = arg.split_once('=') else {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        };
+        feeds.push((instrument.to_string(), open_feed(path).expect("Cannot open file")));
+    }
+    let listener = TcpListener::bind(&args[0]).expect("Cannot listen");
+    let result = run_websocket_server(listener, feeds, Duration::from_millis(10));
+    if let Err(error) = result {
+        eprintln!("{}", error);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,101 @@
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+/// Bid and ask ladders of one instrument, each side with its own rules
+pub struct AggregatedBook<SolutionAsk, SolutionBid> {
+    ask: SolutionAsk,
+    bid: SolutionBid,
+}
+
+impl<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>>
+    AggregatedBook<SolutionAsk, SolutionBid>
+{
+    pub fn new(rules: BookRules) -> Self {
+        Self {
+            ask: SolutionAsk::new(rules.ask),
+            bid: SolutionBid::new(rules.bid),
+        }
+    }
+    pub fn set_quote(&mut self, side: Side, price: u64, amount: Amount) {
+        match side {
+            Side::Bid => self.bid.set_quote(price, amount),
+            Side::Ask => self.ask.set_quote(price, amount),
+        }
+    }
+    /// Returns `false` and changes nothing for values that can't be prices and amounts
+    pub fn apply_trade(&mut self, trade: &Trade) -> bool {
+        let Some((price, amount)) = trade.try_scaled() else {
+            return false;
+        };
+        self.set_quote(trade.side, price, amount);
+        true
+    }
+    /// Drops every raw level of `side` and aggregates `levels` in one pass instead of a `set_quote` per level.
+    /// Levels at price 0 are skipped, it is not a price and for bids it would collide with `BidKey::MAX`
+    pub fn replace_side(&mut self, side: Side, levels: impl IntoIterator<Item = (u64, Amount)>) {
+        let levels = levels.into_iter().filter(|&(price, _)| price > 0);
+        match side {
+            Side::Bid => self.bid.replace_levels(levels),
+            Side::Ask => self.ask.replace_levels(levels),
+        }
+    }
+    /// Full book as scaled `(price, amount)` levels of both sides, the rules are kept. Like in `replace_side`,
+    /// levels at price 0 are skipped
+    pub fn apply_snapshot(
+        &mut self,
+        bids: impl IntoIterator<Item = (u64, Amount)>,
+        asks: impl IntoIterator<Item = (u64, Amount)>,
+    ) {
+        self.replace_side(Side::Bid, bids);
+        self.replace_side(Side::Ask, asks);
+    }
+    /// `apply_snapshot` for a snapshot message of the feed. Returns `false` and changes nothing if any level
+    /// can't be scaled
+    pub fn apply_feed_snapshot(&mut self, snapshot: &FeedSnapshot) -> bool {
+        let Some((bids, asks)) = snapshot.try_scaled() else {
+            return false;
+        };
+        self.apply_snapshot(bids, asks);
+        true
+    }
+    /// Only sides whose rules differ are recalculated, returns the changed sides
+    pub fn set_rules(&mut self, rules: BookRules) -> Vec<Side> {
+        let mut changed_sides = Vec::new();
+        if *self.bid.get_subscription_rules() != rules.bid {
+            self.bid.set_subscription_rules(rules.bid);
+            changed_sides.push(Side::Bid);
+        }
+        if *self.ask.get_subscription_rules() != rules.ask {
+            self.ask.set_subscription_rules(rules.ask);
+            changed_sides.push(Side::Ask);
+        }
+        changed_sides
+    }
+    pub fn get_rules(&self) -> BookRules {
+        BookRules::new(
+            self.bid.get_subscription_rules().clone(),
+            self.ask.get_subscription_rules().clone(),
+        )
+    }
+    pub fn ask(&self) -> &SolutionAsk {
+        &self.ask
+    }
+    pub fn bid(&self) -> &SolutionBid {
+        &self.bid
+    }
+    pub fn get_aggregated_levels_tuples(&self, side: Side) -> Vec<(u64, u64)> {
+        match side {
+            Side::Bid => self.bid.get_aggregated_levels_tuples(),
+            Side::Ask => self.ask.get_aggregated_levels_tuples(),
+        }
+    }
+    /// Both sides are present and the best bid is not below the best ask
+    pub fn is_crossed(&self) -> bool {
+        match (self.ask.best_price(), self.bid.best_price()) {
+            (Some(ask), Some(bid)) => ask <= bid,
+            _ => false,
+        }
+    }
+}
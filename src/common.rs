@@ -1,6 +1,9 @@
+use crate::decimal::Scale;
+
 use std::cmp::Ordering;
+use std::fmt;
 
-pub trait OrderKey: Ord + Eq + Copy + std::fmt::Debug + From<u64> {
+pub trait OrderKey: Ord + Eq + Copy + std::fmt::Debug + From<u64> + Into<u64> + 'static {
     const MAX: Self;
 }
 
@@ -27,6 +30,12 @@ impl Ord for BidKey {
         other.0.cmp(&self.0)
     }
 }
+/// Price in the decimal units of the feed, `Debug` keeps the scaled integer
+impl fmt::Display for BidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&Scale::FEED.display(self.0), f)
+    }
+}
 impl PartialOrd for BidKey {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -55,6 +64,12 @@ impl From<u64> for AskKey {
     }
 }
 
+impl fmt::Display for AskKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&Scale::FEED.display(self.0), f)
+    }
+}
+
 impl PartialOrd for AskKey {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
@@ -68,3 +83,11 @@ pub struct AggregatedLevel<Price: OrderKey> {
     pub last_price: Price,
     pub total_amount: Amount,
 }
+
+/// `amount @ last_price` in the decimal units of the feed
+impl<Price: OrderKey> fmt::Display for AggregatedLevel<Price> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let price: u64 = self.last_price.into();
+        write!(f, "{} @ {}", Scale::FEED.display(self.total_amount), Scale::FEED.display(price))
+    }
+}
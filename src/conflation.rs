@@ -0,0 +1,261 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::server::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Time since some fixed start, the publisher never looks at the wall clock directly
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Moves only when told to, clones share the same time
+#[derive(Clone, Default)]
+pub struct SimulatedClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl SimulatedClock {
+    pub fn advance(&self, duration: Duration) {
+        self.nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Clock for SimulatedClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SubscriberId(u64);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConflationStats {
+    /// Transactions that changed the ladders after the snapshot of the subscriber
+    pub transactions: u64,
+    /// Deliveries with at least one update, each covers one or more transactions
+    pub deliveries: u64,
+    /// Transactions merged into a later one and never sent on their own
+    pub conflated: u64,
+}
+
+/// Ladders the subscriber has already seen, pending changes are whatever differs from the latest ladders
+struct Subscriber {
+    min_interval: Duration,
+    delivered_bids: Vec<(u64, Amount)>,
+    delivered_asks: Vec<(u64, Amount)>,
+    seq: u64,
+    is_snapshot_sent: bool,
+    last_delivery: Option<Duration>,
+    pending_transactions: u64,
+    stats: ConflationStats,
+}
+
+impl Subscriber {
+    fn next_delivery(&self) -> Duration {
+        match self.last_delivery {
+            Some(last_delivery) => last_delivery + self.min_interval,
+            None => Duration::ZERO,
+        }
+    }
+}
+
+/// Publishes the latest aggregated ladders to subscribers with different maximum rates.
+/// A subscriber gets at most one batch of updates per its `min_interval`, transactions in between are merged,
+/// and every batch brings its ladders to the state after the last finished transaction
+pub struct ConflatingPublisher<SolutionAsk, SolutionBid, SomeClock> {
+    book: AggregatedBook<SolutionAsk, SolutionBid>,
+    clock: SomeClock,
+    /// Ladders as of the last end of transaction, never half of one
+    bids: Vec<(u64, Amount)>,
+    asks: Vec<(u64, Amount)>,
+    subscribers: BTreeMap<SubscriberId, Subscriber>,
+    next_subscriber: u64,
+}
+
+impl<SolutionAsk, SolutionBid, SomeClock> ConflatingPublisher<SolutionAsk, SolutionBid, SomeClock>
+where
+    SolutionAsk: AgregatedL2Trait<AskKey>,
+    SolutionBid: AgregatedL2Trait<BidKey>,
+    SomeClock: Clock,
+{
+    pub fn new(rules: BookRules, clock: SomeClock) -> Self {
+        Self {
+            book: AggregatedBook::new(rules),
+            clock,
+            bids: Vec::new(),
+            asks: Vec::new(),
+            subscribers: BTreeMap::new(),
+            next_subscriber: 0,
+        }
+    }
+
+    pub fn book(&self) -> &AggregatedBook<SolutionAsk, SolutionBid> {
+        &self.book
+    }
+
+    /// Changes become visible to subscribers only at the end of a transaction, a skipped line still ends it
+    pub fn apply_trade(&mut self, trade: &Trade) -> bool {
+        let is_applied = self.book.apply_trade(trade);
+        if trade.is_eot {
+            self.end_transaction();
+        }
+        is_applied
+    }
+
+    pub fn set_quote(&mut self, side: Side, price: u64, amount: Amount) {
+        self.book.set_quote(side, price, amount);
+    }
+
+    /// For feeds without `is_eot`, or a feed that ended in the middle of a transaction
+    pub fn end_transaction(&mut self) {
+        let bids = self.book.get_aggregated_levels_tuples(Side::Bid);
+        let asks = self.book.get_aggregated_levels_tuples(Side::Ask);
+        if bids == self.bids && asks == self.asks {
+            return;
+        }
+        self.bids = bids;
+        self.asks = asks;
+        // the snapshot of a new subscriber covers everything before it
+        for subscriber in self.subscribers.values_mut().filter(|subscriber| subscriber.is_snapshot_sent) {
+            subscriber.pending_transactions += 1;
+            subscriber.stats.transactions += 1;
+        }
+    }
+
+    /// The first `poll` of the subscriber returns a snapshot regardless of the rate
+    pub fn subscribe(&mut self, min_interval: Duration) -> SubscriberId {
+        let id = SubscriberId(self.next_subscriber);
+        self.next_subscriber += 1;
+        let subscriber = Subscriber {
+            min_interval,
+            delivered_bids: Vec::new(),
+            delivered_asks: Vec::new(),
+            seq: 0,
+            is_snapshot_sent: false,
+            last_delivery: None,
+            pending_transactions: 0,
+            stats: ConflationStats::default(),
+        };
+        self.subscribers.insert(id, subscriber);
+        id
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+        self.subscribers.remove(&id).is_some()
+    }
+
+    /// When the subscriber may get its pending changes, `None` if there are none
+    pub fn next_delivery(&self, id: SubscriberId) -> Option<Duration> {
+        let subscriber = self.subscribers.get(&id)?;
+        if subscriber.is_snapshot_sent && subscriber.pending_transactions == 0 {
+            return None;
+        }
+        Some(subscriber.next_delivery())
+    }
+
+    /// Messages the subscriber may get now, empty while it is rate limited or up to date
+    pub fn poll(&mut self, id: SubscriberId) -> Vec<ServerMessage> {
+        let now = self.clock.now();
+        let Some(subscriber) = self.subscribers.get_mut(&id) else {
+            return Vec::new();
+        };
+        if !subscriber.is_snapshot_sent {
+            subscriber.is_snapshot_sent = true;
+            subscriber.last_delivery = Some(now);
+            subscriber.delivered_bids = self.bids.clone();
+            subscriber.delivered_asks = self.asks.clone();
+            return vec![ServerMessage::Snapshot {
+                seq: subscriber.seq,
+                bids: self.bids.clone(),
+                asks: self.asks.clone(),
+            }];
+        }
+        if subscriber.pending_transactions == 0 || now < subscriber.next_delivery() {
+            return Vec::new();
+        }
+        let updates = deliver_pending(subscriber, &self.bids, &self.asks);
+        if !updates.is_empty() {
+            subscriber.last_delivery = Some(now);
+        }
+        updates
+    }
+
+    /// Everything pending regardless of the rate, then `EndOfFeed`
+    pub fn end_of_feed(&mut self, id: SubscriberId) -> Vec<ServerMessage> {
+        let Some(subscriber) = self.subscribers.get_mut(&id) else {
+            return Vec::new();
+        };
+        let mut messages = Vec::new();
+        if !subscriber.is_snapshot_sent {
+            subscriber.is_snapshot_sent = true;
+            messages.push(ServerMessage::Snapshot {
+                seq: subscriber.seq,
+                bids: self.bids.clone(),
+                asks: self.asks.clone(),
+            });
+        } else if subscriber.pending_transactions > 0 {
+            messages.extend(deliver_pending(subscriber, &self.bids, &self.asks));
+        }
+        subscriber.seq += 1;
+        messages.push(ServerMessage::EndOfFeed { seq: subscriber.seq });
+        messages
+    }
+
+    pub fn stats(&self, id: SubscriberId) -> Option<&ConflationStats> {
+        self.subscribers.get(&id).map(|subscriber| &subscriber.stats)
+    }
+}
+
+/// Brings the delivered ladders of the subscriber to the latest ones, one update per changed side.
+/// Empty if the pending transactions cancelled each other out
+fn deliver_pending(subscriber: &mut Subscriber, bids: &[(u64, Amount)], asks: &[(u64, Amount)]) -> Vec<ServerMessage> {
+    let mut updates = Vec::new();
+    for (side, latest) in [(Side::Bid, bids), (Side::Ask, asks)] {
+        let delivered = match side {
+            Side::Bid => &mut subscriber.delivered_bids,
+            Side::Ask => &mut subscriber.delivered_asks,
+        };
+        if let Some((from_index, levels)) = ladder_update(delivered, latest) {
+            subscriber.seq += 1;
+            updates.push(ServerMessage::Update {
+                seq: subscriber.seq,
+                side,
+                from_index,
+                levels,
+            });
+            *delivered = latest.to_vec();
+        }
+    }
+    if updates.is_empty() {
+        subscriber.stats.conflated += subscriber.pending_transactions;
+    } else {
+        subscriber.stats.deliveries += 1;
+        subscriber.stats.conflated += subscriber.pending_transactions - 1;
+    }
+    subscriber.pending_transactions = 0;
+    updates
+}
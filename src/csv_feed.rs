@@ -0,0 +1,418 @@
+use crate::feed::*;
+
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::iter::Peekable;
+
+/// Column by its position from 0, or by its name in the header
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeUnit {
+    Seconds,
+    Millis,
+    Micros,
+    #[default]
+    Nanos,
+}
+
+impl TimeUnit {
+    fn nanos(self) -> u64 {
+        match self {
+            Self::Seconds => 1_000_000_000,
+            Self::Millis => 1_000_000,
+            Self::Micros => 1_000,
+            Self::Nanos => 1,
+        }
+    }
+}
+
+/// Where the fields of `Trade` are, optional ones may be missing from the file
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnMapping {
+    pub platform_time: Column,
+    /// `platform_time` is used when missing
+    #[serde(default)]
+    pub exchange_time: Option<Column>,
+    #[serde(default)]
+    pub seq_no: Option<Column>,
+    pub side: Column,
+    pub price: Column,
+    pub amount: Column,
+    /// When missing, a transaction is every run of rows with the same `platform_time` and snapshot flag
+    #[serde(default)]
+    pub is_eot: Option<Column>,
+    #[serde(default)]
+    pub is_snapshot: Option<Column>,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_bid_values() -> Vec<String> {
+    vec!["bid".to_string(), "buy".to_string(), "b".to_string()]
+}
+
+fn default_ask_values() -> Vec<String> {
+    vec!["ask".to_string(), "sell".to_string(), "a".to_string(), "s".to_string()]
+}
+
+/// Layout of one vendor's csv files, can be read from json
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CsvLayout {
+    pub columns: ColumnMapping,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default = "default_true")]
+    pub has_header: bool,
+    /// Unit of the time columns, `Trade` keeps nanoseconds
+    #[serde(default)]
+    pub time_unit: TimeUnit,
+    /// Side values are compared ignoring case
+    #[serde(default = "default_bid_values")]
+    pub bid_values: Vec<String>,
+    #[serde(default = "default_ask_values")]
+    pub ask_values: Vec<String>,
+}
+
+impl CsvLayout {
+    /// Columns of the json feed in the same order, without a header
+    pub fn positional() -> Self {
+        Self {
+            columns: ColumnMapping {
+                platform_time: Column::Index(0),
+                exchange_time: Some(Column::Index(1)),
+                seq_no: Some(Column::Index(2)),
+                side: Column::Index(3),
+                price: Column::Index(4),
+                amount: Column::Index(5),
+                is_eot: Some(Column::Index(6)),
+                is_snapshot: None,
+            },
+            delimiter: default_delimiter(),
+            has_header: false,
+            time_unit: TimeUnit::Nanos,
+            bid_values: default_bid_values(),
+            ask_values: default_ask_values(),
+        }
+    }
+
+    /// Tardis `incremental_book_L2`: `exchange,symbol,timestamp,local_timestamp,is_snapshot,side,price,amount`
+    pub fn tardis() -> Self {
+        let name = |name: &str| Column::Name(name.to_string());
+        Self {
+            columns: ColumnMapping {
+                platform_time: name("local_timestamp"),
+                exchange_time: Some(name("timestamp")),
+                seq_no: None,
+                side: name("side"),
+                price: name("price"),
+                amount: name("amount"),
+                is_eot: None,
+                is_snapshot: Some(name("is_snapshot")),
+            },
+            delimiter: default_delimiter(),
+            has_header: true,
+            time_unit: TimeUnit::Micros,
+            bid_values: default_bid_values(),
+            ask_values: default_ask_values(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvError {
+    Csv(csv::Error),
+    InvalidDelimiter { delimiter: char },
+    /// Named columns need a header
+    NoHeader,
+    MissingColumn { name: String },
+    /// Lines are counted from 1, the header included
+    InvalidValue { line: u64, field: &'static str, value: String },
+}
+
+impl fmt::Display for CsvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Csv(error) => write!(f, "{}", error),
+            Self::InvalidDelimiter { delimiter } => write!(f, "delimiter {:?} is not a single byte", delimiter),
+            Self::NoHeader => write!(f, "columns are named, but the layout has no header"),
+            Self::MissingColumn { name } => write!(f, "no column {:?} in the header", name),
+            Self::InvalidValue { line, field, value } => {
+                write!(f, "line {}: invalid {} {:?}", line, field, value)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+impl From<csv::Error> for CsvError {
+    fn from(error: csv::Error) -> Self {
+        Self::Csv(error)
+    }
+}
+
+/// One csv row as a feed message, `is_snapshot` rows are levels of a full book rather than changes
+#[derive(Debug, Clone)]
+pub struct CsvRow {
+    pub trade: Trade,
+    pub is_snapshot: bool,
+}
+
+/// `ColumnMapping` with names replaced by positions
+struct ColumnIndices {
+    platform_time: usize,
+    exchange_time: Option<usize>,
+    seq_no: Option<usize>,
+    side: usize,
+    price: usize,
+    amount: usize,
+    is_eot: Option<usize>,
+    is_snapshot: Option<usize>,
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads rows of any `CsvLayout` as the same `Trade`s the json parser gives
+pub struct CsvFeedReader<R> {
+    reader: csv::Reader<R>,
+    columns: ColumnIndices,
+    time_unit: TimeUnit,
+    bid_values: Vec<String>,
+    ask_values: Vec<String>,
+    /// Reused for every row
+    record: csv::StringRecord,
+    /// Next row, read ahead when transactions are found by timestamps
+    lookahead: Option<Result<CsvRow, CsvError>>,
+}
+
+impl<R: Read> CsvFeedReader<R> {
+    pub fn new(input: R, layout: CsvLayout) -> Result<Self, CsvError> {
+        if !layout.delimiter.is_ascii() {
+            return Err(CsvError::InvalidDelimiter {
+                delimiter: layout.delimiter,
+            });
+        }
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(layout.delimiter as u8)
+            .has_headers(layout.has_header)
+            .flexible(true)
+            .from_reader(input);
+        let header = match layout.has_header {
+            true => Some(reader.headers()?.clone()),
+            false => None,
+        };
+        let resolve = |column: &Column| match column {
+            Column::Index(index) => Ok(*index),
+            Column::Name(name) => {
+                let header = header.as_ref().ok_or(CsvError::NoHeader)?;
+                header
+                    .iter()
+                    .position(|other| other.trim() == name)
+                    .ok_or_else(|| CsvError::MissingColumn { name: name.clone() })
+            }
+        };
+        let mapping = &layout.columns;
+        let columns = ColumnIndices {
+            platform_time: resolve(&mapping.platform_time)?,
+            exchange_time: mapping.exchange_time.as_ref().map(resolve).transpose()?,
+            seq_no: mapping.seq_no.as_ref().map(resolve).transpose()?,
+            side: resolve(&mapping.side)?,
+            price: resolve(&mapping.price)?,
+            amount: resolve(&mapping.amount)?,
+            is_eot: mapping.is_eot.as_ref().map(resolve).transpose()?,
+            is_snapshot: mapping.is_snapshot.as_ref().map(resolve).transpose()?,
+        };
+        Ok(Self {
+            reader,
+            columns,
+            time_unit: layout.time_unit,
+            bid_values: layout.bid_values,
+            ask_values: layout.ask_values,
+            record: csv::StringRecord::new(),
+            lookahead: None,
+        })
+    }
+
+    fn parse_row(&self) -> Result<CsvRow, CsvError> {
+        let line = self.record.position().map_or(0, |position| position.line());
+        let field = |index: usize, field: &'static str| {
+            self.record.get(index).map(str::trim).ok_or_else(|| CsvError::InvalidValue {
+                line,
+                field,
+                value: String::new(),
+            })
+        };
+        let invalid = |field: &'static str, value: &str| CsvError::InvalidValue {
+            line,
+            field,
+            value: value.to_string(),
+        };
+        let time = |index: usize, name: &'static str| {
+            let value = field(index, name)?;
+            value
+                .parse::<u64>()
+                .ok()
+                .and_then(|time| time.checked_mul(self.time_unit.nanos()))
+                .ok_or_else(|| invalid(name, value))
+        };
+        let number = |index: usize, name: &'static str| {
+            let value = field(index, name)?;
+            value.parse::<f64>().map_err(|_| invalid(name, value))
+        };
+        let flag = |index: Option<usize>, name: &'static str| match index {
+            Some(index) => {
+                let value = field(index, name)?;
+                parse_bool(value).ok_or_else(|| invalid(name, value))
+            }
+            None => Ok(false),
+        };
+
+        let platform_time = time(self.columns.platform_time, "platform_time")?;
+        let exchange_time = match self.columns.exchange_time {
+            Some(index) => time(index, "exchange_time")?,
+            None => platform_time,
+        };
+        let seq_no = match self.columns.seq_no {
+            Some(index) => {
+                let value = field(index, "seq_no")?;
+                match value {
+                    "" | "null" => None,
+                    value => Some(value.parse().map_err(|_| invalid("seq_no", value))?),
+                }
+            }
+            None => None,
+        };
+        let side_value = field(self.columns.side, "side")?;
+        let is_side = |values: &[String]| values.iter().any(|value| value.eq_ignore_ascii_case(side_value));
+        let side = if is_side(&self.bid_values) {
+            Side::Bid
+        } else if is_side(&self.ask_values) {
+            Side::Ask
+        } else {
+            return Err(invalid("side", side_value));
+        };
+        let trade = Trade {
+            platform_time,
+            exchange_time,
+            seq_no,
+            side,
+            price: number(self.columns.price, "price")?,
+            amount: number(self.columns.amount, "amount")?,
+            is_eot: flag(self.columns.is_eot, "is_eot")?,
+        };
+        Ok(CsvRow {
+            trade,
+            is_snapshot: flag(self.columns.is_snapshot, "is_snapshot")?,
+        })
+    }
+
+    fn read_row(&mut self) -> Option<Result<CsvRow, CsvError>> {
+        match self.reader.read_record(&mut self.record) {
+            Ok(true) => Some(self.parse_row()),
+            Ok(false) => None,
+            Err(error) => Some(Err(error.into())),
+        }
+    }
+}
+
+impl<R: Read> Iterator for CsvFeedReader<R> {
+    type Item = Result<CsvRow, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.columns.is_eot.is_some() {
+            return self.read_row();
+        }
+        let mut row = match self.lookahead.take().or_else(|| self.read_row())? {
+            Ok(row) => row,
+            Err(error) => return Some(Err(error)),
+        };
+        self.lookahead = self.read_row();
+        row.trade.is_eot = match &self.lookahead {
+            Some(Ok(next)) => {
+                next.trade.platform_time != row.trade.platform_time || next.is_snapshot != row.is_snapshot
+            }
+            // the error is reported on the next call, the transaction can't go on past it anyway
+            Some(Err(_)) | None => true,
+        };
+        Some(Ok(row))
+    }
+}
+
+impl<R: Read> CsvFeedReader<R> {
+    /// Same rows as `FeedMessage`s for `FeedIngest::apply_message`
+    pub fn messages(self) -> CsvFeedMessages<R> {
+        CsvFeedMessages { rows: self.peekable() }
+    }
+}
+
+/// Deltas row by row, a run of `is_snapshot` rows up to the end of its transaction is one `FeedSnapshot`
+pub struct CsvFeedMessages<R: Read> {
+    rows: Peekable<CsvFeedReader<R>>,
+}
+
+impl<R: Read> Iterator for CsvFeedMessages<R> {
+    type Item = Result<FeedMessage, CsvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut row = match self.rows.next()? {
+            Ok(row) => row,
+            Err(error) => return Some(Err(error)),
+        };
+        if !row.is_snapshot {
+            return Some(Ok(FeedMessage::Delta(row.trade)));
+        }
+        let mut snapshot = FeedSnapshot {
+            platform_time: row.trade.platform_time,
+            exchange_time: row.trade.exchange_time,
+            seq_no: None,
+            bids: Vec::new(),
+            asks: Vec::new(),
+        };
+        loop {
+            let levels = match row.trade.side {
+                Side::Bid => &mut snapshot.bids,
+                Side::Ask => &mut snapshot.asks,
+            };
+            levels.push((row.trade.price, row.trade.amount));
+            snapshot.seq_no = row.trade.seq_no;
+            if row.trade.is_eot {
+                break;
+            }
+            // an error after the run is reported on the next call
+            match self.rows.next_if(|next| matches!(next, Ok(next) if next.is_snapshot)) {
+                Some(Ok(next)) => row = next,
+                _ => break,
+            }
+        }
+        Some(Ok(FeedMessage::Snapshot(snapshot)))
+    }
+}
+
+/// Whole file at once, like `read_trades` for json lines
+pub fn read_csv_rows(path: &str, layout: CsvLayout) -> Result<Vec<CsvRow>, CsvError> {
+    let file = File::open(path).map_err(|error| CsvError::Csv(error.into()))?;
+    CsvFeedReader::new(file, layout)?.collect()
+}
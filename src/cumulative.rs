@@ -0,0 +1,201 @@
+use crate::common::*;
+
+const NIL: usize = usize::MAX;
+
+struct Node<Price> {
+    price: Price,
+    amount: Amount,
+    /// Sum of amounts in the subtree
+    total_amount: Amount,
+    priority: u64,
+    left: usize,
+    right: usize,
+}
+
+/// Raw levels in a treap with subtree sums, so that prefix sums by price and
+/// search by cumulative amount take O(log n)
+pub struct CumulativeTree<Price: OrderKey> {
+    nodes: Vec<Node<Price>>,
+    free_nodes: Vec<usize>,
+    root: usize,
+    random_state: u64,
+}
+
+impl<Price: OrderKey> Default for CumulativeTree<Price> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Price: OrderKey> CumulativeTree<Price> {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            free_nodes: Vec::new(),
+            root: NIL,
+            random_state: 0x9E3779B97F4A7C15,
+        }
+    }
+    fn next_priority(&mut self) -> u64 {
+        // xorshift, priorities only have to be independent from the keys
+        self.random_state ^= self.random_state << 13;
+        self.random_state ^= self.random_state >> 7;
+        self.random_state ^= self.random_state << 17;
+        self.random_state
+    }
+    fn total_of(&self, node: usize) -> Amount {
+        if node == NIL {
+            return 0;
+        }
+        self.nodes[node].total_amount
+    }
+    fn recalculate(&mut self, node: usize) {
+        let total_amount = self.total_of(self.nodes[node].left)
+            + self.nodes[node].amount
+            + self.total_of(self.nodes[node].right);
+        self.nodes[node].total_amount = total_amount;
+    }
+    /// Splits into prices `< price` (or `<= price` if `is_inclusive`) and the rest
+    fn split(&mut self, node: usize, price: Price, is_inclusive: bool) -> (usize, usize) {
+        if node == NIL {
+            return (NIL, NIL);
+        }
+        let goes_left = if is_inclusive {
+            self.nodes[node].price <= price
+        } else {
+            self.nodes[node].price < price
+        };
+        if goes_left {
+            let (left, right) = self.split(self.nodes[node].right, price, is_inclusive);
+            self.nodes[node].right = left;
+            self.recalculate(node);
+            return (node, right);
+        }
+        let (left, right) = self.split(self.nodes[node].left, price, is_inclusive);
+        self.nodes[node].left = right;
+        self.recalculate(node);
+        (left, node)
+    }
+    fn merge(&mut self, left: usize, right: usize) -> usize {
+        if left == NIL {
+            return right;
+        }
+        if right == NIL {
+            return left;
+        }
+        if self.nodes[left].priority > self.nodes[right].priority {
+            self.nodes[left].right = self.merge(self.nodes[left].right, right);
+            self.recalculate(left);
+            return left;
+        }
+        self.nodes[right].left = self.merge(left, self.nodes[right].left);
+        self.recalculate(right);
+        right
+    }
+    fn allocate(&mut self, price: Price, amount: Amount) -> usize {
+        let node = Node {
+            price,
+            amount,
+            total_amount: amount,
+            priority: self.next_priority(),
+            left: NIL,
+            right: NIL,
+        };
+        match self.free_nodes.pop() {
+            Some(index) => {
+                self.nodes[index] = node;
+                index
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        }
+    }
+    /// Same semantics as `set_quote`: zero amount removes the price
+    pub fn set(&mut self, price: Price, amount: Amount) {
+        // fast path without restructuring: the price exists and stays
+        let mut node = self.root;
+        while node != NIL && self.nodes[node].price != price {
+            node = if price < self.nodes[node].price {
+                self.nodes[node].left
+            } else {
+                self.nodes[node].right
+            };
+        }
+        if node != NIL && amount != 0 {
+            let old_amount = self.nodes[node].amount;
+            self.nodes[node].amount = amount;
+            let mut current = self.root;
+            loop {
+                self.nodes[current].total_amount =
+                    self.nodes[current].total_amount - old_amount + amount;
+                if current == node {
+                    return;
+                }
+                current = if price < self.nodes[current].price {
+                    self.nodes[current].left
+                } else {
+                    self.nodes[current].right
+                };
+            }
+        }
+        if node == NIL && amount == 0 {
+            return;
+        }
+
+        let (left, rest) = self.split(self.root, price, /*is_inclusive=*/ false);
+        let (middle, right) = self.split(rest, price, /*is_inclusive=*/ true);
+        let middle = if middle == NIL {
+            self.allocate(price, amount)
+        } else {
+            self.free_nodes.push(middle);
+            NIL
+        };
+        let left = self.merge(left, middle);
+        self.root = self.merge(left, right);
+    }
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.free_nodes.clear();
+        self.root = NIL;
+    }
+    pub fn total_amount(&self) -> Amount {
+        self.total_of(self.root)
+    }
+    /// Sum of amounts of all prices up to `price` inclusive, in the order of the book
+    pub fn cumulative_amount_up_to(&self, price: Price) -> Amount {
+        let mut result = 0;
+        let mut node = self.root;
+        while node != NIL {
+            if self.nodes[node].price <= price {
+                result += self.total_of(self.nodes[node].left) + self.nodes[node].amount;
+                node = self.nodes[node].right;
+            } else {
+                node = self.nodes[node].left;
+            }
+        }
+        result
+    }
+    /// First price at which the cumulative amount reaches `amount`
+    pub fn price_for_cumulative_amount(&self, amount: Amount) -> Option<Price> {
+        if self.root == NIL || self.total_amount() < amount {
+            return None;
+        }
+        let mut rest = amount;
+        let mut node = self.root;
+        loop {
+            let left_total = self.total_of(self.nodes[node].left);
+            if self.nodes[node].left != NIL && rest <= left_total {
+                node = self.nodes[node].left;
+                continue;
+            }
+            let through_node = left_total + self.nodes[node].amount;
+            if rest <= through_node {
+                return Some(self.nodes[node].price);
+            }
+            rest -= through_node;
+            node = self.nodes[node].right;
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecimalError {
+    Empty,
+    /// Anything but digits and a single dot with digits on both sides
+    InvalidCharacter { position: usize },
+    TooManyDecimals { decimals: usize, max: u32 },
+    Overflow,
+}
+
+impl fmt::Display for DecimalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty decimal"),
+            Self::InvalidCharacter { position } => write!(f, "invalid character at {}", position),
+            Self::TooManyDecimals { decimals, max } => {
+                write!(f, "{} digits after the dot, at most {} are allowed", decimals, max)
+            }
+            Self::Overflow => write!(f, "decimal doesn't fit into u64 after scaling"),
+        }
+    }
+}
+
+impl std::error::Error for DecimalError {}
+
+/// How many decimal digits of an instrument are kept in the scaled integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+    decimals: u32,
+}
+
+impl Scale {
+    /// Prices and amounts of the feed, `SCALE_RATIO` is `FEED.ratio()`
+    pub const FEED: Scale = Scale::new(8);
+
+    pub const fn new(decimals: u32) -> Self {
+        assert!(decimals < 20, "10^decimals must fit into u64");
+        Self { decimals }
+    }
+    pub fn decimals(&self) -> u32 {
+        self.decimals
+    }
+    pub fn ratio(&self) -> u64 {
+        10u64.pow(self.decimals)
+    }
+
+    /// Shortest exact decimal, `150000000` is `"1.5"` and `0` is `"0"` with the feed scale
+    pub fn format(&self, value: u64) -> String {
+        self.display(value).to_string()
+    }
+
+    /// For `format!` and friends without an intermediate string, width and alignment are respected
+    pub fn display(&self, value: u64) -> ScaledValue {
+        ScaledValue { value, scale: *self }
+    }
+
+    /// Levels as `(price, amount)` strings, for `get_aggregated_levels_tuples` and the raw level iterators
+    pub fn format_levels(&self, levels: impl IntoIterator<Item = (u64, u64)>) -> Vec<(String, String)> {
+        levels
+            .into_iter()
+            .map(|(price, amount)| (self.format(price), self.format(amount)))
+            .collect()
+    }
+
+    /// Exact inverse of `format`, never goes through floating point
+    pub fn parse(&self, text: &str) -> Result<u64, DecimalError> {
+        if text.is_empty() {
+            return Err(DecimalError::Empty);
+        }
+        let (integer, fraction) = match text.split_once('.') {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (text, None),
+        };
+        let check_digits = |part: &str, offset: usize| match part.bytes().position(|byte| !byte.is_ascii_digit()) {
+            Some(position) => Err(DecimalError::InvalidCharacter { position: offset + position }),
+            None if part.is_empty() => Err(DecimalError::InvalidCharacter { position: offset }),
+            None => Ok(()),
+        };
+        check_digits(integer, 0)?;
+        let mut value = parse_digits(integer)?.checked_mul(self.ratio()).ok_or(DecimalError::Overflow)?;
+        if let Some(fraction) = fraction {
+            check_digits(fraction, integer.len() + 1)?;
+            if fraction.len() > self.decimals as usize {
+                return Err(DecimalError::TooManyDecimals {
+                    decimals: fraction.len(),
+                    max: self.decimals,
+                });
+            }
+            let fraction_value = parse_digits(fraction)? * 10u64.pow(self.decimals - fraction.len() as u32);
+            value = value.checked_add(fraction_value).ok_or(DecimalError::Overflow)?;
+        }
+        Ok(value)
+    }
+}
+
+fn parse_digits(digits: &str) -> Result<u64, DecimalError> {
+    digits.parse().map_err(|_| DecimalError::Overflow)
+}
+
+/// Scaled integer shown as a decimal, see `Scale::display`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScaledValue {
+    pub value: u64,
+    pub scale: Scale,
+}
+
+impl fmt::Display for ScaledValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ratio = self.scale.ratio();
+        let (integer, fraction) = (self.value / ratio, self.value % ratio);
+        if fraction == 0 {
+            return f.pad_integral(true, "", &integer.to_string());
+        }
+        let digits = format!("{:0width$}", fraction, width = self.scale.decimals as usize);
+        let text = format!("{}.{}", integer, digits.trim_end_matches('0'));
+        f.pad_integral(true, "", &text)
+    }
+}
+
+/// Prices and amounts of the feed, shortcut for `Scale::FEED.format`
+pub fn format_scaled(value: u64) -> String {
+    Scale::FEED.format(value)
+}
+
+/// Shortcut for `Scale::FEED.parse`
+pub fn parse_scaled(text: &str) -> Result<u64, DecimalError> {
+    Scale::FEED.parse(text)
+}
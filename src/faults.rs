@@ -0,0 +1,169 @@
+use crate::feed::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Probability of every fault for each source line, checked in this order and at most one per line
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultConfig {
+    pub seed: u64,
+    pub drop_probability: f64,
+    pub duplicate_probability: f64,
+    /// The line is delivered after the next one
+    pub reorder_probability: f64,
+    /// The line is cut short, so it doesn't parse
+    pub corrupt_probability: f64,
+    /// `seq_no` of the line jumps ahead, the content stays correct
+    pub seq_gap_probability: f64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FaultConfigError {
+    /// Not within `[0, 1]`
+    InvalidProbability { field: &'static str },
+}
+
+impl fmt::Display for FaultConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidProbability { field } => write!(f, "{} must be within [0, 1]", field),
+        }
+    }
+}
+
+impl std::error::Error for FaultConfigError {}
+
+impl FaultConfig {
+    pub fn validate(&self) -> Result<(), FaultConfigError> {
+        let probabilities = [
+            (self.drop_probability, "drop_probability"),
+            (self.duplicate_probability, "duplicate_probability"),
+            (self.reorder_probability, "reorder_probability"),
+            (self.corrupt_probability, "corrupt_probability"),
+            (self.seq_gap_probability, "seq_gap_probability"),
+        ];
+        for (value, field) in probabilities {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(FaultConfigError::InvalidProbability { field });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InjectedFaults {
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub reordered: u64,
+    pub corrupted: u64,
+    pub seq_gaps: u64,
+}
+
+impl InjectedFaults {
+    pub fn total(&self) -> u64 {
+        self.dropped + self.duplicated + self.reordered + self.corrupted + self.seq_gaps
+    }
+}
+
+/// Wraps a stream of feed lines and breaks some of them
+pub struct FaultInjector<Lines> {
+    lines: Lines,
+    config: FaultConfig,
+    rng: ChaCha8Rng,
+    ready: VecDeque<String>,
+    /// Line that waits for the next one to be delivered first
+    delayed: Option<String>,
+    source_position: u64,
+    injected: InjectedFaults,
+}
+
+impl<Lines: Iterator<Item = String>> FaultInjector<Lines> {
+    pub fn new(lines: Lines, config: FaultConfig) -> Result<Self, FaultConfigError> {
+        config.validate()?;
+        Ok(Self {
+            lines,
+            rng: ChaCha8Rng::seed_from_u64(config.seed),
+            config,
+            ready: VecDeque::new(),
+            delayed: None,
+            source_position: 0,
+            injected: InjectedFaults::default(),
+        })
+    }
+
+    /// Number of source lines read so far, delivered or not. A snapshot taken "now" on the exchange is as of this line
+    pub fn source_position(&self) -> u64 {
+        self.source_position
+    }
+    pub fn injected(&self) -> &InjectedFaults {
+        &self.injected
+    }
+
+    fn with_seq_gap(&mut self, line: String) -> String {
+        let Ok(mut trade) = parse_trade(&line) else {
+            return line;
+        };
+        let Some(seq_no) = trade.seq_no else {
+            return line;
+        };
+        // the jump only has to differ from the next number, near u64::MAX it wraps around
+        trade.seq_no = Some(seq_no.wrapping_add(self.rng.gen_range(2..=100)));
+        self.injected.seq_gaps += 1;
+        format_trade(&trade)
+    }
+
+    fn corrupt(&mut self, mut line: String) -> String {
+        let length = self.rng.gen_range(0..line.len().max(1));
+        // the feed is ascii, so any byte is a char boundary
+        line.truncate(length);
+        self.injected.corrupted += 1;
+        line
+    }
+
+    /// Moves one source line through the faults into `ready`, false at the end of the source
+    fn pull(&mut self) -> bool {
+        let Some(line) = self.lines.next() else {
+            if let Some(delayed) = self.delayed.take() {
+                self.ready.push_back(delayed);
+            }
+            return false;
+        };
+        self.source_position += 1;
+        let config = self.config.clone();
+        let line = if self.rng.gen_bool(config.drop_probability) {
+            self.injected.dropped += 1;
+            None
+        } else if self.rng.gen_bool(config.duplicate_probability) {
+            self.injected.duplicated += 1;
+            self.ready.push_back(line.clone());
+            Some(line)
+        } else if self.delayed.is_none() && self.rng.gen_bool(config.reorder_probability) {
+            self.injected.reordered += 1;
+            self.delayed = Some(line);
+            return true;
+        } else if self.rng.gen_bool(config.corrupt_probability) {
+            Some(self.corrupt(line))
+        } else if self.rng.gen_bool(config.seq_gap_probability) {
+            Some(self.with_seq_gap(line))
+        } else {
+            Some(line)
+        };
+        self.ready.extend(line);
+        if let Some(delayed) = self.delayed.take() {
+            self.ready.push_back(delayed);
+        }
+        true
+    }
+}
+
+impl<Lines: Iterator<Item = String>> Iterator for FaultInjector<Lines> {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        while self.ready.is_empty() && self.pull() {}
+        self.ready.pop_front()
+    }
+}
@@ -0,0 +1,185 @@
+use crate::common::*;
+use crate::decimal::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+/// Prices and amounts in the feed are decimals, we work with them multiplied by 1e8
+pub const SCALE_RATIO: f64 = 1e8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")] // Ensure consistent casing if needed
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Trade {
+    pub platform_time: u64,
+    pub exchange_time: u64,
+    pub seq_no: Option<u64>, // Nullable sequence number
+    pub side: Side,          // "Bid" or "Ask"
+    pub price: f64,
+    pub amount: f64,
+    pub is_eot: bool,
+}
+
+fn is_integer(num: f64) -> bool {
+    (num.round() - num).abs() < 1e-5
+}
+
+/// Above this the product with `SCALE_RATIO` is too coarse for `is_integer`
+const MAX_FAST_SCALED: f64 = (1u64 << 52) as f64;
+
+fn to_scaled(value: f64) -> u64 {
+    try_to_scaled(value).unwrap_or_else(|| panic!("{} is not a non-negative multiple of 1e-8", value))
+}
+
+fn try_to_scaled(value: f64) -> Option<u64> {
+    if !(value >= 0.0 && value.is_finite()) {
+        return None;
+    }
+    let scaled = value * SCALE_RATIO;
+    if scaled < MAX_FAST_SCALED && is_integer(scaled) {
+        return Some(scaled.round() as u64);
+    }
+    // the shortest decimal that parses back to `value` is what the feed had
+    parse_scaled(&value.to_string()).ok()
+}
+
+/// Back to the decimal units of the feed
+pub fn unscaled(value: u64) -> f64 {
+    value as f64 / SCALE_RATIO
+}
+
+impl Trade {
+    pub fn scaled_price(&self) -> u64 {
+        to_scaled(self.price)
+    }
+    pub fn scaled_amount(&self) -> Amount {
+        to_scaled(self.amount)
+    }
+    /// `None` for a price that is not positive or either value that can't be scaled, instead of panicking
+    pub fn try_scaled(&self) -> Option<(u64, Amount)> {
+        let price = try_to_scaled(self.price).filter(|&price| price > 0)?;
+        Some((price, try_to_scaled(self.amount)?))
+    }
+}
+
+pub fn parse_trade(line: &str) -> serde_json::Result<Trade> {
+    serde_json::from_str(line)
+}
+
+/// `(price, amount)` after scaling, as `AggregatedBook::apply_snapshot` takes them
+pub type ScaledLevels = Vec<(u64, Amount)>;
+
+/// Full book of both sides, every level not listed is gone. Always a transaction of its own
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeedSnapshot {
+    pub platform_time: u64,
+    pub exchange_time: u64,
+    /// Deltas up to this one are already in the snapshot
+    pub seq_no: Option<u64>,
+    /// `(price, amount)` in the decimal units of the feed, in any order
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+impl FeedSnapshot {
+    /// Scaled bids and asks, `None` if any level can't be scaled, see `Trade::try_scaled`
+    pub fn try_scaled(&self) -> Option<(ScaledLevels, ScaledLevels)> {
+        let scale = |levels: &[(f64, f64)]| {
+            levels
+                .iter()
+                .map(|&(price, amount)| {
+                    let price = try_to_scaled(price).filter(|&price| price > 0)?;
+                    Some((price, try_to_scaled(amount)?))
+                })
+                .collect::<Option<ScaledLevels>>()
+        };
+        Some((scale(&self.bids)?, scale(&self.asks)?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FeedMessage {
+    /// One changed level, as in the recorded feed
+    Delta(Trade),
+    Snapshot(FeedSnapshot),
+}
+
+impl FeedMessage {
+    pub fn seq_no(&self) -> Option<u64> {
+        match self {
+            Self::Delta(trade) => trade.seq_no,
+            Self::Snapshot(snapshot) => snapshot.seq_no,
+        }
+    }
+    pub fn is_eot(&self) -> bool {
+        match self {
+            Self::Delta(trade) => trade.is_eot,
+            Self::Snapshot(_) => true,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotLine {
+    snapshot: FeedSnapshot,
+}
+
+/// Deltas are arrays like the recorded feed, snapshots are objects `{"snapshot": {...}}`
+pub fn parse_feed_message(line: &str) -> serde_json::Result<FeedMessage> {
+    if line.trim_start().starts_with('{') {
+        let line: SnapshotLine = serde_json::from_str(line)?;
+        return Ok(FeedMessage::Snapshot(line.snapshot));
+    }
+    parse_trade(line).map(FeedMessage::Delta)
+}
+
+/// Line that `parse_feed_message` reads back
+pub fn format_feed_message(message: &FeedMessage) -> String {
+    match message {
+        FeedMessage::Delta(trade) => format_trade(trade),
+        FeedMessage::Snapshot(snapshot) => serde_json::to_string(&BTreeMap::from([("snapshot", snapshot)])).unwrap(),
+    }
+}
+
+/// Line in the same array form as the recorded feed, `parse_trade` reads it back
+pub fn format_trade(trade: &Trade) -> String {
+    serde_json::to_string(&(
+        trade.platform_time,
+        trade.exchange_time,
+        trade.seq_no,
+        trade.side,
+        trade.price,
+        trade.amount,
+        trade.is_eot,
+    ))
+    .unwrap()
+}
+
+/// Lines of the feed file at `path` for the binaries, `-` reads stdin
+pub fn open_feed(path: &str) -> io::Result<Box<dyn BufRead + Send>> {
+    match path {
+        "-" => Ok(Box::new(BufReader::new(io::stdin()))),
+        path => Ok(Box::new(BufReader::new(File::open(path)?))),
+    }
+}
+
+pub fn read_trades(path: &str) -> Vec<Trade> {
+    let file = File::open(path).expect("Cannot open file");
+    let reader = BufReader::new(file);
+
+    let mut arr = Vec::<Trade>::new();
+    for line in reader.lines() {
+        let line = line.expect("Error reading line");
+        arr.push(parse_trade(&line).expect("Invalid JSON format"));
+    }
+    arr
+}
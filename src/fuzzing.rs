@@ -0,0 +1,113 @@
+use crate::common::*;
+use crate::feed::Side;
+use crate::subscription::*;
+
+/// Rules header: max depth, flags and 3 parameter bytes for each of two schedules
+pub const RULES_HEADER_SIZE: usize = 8;
+/// Price and amount, both u16 little endian
+pub const QUOTE_SIZE: usize = 4;
+/// Quote with this price switches rules, a new header follows it
+pub const SET_RULES_PRICE: u16 = u16::MAX;
+
+#[derive(Debug, Clone)]
+pub enum FuzzOperation {
+    SetQuote { price: u64, amount: Amount },
+    SetRules(SubscriptionRules),
+}
+
+/// Arbitrary bytes decoded into rules and a sequence of updates for one side of the book
+#[derive(Debug, Clone)]
+pub struct FuzzInput {
+    pub side: Side,
+    pub rules: SubscriptionRules,
+    pub operations: Vec<FuzzOperation>,
+}
+
+fn decode_schedule(kind: u8, parameters: &[u8]) -> ThresholdSchedule {
+    // thresholds are scaled up to be comparable with u16 amounts
+    let (a, b, c) = (
+        parameters[0] as Amount,
+        parameters[1] as Amount,
+        parameters[2] as Amount,
+    );
+    let cap = if c == 0 { None } else { Some(c * 64) };
+    match kind % 4 {
+        0 => ThresholdSchedule::Table {
+            amounts: vec![a * 16 + 1, b * 16 + 1],
+            fallback: c * 16 + 1,
+        },
+        1 => ThresholdSchedule::Geometric {
+            start: a * 4 + 1,
+            ratio: 1.0 + b as f64 / 64.0,
+            cap,
+        },
+        2 => ThresholdSchedule::Arithmetic {
+            start: a * 4 + 1,
+            step: b * 4,
+            cap,
+        },
+        _ => ThresholdSchedule::TableThen {
+            amounts: vec![a * 16 + 1],
+            then: Box::new(ThresholdSchedule::Geometric {
+                start: b * 4 + 1,
+                ratio: 1.5,
+                cap,
+            }),
+        },
+    }
+}
+
+fn decode_rules(header: &[u8]) -> SubscriptionRules {
+    let max_depth = 1 + header[0] as usize % 64;
+    let flags = header[1];
+    let rules = SubscriptionRules::with_schedule(decode_schedule(flags >> 2, &header[2..5]), max_depth);
+    if flags & 2 == 0 {
+        return rules;
+    }
+    // a maximum below the minimum is rejected by the rules, such inputs are run without maximums
+    let maximum_schedule = decode_schedule(flags >> 4, &header[5..8]);
+    rules.clone().try_with_maximum_schedule(maximum_schedule).unwrap_or(rules)
+}
+
+impl FuzzInput {
+    /// Never fails on long enough input, so that every mutation is a valid test case
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < RULES_HEADER_SIZE {
+            return None;
+        }
+        let side = if data[1] & 1 == 0 { Side::Ask } else { Side::Bid };
+        let rules = decode_rules(&data[..RULES_HEADER_SIZE]);
+
+        let mut operations = Vec::new();
+        let mut rest = &data[RULES_HEADER_SIZE..];
+        while rest.len() >= QUOTE_SIZE {
+            let price = u16::from_le_bytes([rest[0], rest[1]]);
+            let amount = u16::from_le_bytes([rest[2], rest[3]]);
+            rest = &rest[QUOTE_SIZE..];
+            if price != SET_RULES_PRICE {
+                // zero price is the sentinel of `BidKey`
+                operations.push(FuzzOperation::SetQuote {
+                    price: price as u64 + 1,
+                    amount: amount as Amount,
+                });
+                continue;
+            }
+            if rest.len() < RULES_HEADER_SIZE {
+                break;
+            }
+            operations.push(FuzzOperation::SetRules(decode_rules(&rest[..RULES_HEADER_SIZE])));
+            rest = &rest[RULES_HEADER_SIZE..];
+        }
+        Some(Self {
+            side,
+            rules,
+            operations,
+        })
+    }
+}
+
+pub fn encode_quote(price: u16, amount: u16) -> [u8; QUOTE_SIZE] {
+    let [price_low, price_high] = price.to_le_bytes();
+    let [amount_low, amount_high] = amount.to_le_bytes();
+    [price_low, price_high, amount_low, amount_high]
+}
@@ -0,0 +1,283 @@
+use crate::feed::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use rand_distr::{Distribution, Exp, LogNormal, Normal, Poisson};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
+
+/// Parameters of the synthetic market, prices and sizes are decimals like in the feed
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarketModel {
+    pub seed: u64,
+    pub transactions: usize,
+    pub initial_mid: f64,
+    pub tick_size: f64,
+    pub lot_size: f64,
+    /// Standard deviation of the mid move between transactions, in ticks
+    pub mid_volatility_ticks: f64,
+    /// Mean number of updates in one transaction, arrivals are Poisson
+    pub updates_per_transaction: f64,
+    /// Mean distance of new quotes from the mid, in ticks, distances are exponential
+    pub mean_distance_ticks: f64,
+    /// Level sizes are log-normal with this median, in lots
+    pub median_level_lots: f64,
+    pub level_size_sigma: f64,
+    /// Probability that an update removes an existing level instead of setting one
+    pub cancel_probability: f64,
+    /// Probability that a transaction starts with a sweep of the best levels of one side
+    pub sweep_probability: f64,
+    pub max_sweep_levels: usize,
+    pub start_time: u64,
+    /// Mean time between transactions in nanoseconds, exponential
+    pub mean_interval_nanos: f64,
+    /// Delay of `platform_time` after `exchange_time`
+    pub platform_delay_nanos: u64,
+    /// Fill `seq_no` with consecutive numbers, the recorded fixture has none
+    pub sequence_numbers: bool,
+}
+
+impl Default for MarketModel {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            transactions: 10_000,
+            initial_mid: 1660.0,
+            tick_size: 0.01,
+            lot_size: 0.0001,
+            mid_volatility_ticks: 2.0,
+            updates_per_transaction: 1.5,
+            mean_distance_ticks: 30.0,
+            median_level_lots: 2000.0,
+            level_size_sigma: 1.5,
+            cancel_probability: 0.3,
+            sweep_probability: 0.01,
+            max_sweep_levels: 5,
+            start_time: 1_696_329_921_309_445_000,
+            mean_interval_nanos: 50_000_000.0,
+            platform_delay_nanos: 500_000,
+            sequence_numbers: true,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MarketModelError {
+    NotPositive { field: &'static str },
+    /// The value is not a multiple of 1e-8, so it can't be scaled
+    NotScalable { field: &'static str },
+    InvalidProbability { field: &'static str },
+    /// The initial mid is not a multiple of `tick_size`
+    MidOffTick,
+}
+
+impl fmt::Display for MarketModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPositive { field } => write!(f, "{} must be positive", field),
+            Self::NotScalable { field } => write!(f, "{} must be a multiple of 1e-8", field),
+            Self::InvalidProbability { field } => write!(f, "{} must be between 0 and 1", field),
+            Self::MidOffTick => write!(f, "initial_mid must be a multiple of tick_size"),
+        }
+    }
+}
+
+impl std::error::Error for MarketModelError {}
+
+fn scale(value: f64, field: &'static str) -> Result<u64, MarketModelError> {
+    if !(value > 0.0 && value.is_finite()) {
+        return Err(MarketModelError::NotPositive { field });
+    }
+    let scaled = value * SCALE_RATIO;
+    if (scaled.round() - scaled).abs() > 1e-5 {
+        return Err(MarketModelError::NotScalable { field });
+    }
+    Ok(scaled.round() as u64)
+}
+
+fn check_probability(value: f64, field: &'static str) -> Result<(), MarketModelError> {
+    if !(0.0..=1.0).contains(&value) {
+        return Err(MarketModelError::InvalidProbability { field });
+    }
+    Ok(())
+}
+
+fn check_positive(value: f64, field: &'static str) -> Result<(), MarketModelError> {
+    if !(value > 0.0 && value.is_finite()) {
+        return Err(MarketModelError::NotPositive { field });
+    }
+    Ok(())
+}
+
+/// Endless-until-`transactions` stream of feed lines from a random market.
+/// The generator keeps its own book, so cancels hit existing levels and the book never crosses
+pub struct FeedGenerator {
+    model: MarketModel,
+    rng: ChaCha8Rng,
+    tick_size: u64,
+    lot_size: u64,
+    /// In ticks, asks are strictly above it and bids strictly below
+    mid: u64,
+    asks: BTreeMap<u64, u64>,
+    bids: BTreeMap<u64, u64>,
+    mid_move: Normal<f64>,
+    arrivals: Poisson<f64>,
+    distance: Exp<f64>,
+    level_lots: LogNormal<f64>,
+    interval: Exp<f64>,
+    exchange_time: u64,
+    seq_no: u64,
+    transaction: usize,
+    pending: VecDeque<Trade>,
+}
+
+impl FeedGenerator {
+    pub fn new(model: MarketModel) -> Result<Self, MarketModelError> {
+        let tick_size = scale(model.tick_size, "tick_size")?;
+        let lot_size = scale(model.lot_size, "lot_size")?;
+        let initial_mid = scale(model.initial_mid, "initial_mid")?;
+        if initial_mid % tick_size != 0 {
+            return Err(MarketModelError::MidOffTick);
+        }
+        check_positive(model.updates_per_transaction, "updates_per_transaction")?;
+        check_positive(model.mean_distance_ticks, "mean_distance_ticks")?;
+        check_positive(model.median_level_lots, "median_level_lots")?;
+        check_positive(model.mean_interval_nanos, "mean_interval_nanos")?;
+        if !(model.mid_volatility_ticks >= 0.0 && model.mid_volatility_ticks.is_finite()) {
+            return Err(MarketModelError::NotPositive { field: "mid_volatility_ticks" });
+        }
+        if !(model.level_size_sigma >= 0.0 && model.level_size_sigma.is_finite()) {
+            return Err(MarketModelError::NotPositive { field: "level_size_sigma" });
+        }
+        check_probability(model.cancel_probability, "cancel_probability")?;
+        check_probability(model.sweep_probability, "sweep_probability")?;
+
+        Ok(Self {
+            rng: ChaCha8Rng::seed_from_u64(model.seed),
+            tick_size,
+            lot_size,
+            mid: initial_mid / tick_size,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            mid_move: Normal::new(0.0, model.mid_volatility_ticks).unwrap(),
+            arrivals: Poisson::new(model.updates_per_transaction).unwrap(),
+            distance: Exp::new(1.0 / model.mean_distance_ticks).unwrap(),
+            level_lots: LogNormal::new(model.median_level_lots.ln(), model.level_size_sigma).unwrap(),
+            interval: Exp::new(1.0 / model.mean_interval_nanos).unwrap(),
+            exchange_time: model.start_time,
+            seq_no: 0,
+            transaction: 0,
+            pending: VecDeque::new(),
+            model,
+        })
+    }
+
+    fn side_levels(&mut self, side: Side) -> &mut BTreeMap<u64, u64> {
+        match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        }
+    }
+
+    fn push_update(&mut self, side: Side, price_ticks: u64, lots: u64) {
+        if lots == 0 {
+            self.side_levels(side).remove(&price_ticks);
+        } else {
+            self.side_levels(side).insert(price_ticks, lots);
+        }
+        self.seq_no += 1;
+        self.pending.push_back(Trade {
+            platform_time: self.exchange_time + self.model.platform_delay_nanos,
+            exchange_time: self.exchange_time,
+            seq_no: self.model.sequence_numbers.then_some(self.seq_no),
+            side,
+            price: (price_ticks * self.tick_size) as f64 / SCALE_RATIO,
+            amount: (lots * self.lot_size) as f64 / SCALE_RATIO,
+            is_eot: false,
+        });
+    }
+
+    fn random_side(&mut self) -> Side {
+        if self.rng.gen_bool(0.5) {
+            Side::Ask
+        } else {
+            Side::Bid
+        }
+    }
+
+    /// Removes the best levels of one side, the mid follows the sweep
+    fn sweep(&mut self) {
+        let side = self.random_side();
+        let count = self.rng.gen_range(1..=self.model.max_sweep_levels.max(1));
+        let swept: Vec<u64> = match side {
+            Side::Ask => self.asks.keys().take(count).copied().collect(),
+            Side::Bid => self.bids.keys().rev().take(count).copied().collect(),
+        };
+        for &price_ticks in swept.iter() {
+            self.push_update(side, price_ticks, 0);
+        }
+        if let Some(&last) = swept.last() {
+            self.mid = last;
+        }
+    }
+
+    /// Moves the mid and cancels the levels that would cross it
+    fn move_mid(&mut self) {
+        let step = self.mid_move.sample(&mut self.rng).round() as i64;
+        self.mid = (self.mid as i64 + step).max(2) as u64;
+        let crossed_asks: Vec<u64> = self.asks.range(..=self.mid).map(|(&price, _)| price).collect();
+        for price_ticks in crossed_asks {
+            self.push_update(Side::Ask, price_ticks, 0);
+        }
+        let crossed_bids: Vec<u64> = self.bids.range(self.mid..).map(|(&price, _)| price).collect();
+        for price_ticks in crossed_bids {
+            self.push_update(Side::Bid, price_ticks, 0);
+        }
+    }
+
+    fn random_update(&mut self) {
+        let side = self.random_side();
+        let levels_count = self.side_levels(side).len();
+        if levels_count > 0 && self.rng.gen_bool(self.model.cancel_probability) {
+            let index = self.rng.gen_range(0..levels_count);
+            let price_ticks = *self.side_levels(side).keys().nth(index).unwrap();
+            self.push_update(side, price_ticks, 0);
+            return;
+        }
+        let distance = 1 + self.distance.sample(&mut self.rng) as u64;
+        let price_ticks = match side {
+            Side::Ask => self.mid + distance,
+            Side::Bid => self.mid.saturating_sub(distance).max(1),
+        };
+        let lots = (self.level_lots.sample(&mut self.rng).round() as u64).max(1);
+        self.push_update(side, price_ticks, lots);
+    }
+
+    fn generate_transaction(&mut self) {
+        self.exchange_time += self.interval.sample(&mut self.rng) as u64;
+        if self.rng.gen_bool(self.model.sweep_probability) {
+            self.sweep();
+        }
+        self.move_mid();
+        let arrivals = (self.arrivals.sample(&mut self.rng) as usize).max(1);
+        for _ in 0..arrivals {
+            self.random_update();
+        }
+        self.pending.back_mut().unwrap().is_eot = true;
+    }
+}
+
+impl Iterator for FeedGenerator {
+    type Item = Trade;
+
+    fn next(&mut self) -> Option<Trade> {
+        if self.pending.is_empty() && self.transaction < self.model.transactions {
+            self.transaction += 1;
+            self.generate_transaction();
+        }
+        self.pending.pop_front()
+    }
+}
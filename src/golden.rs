@@ -0,0 +1,115 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use std::fmt::Write;
+use std::path::Path;
+
+/// How many differing transactions are printed, the rest are only counted
+const MAX_REPORTED_DIFFERENCES: usize = 5;
+
+pub const UPDATE_GOLDEN_VARIABLE: &str = "UPDATE_GOLDEN";
+
+fn write_levels(result: &mut String, side: Side, levels: impl Iterator<Item = (u64, Amount)>) {
+    for (price, amount) in levels {
+        writeln!(result, "{:?} {} {}", side, price, amount).unwrap();
+    }
+}
+
+/// Replays the feed and renders both aggregated ladders after every transaction, one level per line
+pub fn render_snapshots<SolutionAsk, SolutionBid>(trades: &[Trade], rules: BookRules) -> String
+where
+    SolutionAsk: AgregatedL2Trait<AskKey>,
+    SolutionBid: AgregatedL2Trait<BidKey>,
+{
+    let mut book = AggregatedBook::<SolutionAsk, SolutionBid>::new(rules);
+    let mut result = String::new();
+    let mut transaction = 0;
+    let mut first_line = 1;
+    for (line_index, trade) in trades.iter().enumerate() {
+        book.apply_trade(trade);
+        if !trade.is_eot && line_index + 1 != trades.len() {
+            continue;
+        }
+        transaction += 1;
+        writeln!(result, "# transaction {}, lines {}..={}", transaction, first_line, line_index + 1).unwrap();
+        write_levels(&mut result, Side::Bid, book.bid().aggregated_levels());
+        write_levels(&mut result, Side::Ask, book.ask().aggregated_levels());
+        first_line = line_index + 2;
+    }
+    result
+}
+
+fn split_transactions(snapshots: &str) -> Vec<&str> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for (offset, _) in snapshots.match_indices("# transaction ") {
+        if offset != 0 {
+            blocks.push(&snapshots[start..offset]);
+            start = offset;
+        }
+    }
+    if start < snapshots.len() {
+        blocks.push(&snapshots[start..]);
+    }
+    blocks
+}
+
+/// Compares snapshots transaction by transaction, `None` if they are equal
+pub fn diff_snapshots(expected: &str, actual: &str) -> Option<String> {
+    let expected_blocks = split_transactions(expected);
+    let actual_blocks = split_transactions(actual);
+    let mut report = String::new();
+    let mut differences = 0;
+    for index in 0..expected_blocks.len().max(actual_blocks.len()) {
+        let expected_block = expected_blocks.get(index).copied().unwrap_or("");
+        let actual_block = actual_blocks.get(index).copied().unwrap_or("");
+        if expected_block == actual_block {
+            continue;
+        }
+        differences += 1;
+        if differences > MAX_REPORTED_DIFFERENCES {
+            continue;
+        }
+        for line in expected_block.lines() {
+            let sign = if actual_block.lines().any(|other| other == line) { ' ' } else { '-' };
+            writeln!(report, "{} {}", sign, line).unwrap();
+        }
+        for line in actual_block.lines() {
+            if !expected_block.lines().any(|other| other == line) {
+                writeln!(report, "+ {}", line).unwrap();
+            }
+        }
+        report.push('\n');
+    }
+    if differences == 0 {
+        return None;
+    }
+    writeln!(report, "{} transaction(s) differ", differences).unwrap();
+    Some(report)
+}
+
+/// Fails with a readable diff when `actual` differs from the file.
+/// With `UPDATE_GOLDEN=1` in the environment the file is rewritten instead, so with several implementations
+/// compare their outputs with `diff_snapshots` first and pass the one they agree on
+pub fn check_golden(path: &Path, actual: &str) -> Result<(), String> {
+    if std::env::var_os(UPDATE_GOLDEN_VARIABLE).is_some() {
+        std::fs::write(path, actual).map_err(|error| format!("cannot write {:?}: {}", path, error))?;
+        return Ok(());
+    }
+    let expected = std::fs::read_to_string(path).map_err(|error| {
+        format!(
+            "cannot read {:?}: {}, run with {}=1 to create it",
+            path, error, UPDATE_GOLDEN_VARIABLE
+        )
+    })?;
+    match diff_snapshots(&expected, actual) {
+        None => Ok(()),
+        Some(diff) => Err(format!(
+            "{:?} differs (- expected, + actual):\n{}run with {}=1 if the change is intended",
+            path, diff, UPDATE_GOLDEN_VARIABLE
+        )),
+    }
+}
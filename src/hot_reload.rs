@@ -0,0 +1,278 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+/// Named subscriptions, each gets its own pair of aggregated ladders
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesConfig {
+    pub subscriptions: BTreeMap<String, BookRules>,
+}
+
+#[derive(Debug)]
+pub enum RulesConfigError {
+    Io(std::io::Error),
+    Invalid(serde_json::Error),
+}
+
+impl fmt::Display for RulesConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "cannot read rules config: {}", error),
+            Self::Invalid(error) => write!(f, "invalid rules config: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for RulesConfigError {}
+
+#[derive(Debug)]
+pub enum HotReloadError {
+    /// The config at start, later bad configs are only reported
+    Rules(RulesConfigError),
+    /// Reading the feed failed, so it was cut short rather than finished
+    Feed(std::io::Error),
+}
+
+impl fmt::Display for HotReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Rules(error) => write!(f, "{}", error),
+            Self::Feed(error) => write!(f, "cannot read the feed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for HotReloadError {}
+
+impl From<RulesConfigError> for HotReloadError {
+    fn from(error: RulesConfigError) -> Self {
+        Self::Rules(error)
+    }
+}
+
+impl RulesConfig {
+    /// Parses and validates the whole config, nothing is applied on error
+    pub fn parse(content: &str) -> Result<Self, RulesConfigError> {
+        serde_json::from_str(content).map_err(RulesConfigError::Invalid)
+    }
+}
+
+/// Detects changes of the rules config file by comparing its content between polls
+pub struct RulesWatcher {
+    path: PathBuf,
+    last_content: Option<String>,
+    /// Errors are repeated on every poll until the file is read once
+    has_read: bool,
+}
+
+impl RulesWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            last_content: None,
+            has_read: false,
+        }
+    }
+    /// Returns `None` while the file is unchanged since the previous call
+    pub fn poll(&mut self) -> Option<Result<RulesConfig, RulesConfigError>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => {
+                if self.last_content.as_ref() == Some(&content) {
+                    return None;
+                }
+                let result = RulesConfig::parse(&content);
+                self.last_content = Some(content);
+                self.has_read = true;
+                Some(result)
+            }
+            Err(error) => {
+                // a file lost after a successful read is reported once, not on every poll
+                if self.last_content.take().is_none() && self.has_read {
+                    return None;
+                }
+                Some(Err(RulesConfigError::Io(error)))
+            }
+        }
+    }
+}
+
+/// Full state of one ladder, emitted after the rules of a subscription change
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LadderRefresh {
+    pub subscription: String,
+    pub side: Side,
+    pub levels: Vec<(u64, u64)>,
+}
+
+pub struct HotReloadAggregator<SolutionAsk, SolutionBid> {
+    raw_asks: BTreeMap<u64, Amount>,
+    raw_bids: BTreeMap<u64, Amount>,
+    subscriptions: BTreeMap<String, AggregatedBook<SolutionAsk, SolutionBid>>,
+}
+
+impl<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>> Default
+    for HotReloadAggregator<SolutionAsk, SolutionBid>
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>>
+    HotReloadAggregator<SolutionAsk, SolutionBid>
+{
+    pub fn new() -> Self {
+        Self {
+            raw_asks: BTreeMap::new(),
+            raw_bids: BTreeMap::new(),
+            subscriptions: BTreeMap::new(),
+        }
+    }
+    pub fn set_quote(&mut self, side: Side, price: u64, amount: Amount) {
+        let raw_levels = match side {
+            Side::Ask => &mut self.raw_asks,
+            Side::Bid => &mut self.raw_bids,
+        };
+        if amount == 0 {
+            raw_levels.remove(&price);
+        } else {
+            raw_levels.insert(price, amount);
+        }
+        for book in self.subscriptions.values_mut() {
+            book.set_quote(side, price, amount);
+        }
+    }
+    /// `AggregatedBook::apply_trade` for the raw levels and every subscription at once
+    pub fn apply_trade(&mut self, trade: &Trade) -> bool {
+        let Some((price, amount)) = trade.try_scaled() else {
+            return false;
+        };
+        self.set_quote(trade.side, price, amount);
+        true
+    }
+    /// `AggregatedBook::apply_snapshot` for the raw levels and every subscription at once.
+    /// Returns `false` and changes nothing if any level can't be scaled
+    pub fn apply_feed_snapshot(&mut self, snapshot: &FeedSnapshot) -> bool {
+        let Some((bids, asks)) = snapshot.try_scaled() else {
+            return false;
+        };
+        let raw_levels = |levels: &ScaledLevels| levels.iter().copied().filter(|&(_, amount)| amount > 0).collect();
+        self.raw_bids = raw_levels(&bids);
+        self.raw_asks = raw_levels(&asks);
+        for book in self.subscriptions.values_mut() {
+            book.apply_snapshot(bids.iter().copied(), asks.iter().copied());
+        }
+        true
+    }
+    /// Config is already validated, so applying it can not fail half way
+    pub fn apply_rules(&mut self, config: RulesConfig) -> Vec<LadderRefresh> {
+        let mut refreshes = Vec::new();
+        self.subscriptions
+            .retain(|name, _| config.subscriptions.contains_key(name));
+
+        for (name, rules) in config.subscriptions {
+            let changed_sides = match self.subscriptions.get_mut(&name) {
+                Some(book) => book.set_rules(rules),
+                None => {
+                    let mut book = AggregatedBook::new(rules);
+                    for (&price, &amount) in self.raw_asks.iter() {
+                        book.set_quote(Side::Ask, price, amount);
+                    }
+                    for (&price, &amount) in self.raw_bids.iter() {
+                        book.set_quote(Side::Bid, price, amount);
+                    }
+                    self.subscriptions.insert(name.clone(), book);
+                    vec![Side::Bid, Side::Ask]
+                }
+            };
+            let book = &self.subscriptions[&name];
+            for side in changed_sides {
+                refreshes.push(LadderRefresh {
+                    subscription: name.clone(),
+                    side,
+                    levels: book.get_aggregated_levels_tuples(side),
+                });
+            }
+        }
+        refreshes
+    }
+    pub fn get_subscription(&self, name: &str) -> Option<&AggregatedBook<SolutionAsk, SolutionBid>> {
+        self.subscriptions.get(name)
+    }
+}
+
+/// Runs until the feed ends, applying every valid change of the rules file on the fly.
+/// Refreshes are written to `output` as json lines, rejected configs are reported to stderr.
+/// A feed that fails to read ends the run with `HotReloadError::Feed`
+pub fn run_hot_reload<SolutionAsk, SolutionBid>(
+    rules_path: &str,
+    feed: impl BufRead + Send + 'static,
+    output: &mut impl Write,
+    poll_interval: Duration,
+) -> Result<(), HotReloadError>
+where
+    SolutionAsk: AgregatedL2Trait<AskKey>,
+    SolutionBid: AgregatedL2Trait<BidKey>,
+{
+    let mut watcher = RulesWatcher::new(rules_path);
+    let initial_config = watcher.poll().expect("first poll reports either the config or an error")?;
+
+    let mut aggregator = HotReloadAggregator::<SolutionAsk, SolutionBid>::new();
+    write_refreshes(output, aggregator.apply_rules(initial_config));
+
+    // reading in a separate thread, so that a quiet feed doesn't delay the reload
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in feed.lines() {
+            let is_error = line.is_err();
+            if sender.send(line).is_err() || is_error {
+                break;
+            }
+        }
+    });
+
+    let mut last_poll = Instant::now();
+    loop {
+        match receiver.recv_timeout(poll_interval) {
+            Ok(Err(error)) => return Err(HotReloadError::Feed(error)),
+            Ok(Ok(line)) => {
+                match parse_feed_message(&line) {
+                    Ok(FeedMessage::Delta(trade)) if aggregator.apply_trade(&trade) => (),
+                    Ok(FeedMessage::Snapshot(snapshot)) if aggregator.apply_feed_snapshot(&snapshot) => (),
+                    Ok(_) => eprintln!("Skipping line with invalid values {:?}", line),
+                    Err(error) => eprintln!("Skipping invalid line {:?}: {}", line, error),
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        if last_poll.elapsed() < poll_interval {
+            continue;
+        }
+        last_poll = Instant::now();
+        match watcher.poll() {
+            Some(Ok(config)) => write_refreshes(output, aggregator.apply_rules(config)),
+            Some(Err(error)) => eprintln!("Rules config rejected, keeping current rules: {}", error),
+            None => (),
+        }
+    }
+}
+
+fn write_refreshes(output: &mut impl Write, refreshes: Vec<LadderRefresh>) {
+    for refresh in refreshes {
+        let line = serde_json::to_string(&refresh).expect("refresh is always serializable");
+        writeln!(output, "{}", line).expect("Cannot write output");
+    }
+    output.flush().expect("Cannot write output");
+}
@@ -0,0 +1,322 @@
+use crate::common::*;
+use crate::decimal::*;
+use crate::feed::*;
+use crate::ingest::*;
+use crate::metrics::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+/// Levels as exact decimal strings in the units of the feed, best first
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Levels {
+    pub bids: Vec<(String, String)>,
+    pub asks: Vec<(String, String)>,
+}
+
+fn decimal_levels(levels: impl Iterator<Item = (u64, Amount)>, depth: usize) -> Vec<(String, String)> {
+    Scale::FEED.format_levels(levels.take(depth))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BookResponse {
+    pub instrument: String,
+    /// Last applied `seq_no`, `None` for feeds without them
+    pub seq_no: Option<u64>,
+    pub is_stale: bool,
+    pub raw: Levels,
+    pub aggregated: Levels,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InstrumentStats {
+    #[serde(flatten)]
+    pub ingest: IngestStats,
+    pub transactions: u64,
+    pub raw_bids: usize,
+    pub raw_asks: usize,
+    pub aggregated_bids: usize,
+    pub aggregated_asks: usize,
+    pub is_stale: bool,
+    pub is_feed_running: bool,
+    /// Why the feed stopped early, `None` while it runs or after a clean end
+    pub feed_error: Option<String>,
+}
+
+struct Instrument<SolutionAsk, SolutionBid> {
+    ingest: FeedIngest<SolutionAsk, SolutionBid>,
+    transactions: u64,
+    is_feed_running: bool,
+    feed_error: Option<String>,
+}
+
+/// Books of all instruments, feeds write them a transaction at a time and requests read them in between
+pub struct BookRegistry<SolutionAsk, SolutionBid> {
+    instruments: Mutex<BTreeMap<String, Instrument<SolutionAsk, SolutionBid>>>,
+    /// Always locked after `instruments`
+    metrics: Mutex<PipelineMetrics>,
+}
+
+impl<SolutionAsk, SolutionBid> Default for BookRegistry<SolutionAsk, SolutionBid> {
+    fn default() -> Self {
+        Self {
+            instruments: Mutex::new(BTreeMap::new()),
+            metrics: Mutex::new(PipelineMetrics::new()),
+        }
+    }
+}
+
+impl<SolutionAsk, SolutionBid> BookRegistry<SolutionAsk, SolutionBid>
+where
+    SolutionAsk: AgregatedL2Trait<AskKey> + Send + 'static,
+    SolutionBid: AgregatedL2Trait<BidKey> + Send + 'static,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies lines of every finished transaction under one lock, so requests never see half of one
+    fn apply_transaction(&self, instrument: &str, lines: &[String]) {
+        let mut instruments = self.instruments.lock().unwrap();
+        let state = instruments.get_mut(instrument).expect("instrument is added before its feed starts");
+        let mut metrics = self.metrics.lock().unwrap();
+        for line in lines {
+            let event = match parse_feed_message(line) {
+                Ok(message) => {
+                    let event = state.ingest.apply_message(&message);
+                    match (&event, &message) {
+                        (IngestEvent::Applied, FeedMessage::Delta(trade)) => {
+                            metrics.observe_set_quote(instrument, state.ingest.book(), trade.side)
+                        }
+                        (IngestEvent::Snapshot, _) => metrics.observe_snapshot(instrument, state.ingest.book()),
+                        _ => {}
+                    }
+                    event
+                }
+                Err(_) => state.ingest.apply_line(line),
+            };
+            metrics.observe_ingest_event(instrument, &event);
+        }
+        metrics.observe_end_of_transaction(instrument, state.ingest.book());
+        state.transactions += 1;
+    }
+
+    /// Registers the instrument and ingests its feed on a new thread
+    pub fn spawn_feed(
+        self: &Arc<Self>,
+        instrument: &str,
+        rules: BookRules,
+        feed: impl BufRead + Send + 'static,
+    ) -> JoinHandle<()> {
+        let state = Instrument {
+            ingest: FeedIngest::new(rules),
+            transactions: 0,
+            is_feed_running: true,
+            feed_error: None,
+        };
+        self.instruments.lock().unwrap().insert(instrument.to_string(), state);
+        let registry = self.clone();
+        let instrument = instrument.to_string();
+        std::thread::spawn(move || {
+            let mut lines = Vec::new();
+            let mut feed_error = None;
+            for line in feed.lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(error) => {
+                        eprintln!("Cannot read the feed of {}: {}", instrument, error);
+                        // the unfinished transaction is dropped, the book stays at the last finished one
+                        lines.clear();
+                        feed_error = Some(error.to_string());
+                        break;
+                    }
+                };
+                let is_eot = parse_feed_message(&line).is_ok_and(|message| message.is_eot());
+                lines.push(line);
+                if is_eot {
+                    registry.apply_transaction(&instrument, &lines);
+                    lines.clear();
+                }
+            }
+            if !lines.is_empty() {
+                registry.apply_transaction(&instrument, &lines);
+            }
+            let mut instruments = registry.instruments.lock().unwrap();
+            let state = instruments.get_mut(&instrument).unwrap();
+            state.is_feed_running = false;
+            state.feed_error = feed_error;
+        })
+    }
+
+    /// Raw levels are cut to `depth` per side, aggregated ones are limited by the rules already
+    pub fn book(&self, instrument: &str, depth: usize) -> Option<BookResponse> {
+        let instruments = self.instruments.lock().unwrap();
+        let ingest = &instruments.get(instrument)?.ingest;
+        let book = ingest.book();
+        Some(BookResponse {
+            instrument: instrument.to_string(),
+            seq_no: ingest.expected_seq_no().map(|expected| expected - 1),
+            is_stale: ingest.is_stale(),
+            raw: Levels {
+                bids: decimal_levels(book.bid().raw_levels(), depth),
+                asks: decimal_levels(book.ask().raw_levels(), depth),
+            },
+            aggregated: Levels {
+                bids: decimal_levels(book.bid().aggregated_levels(), usize::MAX),
+                asks: decimal_levels(book.ask().aggregated_levels(), usize::MAX),
+            },
+        })
+    }
+
+    pub fn stats(&self) -> BTreeMap<String, InstrumentStats> {
+        let instruments = self.instruments.lock().unwrap();
+        instruments
+            .iter()
+            .map(|(name, state)| {
+                let book = state.ingest.book();
+                let stats = InstrumentStats {
+                    ingest: state.ingest.stats().clone(),
+                    transactions: state.transactions,
+                    raw_bids: book.bid().raw_levels_count(),
+                    raw_asks: book.ask().raw_levels_count(),
+                    aggregated_bids: book.bid().get_aggregated_levels().len(),
+                    aggregated_asks: book.ask().get_aggregated_levels().len(),
+                    is_stale: state.ingest.is_stale(),
+                    is_feed_running: state.is_feed_running,
+                    feed_error: state.feed_error.clone(),
+                };
+                (name.clone(), stats)
+            })
+            .collect()
+    }
+
+    /// Prometheus text format
+    pub fn metrics(&self) -> String {
+        self.metrics.lock().unwrap().render()
+    }
+
+    /// Instruments whose books can't be trusted after a gap or a corrupt line
+    pub fn stale_instruments(&self) -> Vec<String> {
+        let instruments = self.instruments.lock().unwrap();
+        instruments
+            .iter()
+            .filter(|(_, state)| state.ingest.is_stale())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Instruments whose feeds stopped on a read error
+    pub fn failed_instruments(&self) -> Vec<String> {
+        let instruments = self.instruments.lock().unwrap();
+        instruments
+            .iter()
+            .filter(|(_, state)| state.feed_error.is_some())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl HttpResponse {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(body).expect("responses are always serializable"),
+        }
+    }
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &BTreeMap::from([("error", message)]))
+    }
+}
+
+#[derive(Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    stale: Vec<String>,
+    failed: Vec<String>,
+}
+
+/// Routes `GET /health`, `GET /stats`, `GET /metrics` and `GET /books/<instrument>?depth=<levels>`
+pub fn handle_request<SolutionAsk, SolutionBid>(
+    registry: &BookRegistry<SolutionAsk, SolutionBid>,
+    method: &str,
+    url: &str,
+) -> HttpResponse
+where
+    SolutionAsk: AgregatedL2Trait<AskKey> + Send + 'static,
+    SolutionBid: AgregatedL2Trait<BidKey> + Send + 'static,
+{
+    if method != "GET" {
+        return HttpResponse::error(405, "only GET is supported");
+    }
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let mut depth = usize::MAX;
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        match parameter.split_once('=') {
+            Some(("depth", value)) => match value.parse() {
+                Ok(value) => depth = value,
+                Err(_) => return HttpResponse::error(400, "depth must be a number of levels"),
+            },
+            _ => return HttpResponse::error(400, &format!("unknown parameter {:?}", parameter)),
+        }
+    }
+    match path {
+        "/health" => {
+            let stale = registry.stale_instruments();
+            let failed = registry.failed_instruments();
+            let (status, text) = match (stale.is_empty(), failed.is_empty()) {
+                (true, true) => (200, "ok"),
+                (_, false) => (503, "failed"),
+                (false, true) => (503, "stale"),
+            };
+            HttpResponse::json(status, &HealthResponse { status: text, stale, failed })
+        }
+        "/stats" => HttpResponse::json(200, &registry.stats()),
+        "/metrics" => HttpResponse {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body: registry.metrics(),
+        },
+        _ => {
+            let Some(instrument) = path.strip_prefix("/books/") else {
+                return HttpResponse::error(404, "no such endpoint");
+            };
+            match registry.book(instrument, depth) {
+                Some(book) => HttpResponse::json(200, &book),
+                None => HttpResponse::error(404, &format!("unknown instrument {:?}", instrument)),
+            }
+        }
+    }
+}
+
+/// Answers requests until `server.unblock()` is called
+pub fn run_http_server<SolutionAsk, SolutionBid>(
+    server: &tiny_http::Server,
+    registry: &BookRegistry<SolutionAsk, SolutionBid>,
+) where
+    SolutionAsk: AgregatedL2Trait<AskKey> + Send + 'static,
+    SolutionBid: AgregatedL2Trait<BidKey> + Send + 'static,
+{
+    for request in server.incoming_requests() {
+        let response = handle_request(registry, request.method().as_str(), request.url());
+        let content_type = tiny_http::Header::from_bytes("Content-Type", response.content_type).unwrap();
+        let response = tiny_http::Response::from_string(response.body)
+            .with_status_code(response.status)
+            .with_header(content_type);
+        if let Err(error) = request.respond(response) {
+            eprintln!("Cannot respond: {}", error);
+        }
+    }
+}
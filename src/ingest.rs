@@ -0,0 +1,180 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use serde::Serialize;
+
+/// Full raw book of both sides as of `seq_no`, prices and amounts are scaled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookSnapshot {
+    pub seq_no: u64,
+    pub bids: Vec<(u64, Amount)>,
+    pub asks: Vec<(u64, Amount)>,
+}
+
+impl BookSnapshot {
+    pub fn from_book<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>>(
+        book: &AggregatedBook<SolutionAsk, SolutionBid>,
+        seq_no: u64,
+    ) -> Self {
+        Self {
+            seq_no,
+            bids: book.bid().raw_levels().collect(),
+            asks: book.ask().raw_levels().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IngestEvent {
+    Applied,
+    /// Full book replaced by a snapshot message, which also ends staleness
+    Snapshot,
+    /// Already applied or covered by the last snapshot, skipped without losing sync
+    Duplicate { seq_no: u64 },
+    Gap { expected: u64, received: u64 },
+    /// Unparseable line or values that can't be prices and amounts
+    Corrupt { error: String },
+    /// Arrived while the book is stale, waits for a resync
+    Skipped,
+}
+
+impl IngestEvent {
+    /// The book can't be trusted after these until a resync
+    pub fn is_problem(&self) -> bool {
+        matches!(self, Self::Gap { .. } | Self::Corrupt { .. })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct IngestStats {
+    pub applied: u64,
+    pub duplicates: u64,
+    pub gaps: u64,
+    pub corrupt: u64,
+    pub skipped: u64,
+    pub resyncs: u64,
+    pub snapshots: u64,
+}
+
+/// Applies feed lines to a book, checking `seq_no` continuity.
+/// After a gap or a corrupt line the book is stale and ignores updates until `resync` or a snapshot message
+pub struct FeedIngest<SolutionAsk, SolutionBid> {
+    book: AggregatedBook<SolutionAsk, SolutionBid>,
+    /// `None` until the first numbered message, messages without `seq_no` are never checked.
+    /// After `u64::MAX` numbering starts over, so it is `None` again
+    expected_seq_no: Option<u64>,
+    is_stale: bool,
+    stats: IngestStats,
+}
+
+impl<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>> FeedIngest<SolutionAsk, SolutionBid> {
+    pub fn new(rules: BookRules) -> Self {
+        Self {
+            book: AggregatedBook::new(rules),
+            expected_seq_no: None,
+            is_stale: false,
+            stats: IngestStats::default(),
+        }
+    }
+
+    fn mark_stale(&mut self, event: IngestEvent) -> IngestEvent {
+        match event {
+            IngestEvent::Gap { .. } => self.stats.gaps += 1,
+            _ => self.stats.corrupt += 1,
+        }
+        self.is_stale = true;
+        event
+    }
+
+    pub fn apply_line(&mut self, line: &str) -> IngestEvent {
+        match parse_feed_message(line) {
+            Ok(message) => self.apply_message(&message),
+            Err(_) if self.is_stale => {
+                self.stats.skipped += 1;
+                IngestEvent::Skipped
+            }
+            Err(error) => self.mark_stale(IngestEvent::Corrupt { error: error.to_string() }),
+        }
+    }
+
+    pub fn apply_message(&mut self, message: &FeedMessage) -> IngestEvent {
+        match message {
+            FeedMessage::Delta(trade) => self.apply_trade(trade),
+            FeedMessage::Snapshot(snapshot) => self.apply_feed_snapshot(snapshot),
+        }
+    }
+
+    /// Applied even when stale, that is how a feed with snapshots recovers from a gap without `resync`
+    pub fn apply_feed_snapshot(&mut self, snapshot: &FeedSnapshot) -> IngestEvent {
+        if let (Some(seq_no), Some(expected), false) = (snapshot.seq_no, self.expected_seq_no, self.is_stale) {
+            if seq_no < expected {
+                self.stats.duplicates += 1;
+                return IngestEvent::Duplicate { seq_no };
+            }
+        }
+        let Some((bids, asks)) = snapshot.try_scaled() else {
+            return self.mark_stale(IngestEvent::Corrupt {
+                error: "invalid price or amount in the snapshot".to_string(),
+            });
+        };
+        self.book.apply_snapshot(bids, asks);
+        if let Some(seq_no) = snapshot.seq_no {
+            self.expected_seq_no = seq_no.checked_add(1);
+        }
+        self.is_stale = false;
+        self.stats.snapshots += 1;
+        IngestEvent::Snapshot
+    }
+
+    pub fn apply_trade(&mut self, trade: &Trade) -> IngestEvent {
+        if self.is_stale {
+            self.stats.skipped += 1;
+            return IngestEvent::Skipped;
+        }
+        if let (Some(seq_no), Some(expected)) = (trade.seq_no, self.expected_seq_no) {
+            if seq_no < expected {
+                self.stats.duplicates += 1;
+                return IngestEvent::Duplicate { seq_no };
+            }
+            if seq_no > expected {
+                return self.mark_stale(IngestEvent::Gap { expected, received: seq_no });
+            }
+        }
+        let Some((price, amount)) = trade.try_scaled() else {
+            return self.mark_stale(IngestEvent::Corrupt {
+                error: format!("invalid price {} or amount {}", trade.price, trade.amount),
+            });
+        };
+        if let Some(seq_no) = trade.seq_no {
+            self.expected_seq_no = seq_no.checked_add(1);
+        }
+        self.book.set_quote(trade.side, price, amount);
+        self.stats.applied += 1;
+        IngestEvent::Applied
+    }
+
+    /// Replaces the whole book, messages up to `snapshot.seq_no` are treated as duplicates from now on
+    pub fn resync(&mut self, snapshot: &BookSnapshot) {
+        self.book.apply_snapshot(snapshot.bids.iter().copied(), snapshot.asks.iter().copied());
+        self.expected_seq_no = snapshot.seq_no.checked_add(1);
+        self.is_stale = false;
+        self.stats.resyncs += 1;
+    }
+
+    /// A lost tail of the feed can't be seen from the messages, compare this with the exchange heartbeat
+    pub fn expected_seq_no(&self) -> Option<u64> {
+        self.expected_seq_no
+    }
+    pub fn is_stale(&self) -> bool {
+        self.is_stale
+    }
+    pub fn book(&self) -> &AggregatedBook<SolutionAsk, SolutionBid> {
+        &self.book
+    }
+    pub fn stats(&self) -> &IngestStats {
+        &self.stats
+    }
+}
@@ -0,0 +1,229 @@
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use hdrhistogram::Histogram;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Instant;
+
+/// Latencies above a minute are clamped, 3 significant digits are kept
+const HIGHEST_LATENCY_NANOS: u64 = 60_000_000_000;
+const SIGNIFICANT_DIGITS: u8 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateKind {
+    Insert,
+    Increase,
+    Decrease,
+    Remove,
+    /// Same amount as before, or removal of an absent price
+    Unchanged,
+}
+
+impl UpdateKind {
+    pub fn classify(current_amount: Amount, new_amount: Amount) -> Self {
+        match (current_amount, new_amount) {
+            (current, new) if current == new => Self::Unchanged,
+            (0, _) => Self::Insert,
+            (_, 0) => Self::Remove,
+            (current, new) if new > current => Self::Increase,
+            _ => Self::Decrease,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Insert => "insert",
+            Self::Increase => "increase",
+            Self::Decrease => "decrease",
+            Self::Remove => "remove",
+            Self::Unchanged => "unchanged",
+        }
+    }
+}
+
+/// Cascade lengths are grouped by powers of two: 0, 1, 2-3, 4-7, ...
+pub fn cascade_bucket(cascade_length: usize) -> usize {
+    (usize::BITS - cascade_length.leading_zeros()) as usize
+}
+
+pub fn cascade_bucket_name(bucket: usize) -> String {
+    match bucket {
+        0 => "0".to_string(),
+        1 => "1".to_string(),
+        _ => format!("{}-{}", 1usize << (bucket - 1), (1usize << bucket) - 1),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub name: String,
+    pub count: u64,
+    pub min: u64,
+    pub mean: f64,
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub p999: u64,
+    pub max: u64,
+}
+
+impl LatencySummary {
+    fn new(name: String, histogram: &Histogram<u64>) -> Self {
+        Self {
+            name,
+            count: histogram.len(),
+            min: histogram.min(),
+            mean: histogram.mean(),
+            p50: histogram.value_at_quantile(0.5),
+            p90: histogram.value_at_quantile(0.9),
+            p99: histogram.value_at_quantile(0.99),
+            p999: histogram.value_at_quantile(0.999),
+            max: histogram.max(),
+        }
+    }
+}
+
+/// Latencies of `set_quote` in nanoseconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyReport {
+    pub all: LatencySummary,
+    pub by_kind: Vec<LatencySummary>,
+    /// Empty for implementations without cascade counters
+    pub by_cascade_length: Vec<LatencySummary>,
+}
+
+fn write_row(f: &mut fmt::Formatter<'_>, summary: &LatencySummary) -> fmt::Result {
+    writeln!(
+        f,
+        "{:<10} {:>10} {:>8} {:>10.1} {:>8} {:>8} {:>8} {:>8} {:>10}",
+        summary.name,
+        summary.count,
+        summary.min,
+        summary.mean,
+        summary.p50,
+        summary.p90,
+        summary.p99,
+        summary.p999,
+        summary.max
+    )
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<10} {:>10} {:>8} {:>10} {:>8} {:>8} {:>8} {:>8} {:>10}",
+            "ns", "count", "min", "mean", "p50", "p90", "p99", "p99.9", "max"
+        )?;
+        write_row(f, &self.all)?;
+        for summary in self.by_kind.iter() {
+            write_row(f, summary)?;
+        }
+        if !self.by_cascade_length.is_empty() {
+            writeln!(f, "by cascade length:")?;
+        }
+        for summary in self.by_cascade_length.iter() {
+            write_row(f, summary)?;
+        }
+        Ok(())
+    }
+}
+
+fn new_histogram() -> Histogram<u64> {
+    Histogram::new_with_bounds(1, HIGHEST_LATENCY_NANOS, SIGNIFICANT_DIGITS).unwrap()
+}
+
+pub struct LatencyRecorder {
+    all: Histogram<u64>,
+    by_kind: BTreeMap<UpdateKind, Histogram<u64>>,
+    by_cascade_bucket: BTreeMap<usize, Histogram<u64>>,
+}
+
+impl Default for LatencyRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyRecorder {
+    pub fn new() -> Self {
+        Self {
+            all: new_histogram(),
+            by_kind: BTreeMap::new(),
+            by_cascade_bucket: BTreeMap::new(),
+        }
+    }
+    pub fn record(&mut self, kind: UpdateKind, cascade_length: Option<usize>, nanos: u64) {
+        self.all.saturating_record(nanos);
+        self.by_kind
+            .entry(kind)
+            .or_insert_with(new_histogram)
+            .saturating_record(nanos);
+        if let Some(cascade_length) = cascade_length {
+            self.by_cascade_bucket
+                .entry(cascade_bucket(cascade_length))
+                .or_insert_with(new_histogram)
+                .saturating_record(nanos);
+        }
+    }
+    /// Times one `set_quote`, the price lookup for classification is not measured
+    pub fn set_quote<Price: OrderKey, Solution: AgregatedL2Trait<Price>>(
+        &mut self,
+        solution: &mut Solution,
+        price: u64,
+        amount: Amount,
+    ) {
+        let current_amount = solution.get_raw_amount(price).unwrap_or(0);
+        let start = Instant::now();
+        solution.set_quote(price, amount);
+        let nanos = start.elapsed().as_nanos() as u64;
+        self.record(
+            UpdateKind::classify(current_amount, amount),
+            solution.get_last_cascade_length(),
+            nanos,
+        );
+    }
+    pub fn report(&self) -> LatencyReport {
+        LatencyReport {
+            all: LatencySummary::new("all".to_string(), &self.all),
+            by_kind: self
+                .by_kind
+                .iter()
+                .map(|(kind, histogram)| LatencySummary::new(kind.name().to_string(), histogram))
+                .collect(),
+            by_cascade_length: self
+                .by_cascade_bucket
+                .iter()
+                .map(|(&bucket, histogram)| LatencySummary::new(cascade_bucket_name(bucket), histogram))
+                .collect(),
+        }
+    }
+}
+
+/// Replays the feed `replays` times into fresh ladders of both sides, recording every `set_quote`
+pub fn measure_latency<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>>(
+    trades: &[Trade],
+    rules: &BookRules,
+    replays: usize,
+) -> LatencyReport {
+    let mut recorder = LatencyRecorder::new();
+    for _ in 0..replays {
+        let mut ask = SolutionAsk::new(rules.ask.clone());
+        let mut bid = SolutionBid::new(rules.bid.clone());
+        for trade in trades.iter() {
+            // skipped like in `AggregatedBook::apply_trade`
+            let Some((price, amount)) = trade.try_scaled() else {
+                continue;
+            };
+            match trade.side {
+                Side::Ask => recorder.set_quote(&mut ask, price, amount),
+                Side::Bid => recorder.set_quote(&mut bid, price, amount),
+            }
+        }
+    }
+    recorder.report()
+}
@@ -1,7 +1,28 @@
 #![feature(btree_cursors)]
 #![feature(map_try_insert)]
 
+pub mod book;
+#[allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod common;
+pub mod conflation;
+pub mod csv_feed;
+pub mod cumulative;
+pub mod decimal;
+pub mod faults;
+pub mod feed;
+pub mod fuzzing;
+pub mod generator;
+pub mod golden;
+pub mod hot_reload;
+pub mod http;
+pub mod ingest;
+pub mod latency;
+#[allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod measure_time;
+pub mod metrics;
+pub mod replay;
+pub mod server;
 pub mod solutions;
+#[allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod subscription;
+pub mod websocket;
@@ -1,5 +1,84 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::generator::*;
+pub use market_data_aggregator::hot_reload::*;
 pub use market_data_aggregator::measure_time::*;
+pub use market_data_aggregator::replay::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::time::Duration;
+
+const USAGE: &str = "usage:
+    market_data_aggregator                          compare speed of the solutions on l2.json
+    market_data_aggregator watch <rules.json> [feed] aggregate feed (stdin by default), reloading rules on change
+    market_data_aggregator latency [--json <output>] per-update latency histograms on l2.json
+    market_data_aggregator generate [model.json]    print a synthetic feed, default market model without a file
+    market_data_aggregator convert <feed> <replay>  convert a feed in json lines to a binary replay file
+    market_data_aggregator replay <feed> <replay>   compare replay speed of the feed and its replay file";
+
+fn generate_feed(model_path: Option<&String>) -> Result<(), Box<dyn std::error::Error>> {
+    let model = match model_path {
+        Some(path) => serde_json::from_str(&std::fs::read_to_string(path)?)?,
+        None => MarketModel::default(),
+    };
+    let mut output = BufWriter::new(std::io::stdout().lock());
+    for trade in FeedGenerator::new(model)? {
+        writeln!(output, "{}", format_trade(&trade))?;
+    }
+    output.flush()?;
+    Ok(())
+}
+
+fn convert_feed(feed_path: &str, replay_path: &str) -> Result<(), ReplayError> {
+    let reader = BufReader::new(File::open(feed_path)?);
+    let writer = BufWriter::new(File::create(replay_path)?);
+    let records = convert_json_lines(reader, writer)?;
+    eprintln!("Converted {} records", records);
+    Ok(())
+}
 
 fn main() {
-    measure_time_for_both_solutions()
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => measure_time_for_both_solutions(),
+        Some("watch") if args.len() == 2 || args.len() == 3 => {
+            let result = run_hot_reload::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+                &args[1],
+                open_feed(args.get(2).map_or("-", String::as_str)).expect("Cannot open file"),
+                &mut std::io::stdout(),
+                Duration::from_millis(200),
+            );
+            if let Err(error) = result {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        Some("latency") if args.len() == 1 => measure_latency_for_both_solutions(None),
+        Some("latency") if args.len() == 3 && args[1] == "--json" => {
+            measure_latency_for_both_solutions(Some(&args[2]))
+        }
+        Some("generate") if args.len() <= 2 => {
+            if let Err(error) = generate_feed(args.get(1)) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        Some("convert") if args.len() == 3 => {
+            if let Err(error) = convert_feed(&args[1], &args[2]) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        Some("replay") if args.len() == 3 => {
+            if let Err(error) = measure_replay_time(&args[1], &args[2]) {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        }
+        Some(_) => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
 }
@@ -1,91 +1,127 @@
+use crate::book::*;
+use crate::feed::*;
+use crate::latency::*;
+use crate::replay::*;
 use crate::solutions::fast::*;
 use crate::solutions::slow_for_comparisons::*;
 
-use serde::{Deserialize, Serialize};
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "PascalCase")] // Ensure consistent casing if needed
-enum Side {
-    Bid,
-    Ask,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Trade {
-    platform_time: u64,
-    exchange_time: u64,
-    seq_no: Option<u64>, // Nullable sequence number
-    side: Side,          // "Bid" or "Ask"
-    price: f64,
-    amount: f64,
-    is_eot: bool,
-}
-
-fn is_integer(num: f64) -> bool {
-    (num.round() - num).abs() < 1e-5
-}
-
 use std::time::Instant;
 
+/// Replays of the fixture for latency histograms, the slow solution makes more too long
+const LATENCY_REPLAYS: usize = 200;
+
 fn measure_time<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>>(
-    arr: &Vec<Trade>,
-    subscription: &SubscriptionRules,
+    arr: &[Trade],
+    rules: &BookRules,
 ) {
-    let ratio: f64 = 1e8;
-
     let start = Instant::now();
     for _ in 0..40000 {
-        let mut solution_for_ask = SolutionAsk::new(subscription.clone());
-        let mut solution_for_bid = SolutionBid::new(subscription.clone());
+        let mut book = AggregatedBook::<SolutionAsk, SolutionBid>::new(rules.clone());
         for trade in arr.iter() {
-            let price = (trade.price * ratio).round() as u64;
-            let amount = (trade.amount * ratio).round() as u64;
-            assert!(is_integer(trade.price * ratio));
-            assert!(is_integer(trade.amount * ratio));
-
-            match trade.side {
-                Side::Bid => solution_for_bid.set_quote(price, amount),
-                Side::Ask => solution_for_ask.set_quote(price, amount),
-            }
-            if solution_for_bid.get_levels().is_empty() || solution_for_ask.get_levels().is_empty()
-            {
-                continue;
-            }
-            let ask = u64::from(*solution_for_ask.get_levels().first_key_value().unwrap().0);
-            let bid = u64::from(*solution_for_bid.get_levels().first_key_value().unwrap().0);
-            assert!(ask > bid);
+            book.apply_trade(trade);
+            assert!(!book.is_crossed());
         }
     }
     let duration = start.elapsed();
     println!("Time taken: {:.2?}", duration);
 }
 
-pub fn measure_time_for_both_solutions() {
-    let file = File::open("l2.json").expect("Cannot open file");
-    let reader = BufReader::new(file);
-
-    let subscription = SubscriptionRules::new(
+fn fixture_rules() -> BookRules {
+    BookRules::symmetric(SubscriptionRules::new(
         vec![5e13 as u64, 2e14 as u64, 3e13 as u64, 4e12 as u64],
         2e13 as u64,
         300,
-    );
+    ))
+}
 
-    let mut arr = Vec::<Trade>::new();
+pub fn measure_time_for_both_solutions() {
+    let rules = fixture_rules();
 
-    for line in reader.lines() {
-        let line = line.expect("Error reading line");
-        let trade: Trade = serde_json::from_str(&line).expect("Invalid JSON format");
-        arr.push(trade);
-    }
+    let arr = read_trades("l2.json");
 
     println!("Fast solution: ");
-    measure_time::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(&arr, &subscription);
+    measure_time::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(&arr, &rules);
 
     println!("\nSlow obvious solution:");
     measure_time::<SlowAggregatedL2ForComparisons<AskKey>, SlowAggregatedL2ForComparisons<BidKey>>(
         &arr,
-        &subscription,
+        &rules,
+    );
+}
+
+/// Prints per-update latency histograms and optionally saves them as JSON
+pub fn measure_latency_for_both_solutions(json_path: Option<&str>) {
+    let rules = fixture_rules();
+    let arr = read_trades("l2.json");
+
+    let fast = measure_latency::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(&arr, &rules, LATENCY_REPLAYS);
+    println!("Fast solution:\n{}", fast);
+    let slow = measure_latency::<SlowAggregatedL2ForComparisons<AskKey>, SlowAggregatedL2ForComparisons<BidKey>>(
+        &arr,
+        &rules,
+        LATENCY_REPLAYS,
     );
+    println!("Slow obvious solution:\n{}", slow);
+
+    if let Some(json_path) = json_path {
+        #[derive(serde::Serialize)]
+        struct BothReports {
+            fast: LatencyReport,
+            slow: LatencyReport,
+        }
+        let json = serde_json::to_string_pretty(&BothReports { fast, slow }).unwrap();
+        std::fs::write(json_path, json).expect("Cannot write file");
+    }
+}
+
+/// Replays of a recorded feed when comparing json lines with the binary replay
+const REPLAY_ROUNDS: usize = 100;
+
+type FastBook = AggregatedBook<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+
+fn replay_json_lines(text: &str, rules: &BookRules) -> Result<FastBook, ReplayError> {
+    let mut book = AggregatedBook::new(rules.clone());
+    for (index, line) in text.lines().enumerate() {
+        let trade = parse_trade(line).map_err(|error| ReplayError::InvalidLine {
+            line: index + 1,
+            error: error.to_string(),
+        })?;
+        book.apply_trade(&trade);
+    }
+    Ok(book)
+}
+
+fn replay_file(replay: &MappedReplay, rules: &BookRules) -> FastBook {
+    let mut book = AggregatedBook::new(rules.clone());
+    replay.view().apply_to(&mut book);
+    book
+}
+
+/// Time of replaying a feed from json lines, parsing included, against the same feed converted to a replay file
+pub fn measure_replay_time(json_path: &str, replay_path: &str) -> Result<(), ReplayError> {
+    let rules = fixture_rules();
+    let text = std::fs::read_to_string(json_path)?;
+    let replay = MappedReplay::open(replay_path)?;
+
+    let start = Instant::now();
+    for _ in 0..REPLAY_ROUNDS {
+        std::hint::black_box(replay_json_lines(&text, &rules)?);
+    }
+    println!("Json lines: {:.2?}", start.elapsed());
+
+    let start = Instant::now();
+    for _ in 0..REPLAY_ROUNDS {
+        std::hint::black_box(replay_file(&replay, &rules));
+    }
+    println!("Replay file: {:.2?}", start.elapsed());
+
+    let (json_book, replay_book) = (replay_json_lines(&text, &rules)?, replay_file(&replay, &rules));
+    for side in [Side::Bid, Side::Ask] {
+        assert_eq!(
+            json_book.get_aggregated_levels_tuples(side),
+            replay_book.get_aggregated_levels_tuples(side),
+            "the replay file is not a conversion of the feed"
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,237 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::ingest::*;
+use crate::solutions::aggregated_l2_trait::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Upper bounds of the cascade length histogram buckets, `+Inf` is added on rendering
+pub const CASCADE_BUCKETS: [usize; 8] = [0, 1, 2, 4, 8, 16, 32, 64];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Not cumulative, the last one is for values above every bound
+    counts: [u64; CASCADE_BUCKETS.len() + 1],
+    sum: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: usize) {
+        let bucket = CASCADE_BUCKETS.partition_point(|&bound| bound < value);
+        self.counts[bucket] += 1;
+        self.sum += value as u64;
+    }
+    fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct SideMetrics {
+    updates: u64,
+    surplus_cascades: Histogram,
+    shortage_cascades: Histogram,
+    rebuild_cascades: Histogram,
+    raw_levels: usize,
+    aggregated_levels: usize,
+}
+
+impl SideMetrics {
+    fn observe<Price: OrderKey>(&mut self, solution: &impl AgregatedL2Trait<Price>) {
+        self.updates += 1;
+        if let Some(cascade) = solution.get_last_cascade() {
+            self.surplus_cascades.observe(cascade.surplus);
+            self.shortage_cascades.observe(cascade.shortage);
+            self.rebuild_cascades.observe(cascade.rebuild);
+        }
+        self.observe_levels(solution);
+    }
+    fn observe_levels<Price: OrderKey>(&mut self, solution: &impl AgregatedL2Trait<Price>) {
+        self.raw_levels = solution.raw_levels_count();
+        self.aggregated_levels = solution.get_aggregated_levels().len();
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct InstrumentMetrics {
+    bid: SideMetrics,
+    ask: SideMetrics,
+    seq_gaps: u64,
+    crossed_book_events: u64,
+    is_crossed: bool,
+}
+
+impl InstrumentMetrics {
+    fn sides(&self) -> [(Side, &SideMetrics); 2] {
+        [(Side::Bid, &self.bid), (Side::Ask, &self.ask)]
+    }
+}
+
+/// Counters and gauges of the whole pipeline, rendered in the Prometheus text format
+#[derive(Debug, Clone, Default)]
+pub struct PipelineMetrics {
+    instruments: BTreeMap<String, InstrumentMetrics>,
+}
+
+impl PipelineMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn instrument(&mut self, instrument: &str) -> &mut InstrumentMetrics {
+        if !self.instruments.contains_key(instrument) {
+            self.instruments.insert(instrument.to_string(), InstrumentMetrics::default());
+        }
+        self.instruments.get_mut(instrument).unwrap()
+    }
+
+    /// Call right after `set_quote` on `side` of the book, the cascade is read from the solution
+    pub fn observe_set_quote<SolutionAsk, SolutionBid>(
+        &mut self,
+        instrument: &str,
+        book: &AggregatedBook<SolutionAsk, SolutionBid>,
+        side: Side,
+    ) where
+        SolutionAsk: AgregatedL2Trait<AskKey>,
+        SolutionBid: AgregatedL2Trait<BidKey>,
+    {
+        let metrics = self.instrument(instrument);
+        match side {
+            Side::Bid => metrics.bid.observe(book.bid()),
+            Side::Ask => metrics.ask.observe(book.ask()),
+        }
+    }
+
+    /// A snapshot is not an update, only the level gauges of both sides change
+    pub fn observe_snapshot<SolutionAsk, SolutionBid>(
+        &mut self,
+        instrument: &str,
+        book: &AggregatedBook<SolutionAsk, SolutionBid>,
+    ) where
+        SolutionAsk: AgregatedL2Trait<AskKey>,
+        SolutionBid: AgregatedL2Trait<BidKey>,
+    {
+        let metrics = self.instrument(instrument);
+        metrics.bid.observe_levels(book.bid());
+        metrics.ask.observe_levels(book.ask());
+    }
+
+    /// A book may cross in the middle of a transaction, so crossing is only checked between them.
+    /// Counts transitions into the crossed state, not transactions spent in it
+    pub fn observe_end_of_transaction<SolutionAsk, SolutionBid>(
+        &mut self,
+        instrument: &str,
+        book: &AggregatedBook<SolutionAsk, SolutionBid>,
+    ) where
+        SolutionAsk: AgregatedL2Trait<AskKey>,
+        SolutionBid: AgregatedL2Trait<BidKey>,
+    {
+        let metrics = self.instrument(instrument);
+        let is_crossed = book.is_crossed();
+        if is_crossed && !metrics.is_crossed {
+            metrics.crossed_book_events += 1;
+        }
+        metrics.is_crossed = is_crossed;
+    }
+
+    pub fn observe_ingest_event(&mut self, instrument: &str, event: &IngestEvent) {
+        if let IngestEvent::Gap { .. } = event {
+            self.instrument(instrument).seq_gaps += 1;
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut result = String::new();
+        write_header(&mut result, "market_data_updates_total", "counter", "Quotes applied to the book");
+        for (instrument, metrics) in self.instruments.iter() {
+            for (side, side_metrics) in metrics.sides() {
+                let labels = format!("instrument=\"{}\",side=\"{:?}\"", escape(instrument), side);
+                writeln!(result, "market_data_updates_total{{{}}} {}", labels, side_metrics.updates).unwrap();
+            }
+        }
+
+        write_header(
+            &mut result,
+            "market_data_cascade_length",
+            "histogram",
+            "Raw quotes moved between aggregated levels by one update",
+        );
+        for (instrument, metrics) in self.instruments.iter() {
+            for (side, side_metrics) in metrics.sides() {
+                let cascades = [
+                    ("surplus", &side_metrics.surplus_cascades),
+                    ("shortage", &side_metrics.shortage_cascades),
+                    ("rebuild", &side_metrics.rebuild_cascades),
+                ];
+                for (direction, histogram) in cascades {
+                    let labels = format!(
+                        "instrument=\"{}\",side=\"{:?}\",direction=\"{}\"",
+                        escape(instrument),
+                        side,
+                        direction
+                    );
+                    write_histogram(&mut result, "market_data_cascade_length", &labels, histogram);
+                }
+            }
+        }
+
+        write_header(&mut result, "market_data_raw_levels", "gauge", "Raw price levels in the book");
+        for (instrument, metrics) in self.instruments.iter() {
+            for (side, side_metrics) in metrics.sides() {
+                let labels = format!("instrument=\"{}\",side=\"{:?}\"", escape(instrument), side);
+                writeln!(result, "market_data_raw_levels{{{}}} {}", labels, side_metrics.raw_levels).unwrap();
+            }
+        }
+
+        write_header(&mut result, "market_data_aggregated_levels", "gauge", "Levels in the aggregated ladder");
+        for (instrument, metrics) in self.instruments.iter() {
+            for (side, side_metrics) in metrics.sides() {
+                let labels = format!("instrument=\"{}\",side=\"{:?}\"", escape(instrument), side);
+                writeln!(result, "market_data_aggregated_levels{{{}}} {}", labels, side_metrics.aggregated_levels)
+                    .unwrap();
+            }
+        }
+
+        write_header(&mut result, "market_data_seq_gaps_total", "counter", "Gaps in feed sequence numbers");
+        for (instrument, metrics) in self.instruments.iter() {
+            let labels = format!("instrument=\"{}\"", escape(instrument));
+            writeln!(result, "market_data_seq_gaps_total{{{}}} {}", labels, metrics.seq_gaps).unwrap();
+        }
+
+        write_header(
+            &mut result,
+            "market_data_crossed_book_events_total",
+            "counter",
+            "Times the best bid reached the best ask after a transaction",
+        );
+        for (instrument, metrics) in self.instruments.iter() {
+            let labels = format!("instrument=\"{}\"", escape(instrument));
+            writeln!(result, "market_data_crossed_book_events_total{{{}}} {}", labels, metrics.crossed_book_events)
+                .unwrap();
+        }
+        result
+    }
+}
+
+/// Label values may contain anything, the text format needs these three escaped
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn write_header(result: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(result, "# HELP {} {}", name, help).unwrap();
+    writeln!(result, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn write_histogram(result: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let mut cumulative = 0;
+    for (bound, count) in CASCADE_BUCKETS.iter().zip(histogram.counts.iter()) {
+        cumulative += count;
+        writeln!(result, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative).unwrap();
+    }
+    writeln!(result, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count()).unwrap();
+    writeln!(result, "{}_sum{{{}}} {}", name, labels, histogram.sum).unwrap();
+    writeln!(result, "{}_count{{{}}} {}", name, labels, histogram.count()).unwrap();
+}
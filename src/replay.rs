@@ -0,0 +1,273 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+pub const MAGIC: [u8; 8] = *b"L2REPLAY";
+pub const VERSION: u32 = 1;
+/// Magic, version and record size
+pub const HEADER_SIZE: usize = 16;
+/// Five little endian u64 fields, one byte of flags and padding to keep records 8 byte aligned
+pub const RECORD_SIZE: usize = 48;
+
+const FLAG_ASK: u8 = 1;
+const FLAG_EOT: u8 = 2;
+const FLAG_HAS_SEQ_NO: u8 = 4;
+const KNOWN_FLAGS: u8 = FLAG_ASK | FLAG_EOT | FLAG_HAS_SEQ_NO;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    BadMagic,
+    UnsupportedVersion { version: u32 },
+    BadRecordSize { record_size: u32 },
+    /// The body is not a whole number of records
+    Truncated { length: usize },
+    UnknownFlags { index: usize, flags: u8 },
+    /// Not a price, for bids it would also collide with `BidKey::MAX`
+    ZeroPrice { index: usize },
+    /// Lines are counted from 1
+    InvalidLine { line: usize, error: String },
+    /// Records are single levels, a feed snapshot has no place in the format
+    SnapshotLine { line: usize },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "{}", error),
+            Self::BadMagic => write!(f, "not a replay file"),
+            Self::UnsupportedVersion { version } => write!(f, "replay version {} is not supported", version),
+            Self::BadRecordSize { record_size } => write!(f, "records of {} bytes are not supported", record_size),
+            Self::Truncated { length } => write!(f, "{} bytes are not a whole number of records", length),
+            Self::UnknownFlags { index, flags } => write!(f, "record {} has unknown flags {:#04x}", index, flags),
+            Self::ZeroPrice { index } => write!(f, "record {} has price 0", index),
+            Self::InvalidLine { line, error } => write!(f, "line {}: {}", line, error),
+            Self::SnapshotLine { line } => write!(f, "line {}: snapshots can't be stored in a replay file", line),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        Self::Io(error)
+    }
+}
+
+/// One feed message with scaled price and amount, as stored in the replay file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayRecord {
+    pub platform_time: u64,
+    pub exchange_time: u64,
+    pub seq_no: Option<u64>,
+    pub side: Side,
+    pub price: u64,
+    pub amount: Amount,
+    pub is_eot: bool,
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+impl ReplayRecord {
+    /// `None` if the price or the amount can't be scaled, see `Trade::try_scaled`
+    pub fn from_trade(trade: &Trade) -> Option<Self> {
+        let (price, amount) = trade.try_scaled()?;
+        Some(Self {
+            platform_time: trade.platform_time,
+            exchange_time: trade.exchange_time,
+            seq_no: trade.seq_no,
+            side: trade.side,
+            price,
+            amount,
+            is_eot: trade.is_eot,
+        })
+    }
+
+    pub fn to_trade(&self) -> Trade {
+        Trade {
+            platform_time: self.platform_time,
+            exchange_time: self.exchange_time,
+            seq_no: self.seq_no,
+            side: self.side,
+            price: unscaled(self.price),
+            amount: unscaled(self.amount),
+            is_eot: self.is_eot,
+        }
+    }
+
+    pub fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        let fields = [
+            self.platform_time,
+            self.exchange_time,
+            self.seq_no.unwrap_or(0),
+            self.price,
+            self.amount,
+        ];
+        for (index, field) in fields.iter().enumerate() {
+            bytes[index * 8..index * 8 + 8].copy_from_slice(&field.to_le_bytes());
+        }
+        let mut flags = 0;
+        if self.side == Side::Ask {
+            flags |= FLAG_ASK;
+        }
+        if self.is_eot {
+            flags |= FLAG_EOT;
+        }
+        if self.seq_no.is_some() {
+            flags |= FLAG_HAS_SEQ_NO;
+        }
+        bytes[40] = flags;
+        bytes
+    }
+
+    /// `bytes` must be a whole record with known flags, `ReplayView::new` checks every record once
+    fn decode(bytes: &[u8]) -> Self {
+        let flags = bytes[40];
+        Self {
+            platform_time: read_u64(bytes, 0),
+            exchange_time: read_u64(bytes, 8),
+            seq_no: (flags & FLAG_HAS_SEQ_NO != 0).then(|| read_u64(bytes, 16)),
+            side: if flags & FLAG_ASK != 0 { Side::Ask } else { Side::Bid },
+            price: read_u64(bytes, 24),
+            amount: read_u64(bytes, 32),
+            is_eot: flags & FLAG_EOT != 0,
+        }
+    }
+}
+
+pub struct ReplayWriter<W: Write> {
+    writer: W,
+    records: u64,
+}
+
+impl<W: Write> ReplayWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&(RECORD_SIZE as u32).to_le_bytes())?;
+        Ok(Self { writer, records: 0 })
+    }
+    pub fn write(&mut self, record: &ReplayRecord) -> io::Result<()> {
+        self.records += 1;
+        self.writer.write_all(&record.encode())
+    }
+    /// Flushes and returns the number of written records
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.records)
+    }
+}
+
+/// Converts a feed in json lines, stops at the first line that can't be stored, snapshot lines included
+pub fn convert_json_lines(reader: impl BufRead, writer: impl Write) -> Result<u64, ReplayError> {
+    let mut replay = ReplayWriter::new(writer)?;
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let invalid_line = |error: String| ReplayError::InvalidLine { line: index + 1, error };
+        let trade = match parse_feed_message(&line).map_err(|error| invalid_line(error.to_string()))? {
+            FeedMessage::Delta(trade) => trade,
+            FeedMessage::Snapshot(_) => return Err(ReplayError::SnapshotLine { line: index + 1 }),
+        };
+        let record = ReplayRecord::from_trade(&trade)
+            .ok_or_else(|| invalid_line(format!("invalid price {} or amount {}", trade.price, trade.amount)))?;
+        replay.write(&record)?;
+    }
+    Ok(replay.finish()?)
+}
+
+/// Checked replay bytes, records are decoded on the fly without allocating
+#[derive(Debug, Clone, Copy)]
+pub struct ReplayView<'a> {
+    records: &'a [u8],
+}
+
+impl<'a> ReplayView<'a> {
+    /// Checks the header, the flags and the price of every record, so iteration can't fail later
+    /// and every record is a valid `set_quote`
+    pub fn new(bytes: &'a [u8]) -> Result<Self, ReplayError> {
+        if bytes.len() < HEADER_SIZE || bytes[..8] != MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+        let version = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+        if version != VERSION {
+            return Err(ReplayError::UnsupportedVersion { version });
+        }
+        let record_size = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+        if record_size as usize != RECORD_SIZE {
+            return Err(ReplayError::BadRecordSize { record_size });
+        }
+        let records = &bytes[HEADER_SIZE..];
+        if !records.len().is_multiple_of(RECORD_SIZE) {
+            return Err(ReplayError::Truncated { length: records.len() });
+        }
+        for (index, record) in records.chunks_exact(RECORD_SIZE).enumerate() {
+            let flags = record[40];
+            if flags & !KNOWN_FLAGS != 0 {
+                return Err(ReplayError::UnknownFlags { index, flags });
+            }
+            if read_u64(record, 24) == 0 {
+                return Err(ReplayError::ZeroPrice { index });
+            }
+        }
+        Ok(Self { records })
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len() / RECORD_SIZE
+    }
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+    pub fn get(&self, index: usize) -> Option<ReplayRecord> {
+        let start = index.checked_mul(RECORD_SIZE)?;
+        let bytes = self.records.get(start..start + RECORD_SIZE)?;
+        Some(ReplayRecord::decode(bytes))
+    }
+    pub fn records(
+        &self,
+    ) -> impl ExactSizeIterator<Item = ReplayRecord> + DoubleEndedIterator + Clone + 'a {
+        self.records.chunks_exact(RECORD_SIZE).map(ReplayRecord::decode)
+    }
+
+    /// Applies every record, the same as `apply_trade` for each line of the original feed:
+    /// records are scaled already and `new` has rejected price 0
+    pub fn apply_to<SolutionAsk, SolutionBid>(&self, book: &mut AggregatedBook<SolutionAsk, SolutionBid>)
+    where
+        SolutionAsk: AgregatedL2Trait<AskKey>,
+        SolutionBid: AgregatedL2Trait<BidKey>,
+    {
+        for record in self.records() {
+            book.set_quote(record.side, record.price, record.amount);
+        }
+    }
+}
+
+/// Replay file mapped into memory, the OS pages it in as records are read
+pub struct MappedReplay {
+    map: memmap2::Mmap,
+}
+
+impl MappedReplay {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        let file = File::open(path)?;
+        // SAFETY: the file must not be truncated or modified while mapped, replay files are written once
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        ReplayView::new(&map)?;
+        Ok(Self { map })
+    }
+    pub fn view(&self) -> ReplayView<'_> {
+        ReplayView {
+            records: &self.map[HEADER_SIZE..],
+        }
+    }
+}
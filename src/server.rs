@@ -0,0 +1,376 @@
+use crate::book::*;
+use crate::common::*;
+use crate::feed::*;
+use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::subscription::*;
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::net::Shutdown;
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Frames above this are rejected by `read_frame`, a ladder is never that large
+pub const MAX_FRAME_SIZE: usize = 64 << 20;
+
+/// Frames waiting for a client's writer, a client that falls this far behind is disconnected
+/// and has to reconnect for a fresh snapshot
+pub const CLIENT_QUEUE_FRAMES: usize = 1024;
+
+/// Every frame is a big endian u32 length followed by one message in json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Both aggregated ladders, sent first to every client
+    Snapshot {
+        seq: u64,
+        bids: Vec<(u64, Amount)>,
+        asks: Vec<(u64, Amount)>,
+    },
+    /// Levels of `side` from `from_index` on are replaced by `levels`
+    Update {
+        seq: u64,
+        side: Side,
+        from_index: usize,
+        levels: Vec<(u64, Amount)>,
+    },
+    EndOfFeed { seq: u64 },
+}
+
+pub fn encode_frame(message: &ServerMessage) -> Vec<u8> {
+    let payload = serde_json::to_vec(message).expect("message is always serializable");
+    let mut frame = Vec::with_capacity(4 + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+pub fn write_frame(writer: &mut impl Write, message: &ServerMessage) -> io::Result<()> {
+    writer.write_all(&encode_frame(message))?;
+    writer.flush()
+}
+
+/// `None` when the stream ends between frames
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Option<ServerMessage>> {
+    let mut header = [0; 4];
+    match reader.read_exact(&mut header) {
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        result => result?,
+    }
+    let length = u32::from_be_bytes(header) as usize;
+    if length > MAX_FRAME_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("frame of {} bytes is too large", length),
+        ));
+    }
+    let mut payload = vec![0; length];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(serde_json::from_slice(&payload)?))
+}
+
+/// Shortest tail replacement turning `old` into `new`, `None` if they are equal
+pub fn ladder_update(old: &[(u64, Amount)], new: &[(u64, Amount)]) -> Option<(usize, Vec<(u64, Amount)>)> {
+    let common = old.iter().zip(new.iter()).take_while(|(old, new)| old == new).count();
+    if common == old.len() && common == new.len() {
+        return None;
+    }
+    Some((common, new[common..].to_vec()))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplicaError {
+    UpdateBeforeSnapshot,
+    SeqGap { expected: u64, received: u64 },
+    IndexOutOfRange { from_index: usize, length: usize },
+}
+
+impl fmt::Display for ReplicaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UpdateBeforeSnapshot => write!(f, "update received before a snapshot"),
+            Self::SeqGap { expected, received } => {
+                write!(f, "expected message {}, received {}", expected, received)
+            }
+            Self::IndexOutOfRange { from_index, length } => {
+                write!(f, "update from level {} of a ladder with {} levels", from_index, length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReplicaError {}
+
+/// Client side copy of both ladders, built from a snapshot and the following updates
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookReplica {
+    pub seq: Option<u64>,
+    pub bids: Vec<(u64, Amount)>,
+    pub asks: Vec<(u64, Amount)>,
+    pub is_finished: bool,
+}
+
+impl BookReplica {
+    fn check_seq(&self, seq: u64) -> Result<(), ReplicaError> {
+        let expected = self.seq.ok_or(ReplicaError::UpdateBeforeSnapshot)? + 1;
+        if seq != expected {
+            return Err(ReplicaError::SeqGap { expected, received: seq });
+        }
+        Ok(())
+    }
+
+    /// A rejected message leaves the replica unchanged
+    pub fn apply(&mut self, message: ServerMessage) -> Result<(), ReplicaError> {
+        match message {
+            ServerMessage::Snapshot { seq, bids, asks } => {
+                self.seq = Some(seq);
+                self.bids = bids;
+                self.asks = asks;
+            }
+            ServerMessage::Update { seq, side, from_index, levels } => {
+                self.check_seq(seq)?;
+                let ladder = match side {
+                    Side::Bid => &mut self.bids,
+                    Side::Ask => &mut self.asks,
+                };
+                if from_index > ladder.len() {
+                    return Err(ReplicaError::IndexOutOfRange { from_index, length: ladder.len() });
+                }
+                ladder.truncate(from_index);
+                ladder.extend(levels);
+                self.seq = Some(seq);
+            }
+            ServerMessage::EndOfFeed { seq } => {
+                self.check_seq(seq)?;
+                self.seq = Some(seq);
+                self.is_finished = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Ladders the clients have already seen, turns new ladders into updates
+#[derive(Debug, Default)]
+pub struct LadderPublisher {
+    published_bids: Vec<(u64, Amount)>,
+    published_asks: Vec<(u64, Amount)>,
+    seq: u64,
+}
+
+impl LadderPublisher {
+    /// Ladders as of the last `publish`, what a new client has to start from
+    pub fn snapshot(&self) -> ServerMessage {
+        ServerMessage::Snapshot {
+            seq: self.seq,
+            bids: self.published_bids.clone(),
+            asks: self.published_asks.clone(),
+        }
+    }
+    /// Update of the side since the previous call, `None` if its ladder hasn't changed
+    pub fn publish(&mut self, side: Side, ladder: Vec<(u64, Amount)>) -> Option<ServerMessage> {
+        let published = match side {
+            Side::Bid => &mut self.published_bids,
+            Side::Ask => &mut self.published_asks,
+        };
+        let (from_index, levels) = ladder_update(published, &ladder)?;
+        self.seq += 1;
+        *published = ladder;
+        Some(ServerMessage::Update {
+            seq: self.seq,
+            side,
+            from_index,
+            levels,
+        })
+    }
+    pub fn end_of_feed(&mut self) -> ServerMessage {
+        self.seq += 1;
+        ServerMessage::EndOfFeed { seq: self.seq }
+    }
+}
+
+/// Book plus the ladders the clients have already seen, turns transactions into updates
+pub struct BookPublisher<SolutionAsk, SolutionBid> {
+    book: AggregatedBook<SolutionAsk, SolutionBid>,
+    ladders: LadderPublisher,
+}
+
+impl<SolutionAsk: AgregatedL2Trait<AskKey>, SolutionBid: AgregatedL2Trait<BidKey>> BookPublisher<SolutionAsk, SolutionBid> {
+    pub fn new(rules: BookRules) -> Self {
+        Self {
+            book: AggregatedBook::new(rules),
+            ladders: LadderPublisher::default(),
+        }
+    }
+    pub fn set_quote(&mut self, side: Side, price: u64, amount: Amount) {
+        self.book.set_quote(side, price, amount);
+    }
+    pub fn replace_side(&mut self, side: Side, levels: impl IntoIterator<Item = (u64, Amount)>) {
+        self.book.replace_side(side, levels);
+    }
+    pub fn apply_trade(&mut self, trade: &Trade) -> bool {
+        self.book.apply_trade(trade)
+    }
+    pub fn apply_feed_snapshot(&mut self, snapshot: &FeedSnapshot) -> bool {
+        self.book.apply_feed_snapshot(snapshot)
+    }
+    pub fn book(&self) -> &AggregatedBook<SolutionAsk, SolutionBid> {
+        &self.book
+    }
+    /// Ladders as of the last `take_updates`, what a new client has to start from
+    pub fn snapshot(&self) -> ServerMessage {
+        self.ladders.snapshot()
+    }
+    /// Changes of both sides since the previous call
+    pub fn take_updates(&mut self) -> Vec<ServerMessage> {
+        [Side::Bid, Side::Ask]
+            .into_iter()
+            .filter_map(|side| self.ladders.publish(side, self.book.get_aggregated_levels_tuples(side)))
+            .collect()
+    }
+    pub fn end_of_feed(&mut self) -> ServerMessage {
+        self.ladders.end_of_feed()
+    }
+}
+
+/// Frames are written by a thread per client, so a slow client doesn't stop the feed
+struct Client {
+    frames: SyncSender<Arc<Vec<u8>>>,
+    stream: TcpStream,
+    writer: JoinHandle<()>,
+}
+
+fn start_client(mut stream: TcpStream, snapshot: Arc<Vec<u8>>) -> io::Result<Client> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    let client_stream = stream.try_clone()?;
+    let (frames, receiver) = mpsc::sync_channel::<Arc<Vec<u8>>>(CLIENT_QUEUE_FRAMES);
+    frames.send(snapshot).expect("receiver is alive");
+    let writer = std::thread::spawn(move || {
+        for frame in receiver {
+            if stream.write_all(&frame).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(Client {
+        frames,
+        stream: client_stream,
+        writer,
+    })
+}
+
+fn broadcast(clients: &mut Vec<Client>, message: &ServerMessage) {
+    let frame = Arc::new(encode_frame(message));
+    clients.retain(|client| match client.frames.try_send(frame.clone()) {
+        Ok(()) => true,
+        // the client can't keep up, closing the connection stops its writer without draining the queue
+        Err(TrySendError::Full(_)) => {
+            eprintln!("Disconnecting a client {} frames behind", CLIENT_QUEUE_FRAMES);
+            let _ = client.stream.shutdown(Shutdown::Both);
+            false
+        }
+        // the writer has stopped on a closed connection
+        Err(TrySendError::Disconnected(_)) => false,
+    });
+}
+
+/// Serves the book aggregated from `feed` until the feed ends. Updates are published after every transaction,
+/// clients accepted in between get a snapshot first. Every client that keeps up gets `EndOfFeed` before its connection
+/// is closed, lines with invalid values are skipped. A snapshot line replaces the book and is a transaction of its own.
+/// If reading the feed fails, connections are closed after the last finished transaction without `EndOfFeed`
+/// and the error is returned
+pub fn run_tcp_server<SolutionAsk, SolutionBid>(
+    listener: TcpListener,
+    feed: impl BufRead + Send + 'static,
+    rules: BookRules,
+    poll_interval: Duration,
+) -> io::Result<()>
+where
+    SolutionAsk: AgregatedL2Trait<AskKey>,
+    SolutionBid: AgregatedL2Trait<BidKey>,
+{
+    listener.set_nonblocking(true)?;
+    let mut publisher = BookPublisher::<SolutionAsk, SolutionBid>::new(rules);
+    let mut clients = Vec::new();
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for line in feed.lines() {
+            let is_error = line.is_err();
+            if sender.send(line).is_err() || is_error {
+                break;
+            }
+        }
+    });
+
+    let mut feed_error = None;
+
+    loop {
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) => {
+                    let snapshot = Arc::new(encode_frame(&publisher.snapshot()));
+                    match start_client(stream, snapshot) {
+                        Ok(client) => clients.push(client),
+                        Err(error) => eprintln!("Cannot start client: {}", error),
+                    }
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            }
+        }
+        match receiver.recv_timeout(poll_interval) {
+            Ok(Err(error)) => {
+                feed_error = Some(error);
+                break;
+            }
+            Ok(Ok(line)) => {
+                let message = match parse_feed_message(&line) {
+                    Ok(message) => message,
+                    Err(error) => {
+                        eprintln!("Skipping invalid line {:?}: {}", line, error);
+                        continue;
+                    }
+                };
+                let is_applied = match &message {
+                    FeedMessage::Delta(trade) => publisher.apply_trade(trade),
+                    FeedMessage::Snapshot(snapshot) => publisher.apply_feed_snapshot(snapshot),
+                };
+                if !is_applied {
+                    eprintln!("Skipping line with invalid values {:?}", line);
+                }
+                // the transaction still ends on a skipped line
+                if !message.is_eot() {
+                    continue;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        for update in publisher.take_updates() {
+            broadcast(&mut clients, &update);
+        }
+    }
+
+    // a truncated feed is not a finished one, clients keep the last finished transaction and see no `EndOfFeed`
+    if feed_error.is_none() {
+        for update in publisher.take_updates() {
+            broadcast(&mut clients, &update);
+        }
+        let end_of_feed = publisher.end_of_feed();
+        broadcast(&mut clients, &end_of_feed);
+    }
+    for client in clients {
+        drop(client.frames);
+        let _ = client.writer.join();
+    }
+    match feed_error {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
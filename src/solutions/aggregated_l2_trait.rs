@@ -1,12 +1,256 @@
 use crate::common::*;
+use crate::solutions::invariants::InvariantReport;
 use crate::subscription::*;
 
 use std::collections::BTreeMap;
+use std::ops::Bound;
 
 pub trait AgregatedL2Trait<Price: OrderKey> {
     fn new(subscription: SubscriptionRules) -> Self;
+    /// Ladder built from levels already sorted from the touch, zero amounts are skipped.
+    /// Levels out of book order are loaded through `replace_levels` instead, prices still have to be distinct
+    fn from_sorted_levels(subscription: SubscriptionRules, levels: impl IntoIterator<Item = (u64, Amount)>) -> Self
+    where
+        Self: Sized,
+    {
+        let mut solution = Self::new(subscription);
+        solution.replace_levels(levels);
+        solution
+    }
     fn set_quote(&mut self, price_: u64, new_amount: Amount);
-    fn get_levels(&self) -> &BTreeMap<Price, Amount>;
-    fn get_aggregated_levels(&self) -> &Vec<AggregatedLevel<Price>>;
-    fn get_aggregated_levels_tuples(&self) -> Vec<(u64, u64)>;
+    /// Replaces all raw levels at once and recalculates aggregated levels, for full book snapshots.
+    /// Levels may come in any order, prices must be distinct and zero amounts are skipped
+    fn replace_levels(&mut self, levels: impl IntoIterator<Item = (u64, Amount)>);
+    /// Replaces the rules and recalculates aggregated levels from the current raw levels
+    fn set_subscription_rules(&mut self, subscription: SubscriptionRules);
+    fn get_subscription_rules(&self) -> &SubscriptionRules;
+    /// Raw levels within the bounds as `(price, amount)`, from the touch.
+    /// Bounds follow the book order and, like in `BTreeMap::range`, must not be reversed
+    fn raw_levels_range(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> impl DoubleEndedIterator<Item = (u64, Amount)> + Clone + '_;
+    fn get_raw_amount(&self, price: u64) -> Option<Amount>;
+    fn raw_levels_count(&self) -> usize;
+    fn get_aggregated_levels(&self) -> &[AggregatedLevel<Price>];
+    /// Verifies the whole internal state, for tests and fuzzing
+    fn check_invariants(&self) -> Result<(), InvariantReport>;
+    /// Raw quotes the last `set_quote` moved between aggregated levels, by the place that moved them,
+    /// `None` if the implementation doesn't count them
+    fn get_last_cascade(&self) -> Option<CascadeLengths> {
+        None
+    }
+    /// How many raw quotes the last `set_quote` moved between aggregated levels
+    fn get_last_cascade_length(&self) -> Option<usize> {
+        self.get_last_cascade().map(|cascade| cascade.total())
+    }
+
+    /// All raw levels as `(price, amount)`, from the touch
+    fn raw_levels(&self) -> impl DoubleEndedIterator<Item = (u64, Amount)> + Clone + '_ {
+        self.raw_levels_range((Bound::Unbounded, Bound::Unbounded))
+    }
+    fn best_price(&self) -> Option<u64> {
+        self.raw_levels().next().map(|(price, _)| price)
+    }
+    /// Aggregated levels as `(last_price, total_amount)` without copying them
+    fn aggregated_levels(&self) -> impl ExactSizeIterator<Item = (u64, Amount)> + DoubleEndedIterator + Clone + '_ {
+        self.get_aggregated_levels()
+            .iter()
+            .map(|level| (level.last_price.into(), level.total_amount))
+    }
+    fn get_aggregated_levels_tuples(&self) -> Vec<(u64, u64)> {
+        self.aggregated_levels().collect()
+    }
+    /// Aggregated levels with amounts accumulated from the touch
+    fn get_cumulative_aggregated_levels(&self) -> Vec<(u64, u64)> {
+        let mut cumulative_amount = 0;
+        self.get_aggregated_levels()
+            .iter()
+            .map(|level| {
+                cumulative_amount += level.total_amount;
+                (level.last_price.into(), cumulative_amount)
+            })
+            .collect()
+    }
+    /// Raw levels with amounts accumulated from the touch
+    fn get_cumulative_levels(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.raw_levels().scan(0, |cumulative_amount, (price, amount)| {
+            *cumulative_amount += amount;
+            Some((price, *cumulative_amount))
+        })
+    }
+    /// Sum of raw amounts at `price` and all better prices.
+    /// Linear in the number of better raw levels, unless the implementation keeps a cumulative index
+    /// (`AggregatedL2::enable_cumulative_index`, off by default)
+    fn cumulative_amount_up_to(&self, price: u64) -> Amount {
+        self.raw_levels_range((Bound::Unbounded, Bound::Included(price)))
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+    /// Best price at which the cumulative raw amount reaches `amount`.
+    /// Linear in the number of raw levels up to that price without a cumulative index, like `cumulative_amount_up_to`
+    fn price_for_cumulative_amount(&self, amount: Amount) -> Option<u64> {
+        self.get_cumulative_levels()
+            .find(|&(_, cumulative_amount)| cumulative_amount >= amount)
+            .map(|(price, _)| price)
+    }
+    /// Raw levels folded into the aggregated level `index`, borrowed from the raw book.
+    /// With maximum amounts the first and the last raw levels may be shared with the neighbour levels,
+    /// finding that takes a `cumulative_amount_up_to` query
+    fn get_aggregated_level_source(
+        &self,
+        index: usize,
+    ) -> Option<AggregatedLevelSource<impl DoubleEndedIterator<Item = (u64, Amount)> + Clone + '_>> {
+        let aggregated_levels = self.get_aggregated_levels();
+        let level = aggregated_levels.get(index)?;
+        let start = match index.checked_sub(1).map(|previous| aggregated_levels[previous].last_price.into()) {
+            None => Bound::Unbounded,
+            Some(previous_price) if !self.get_subscription_rules().has_maximum_amounts() => {
+                Bound::Excluded(previous_price)
+            }
+            Some(previous_price) => {
+                // the previous levels take a prefix of the raw book, the rest of its last quote is here
+                let previous_amount: Amount = aggregated_levels[..index].iter().map(|level| level.total_amount).sum();
+                if previous_amount < self.cumulative_amount_up_to(previous_price) {
+                    Bound::Included(previous_price)
+                } else {
+                    Bound::Excluded(previous_price)
+                }
+            }
+        };
+        Some(AggregatedLevelSource {
+            index,
+            total_amount: level.total_amount,
+            raw_levels: self.raw_levels_range((start, Bound::Included(level.last_price.into()))),
+        })
+    }
+    /// Index of the aggregated level containing the raw level at `price`, the first one if it is split.
+    /// `None` if there is no such raw level or it is beyond the max depth
+    fn find_aggregated_level_index(&self, price: u64) -> Option<usize> {
+        self.get_raw_amount(price)?;
+        let price = Price::from(price);
+        let aggregated_levels = self.get_aggregated_levels();
+        let index = aggregated_levels.partition_point(|level| level.last_price < price);
+        if index == aggregated_levels.len() {
+            return None;
+        }
+        Some(index)
+    }
+}
+
+/// Raw quotes moved by one `set_quote`: pushed to the next levels by a surplus, pulled back by a shortage,
+/// or appended again when the tail of the ladder is rebuilt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CascadeLengths {
+    pub surplus: usize,
+    pub shortage: usize,
+    pub rebuild: usize,
+}
+
+impl CascadeLengths {
+    pub fn total(self: &Self) -> usize {
+        self.surplus + self.shortage + self.rebuild
+    }
+}
+
+/// Range of raw levels behind one aggregated level, iterates them as `(price, amount)`
+pub struct AggregatedLevelSource<RawLevels> {
+    pub index: usize,
+    pub total_amount: Amount,
+    raw_levels: RawLevels,
+}
+
+impl<RawLevels: DoubleEndedIterator<Item = (u64, Amount)> + Clone> AggregatedLevelSource<RawLevels> {
+    pub fn first_price(self: &Self) -> Option<u64> {
+        self.raw_levels.clone().next().map(|(price, _)| price)
+    }
+    pub fn last_price(self: &Self) -> Option<u64> {
+        self.raw_levels.clone().next_back().map(|(price, _)| price)
+    }
+}
+
+impl<RawLevels: Iterator<Item = (u64, Amount)>> Iterator for AggregatedLevelSource<RawLevels> {
+    type Item = (u64, Amount);
+
+    fn next(&mut self) -> Option<(u64, Amount)> {
+        self.raw_levels.next()
+    }
+}
+
+/// Converts bounds given as plain prices into bounds of a map keyed by `Price`
+pub fn price_bounds<Price: OrderKey>(range: (Bound<u64>, Bound<u64>)) -> (Bound<Price>, Bound<Price>) {
+    (range.0.map(Price::from), range.1.map(Price::from))
+}
+
+/// `raw_levels_range` for implementations that keep raw levels in a `BTreeMap`
+pub fn btree_raw_levels_range<Price: OrderKey>(
+    levels: &BTreeMap<Price, Amount>,
+    range: (Bound<u64>, Bound<u64>),
+) -> impl DoubleEndedIterator<Item = (u64, Amount)> + Clone + '_ {
+    levels
+        .range(price_bounds::<Price>(range))
+        .map(|(&price, &amount)| (price.into(), amount))
+}
+
+/// Aggregated levels of the whole ladder built from scratch, walking at most `max_depth` raw levels
+pub fn aggregate_levels_linear<Price: OrderKey>(
+    levels: &BTreeMap<Price, Amount>,
+    subscription_rules: &SubscriptionRules,
+) -> Vec<AggregatedLevel<Price>> {
+    let mut aggregated_levels: Vec<AggregatedLevel<Price>> = Vec::new();
+    'quotes: for (&price, &amount) in levels.iter().take(subscription_rules.max_depth) {
+        debug_assert!(amount > 0);
+        let mut rest = amount;
+        while rest > 0 {
+            let count = aggregated_levels.len();
+            let is_last_level_open = count > 0 && {
+                let last_level = &aggregated_levels[count - 1];
+                last_level.total_amount < subscription_rules.get_amount(count - 1)
+                    && Some(last_level.total_amount) != subscription_rules.get_maximum_amount(count - 1)
+            };
+            if !is_last_level_open {
+                // huge quotes split by small maximums would make the ladder endless otherwise
+                if count == subscription_rules.max_depth {
+                    break 'quotes;
+                }
+                aggregated_levels.push(AggregatedLevel {
+                    last_price: price,
+                    total_amount: 0,
+                });
+            }
+            let index = aggregated_levels.len() - 1;
+            let mut taken = rest;
+            if let Some(maximum_amount) = subscription_rules.get_maximum_amount(index) {
+                taken = taken.min(maximum_amount - aggregated_levels[index].total_amount);
+            }
+            aggregated_levels[index].last_price = price;
+            aggregated_levels[index].total_amount += taken;
+            rest -= taken;
+        }
+    }
+    aggregated_levels
+}
+
+pub fn cumulative_amount_up_to_linear<Price: OrderKey>(
+    levels: &BTreeMap<Price, Amount>,
+    price: u64,
+) -> Amount {
+    levels
+        .range(..=Price::from(price))
+        .map(|(_, &amount)| amount)
+        .sum()
+}
+
+pub fn price_for_cumulative_amount_linear<Price: OrderKey>(
+    levels: &BTreeMap<Price, Amount>,
+    amount: Amount,
+) -> Option<u64> {
+    let mut cumulative_amount = 0;
+    for (&price, &level_amount) in levels.iter() {
+        cumulative_amount += level_amount;
+        if cumulative_amount >= amount {
+            return Some(price.into());
+        }
+    }
+    None
 }
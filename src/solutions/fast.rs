@@ -1,5 +1,7 @@
 pub use crate::common::*;
+use crate::cumulative::CumulativeTree;
 pub use crate::solutions::aggregated_l2_trait::*;
+pub use crate::solutions::invariants::*;
 pub use crate::subscription::*;
 
 use std::collections::BTreeMap;
@@ -10,7 +12,14 @@ pub struct AggregatedL2<Price: OrderKey> {
     levels: BTreeMap<Price, Amount>,
     max_depth_price: Price,
     aggregated_levels: Vec<AggregatedLevel<Price>>,
+    /// Only maintained when the rules have maximum amounts, see `append_quote`
+    remainders: Vec<Amount>,
     subscription_rules: SubscriptionRules,
+    /// Built by `enable_cumulative_index`, it costs a lot on every `set_quote`
+    cumulative_levels: Option<CumulativeTree<Price>>,
+    is_paranoid: bool,
+    /// Raw quotes moved between aggregated levels by the last `set_quote`
+    cascade: CascadeLengths,
 }
 
 impl<Price: OrderKey> AggregatedL2<Price>
@@ -52,6 +61,7 @@ where
                 }
             }
             self.aggregated_levels[index].total_amount -= amount;
+            self.cascade.surplus += 1;
             cursor.prev();
             self.aggregated_levels[index].last_price = *cursor.peek_next().unwrap().0;
             if !self.does_level_have_surplus(index, &cursor) {
@@ -193,6 +203,7 @@ where
             }
             self.aggregated_levels[index].last_price = price;
             self.aggregated_levels[index].total_amount += amount;
+            self.cascade.shortage += 1;
             if index_to_steal_quotes < self.aggregated_levels.len() {
                 self.aggregated_levels[index_to_steal_quotes].total_amount -= amount;
                 if self.aggregated_levels[index_to_steal_quotes].total_amount == 0 {
@@ -271,27 +282,67 @@ where
             }
         };
     }
-    pub fn get_max_depth_price(&self) -> Price {
-        return self.max_depth_price;
+    /// Appends quote after the last aggregated level, splitting it when levels have maximum amounts.
+    /// Remainder of a level is the part of its last quote that went to the next levels.
+    /// Splits stop at `max_depth` aggregated levels, the rest of the quote is not shown like quotes beyond the depth
+    fn append_quote(
+        aggregated_levels: &mut Vec<AggregatedLevel<Price>>,
+        remainders: &mut Vec<Amount>,
+        subscription_rules: &SubscriptionRules,
+        price: Price,
+        amount: Amount,
+    ) {
+        let mut rest = amount;
+        while rest > 0 {
+            let mut index = aggregated_levels.len();
+            if index == 0
+                || subscription_rules.is_level_complete(index - 1, aggregated_levels[index - 1].total_amount)
+            {
+                if index == subscription_rules.max_depth {
+                    return;
+                }
+                aggregated_levels.push(AggregatedLevel {
+                    last_price: price,
+                    total_amount: 0,
+                });
+                remainders.push(0);
+            } else {
+                index -= 1;
+            }
+            let level = &mut aggregated_levels[index];
+            let room = match subscription_rules.get_maximum_amount(index) {
+                Some(maximum_amount) => maximum_amount - level.total_amount,
+                None => Amount::MAX,
+            };
+            let taken = rest.min(room);
+            level.last_price = price;
+            level.total_amount += taken;
+            rest -= taken;
+            remainders[index] = rest;
+        }
     }
-}
-
-impl<Price: OrderKey> AgregatedL2Trait<Price> for AggregatedL2<Price>
-where
-    u64: From<Price>,
-    Price: From<u64>,
-{
-    fn new(table: SubscriptionRules) -> Self {
-        Self {
-            levels: BTreeMap::new(),
-            max_depth_price: Price::MAX,
-            aggregated_levels: Vec::new(),
-            subscription_rules: table,
+    fn rebuild_aggregated_levels(self: &mut Self) {
+        // full recalculation in one pass over the levels, used when rules change
+        self.aggregated_levels.clear();
+        self.remainders.clear();
+        self.max_depth_price = Price::MAX;
+        for (quote_index, (&price, &amount)) in self.levels.iter().enumerate() {
+            if quote_index == self.subscription_rules.max_depth {
+                break;
+            }
+            if quote_index + 1 == self.subscription_rules.max_depth {
+                self.max_depth_price = price;
+            }
+            Self::append_quote(
+                &mut self.aggregated_levels,
+                &mut self.remainders,
+                &self.subscription_rules,
+                price,
+                amount,
+            );
         }
     }
-    fn set_quote(self: &mut Self, price_: u64, new_amount: Amount) {
-        let price = Price::from(price_);
-
+    fn set_quote_without_maximum_amounts(self: &mut Self, price: Price, new_amount: Amount) {
         match self.levels.entry(price) {
             Entry::Vacant(entry) => {
                 if new_amount != 0 {
@@ -317,17 +368,288 @@ where
             }
         };
     }
-    fn get_levels(&self) -> &BTreeMap<Price, Amount> {
-        return &self.levels;
+    /// Splits make the boundaries of every later level depend on the amount, so this is not incremental:
+    /// the tail is rebuilt from the changed price and costs O(levels) when the change is near the touch.
+    /// The rebuild stops early once the new levels line up with the old ones, see `rebuild_aggregated_levels_from`
+    fn set_quote_with_maximum_amounts(self: &mut Self, price: Price, new_amount: Amount) {
+        // Levels before the first one containing price can't change, so only the tail is rebuilt
+        let current_amount = self.levels.get(&price).copied().unwrap_or(0);
+        if new_amount == current_amount {
+            return;
+        }
+        let previous_max_depth_price = self.max_depth_price;
+        if new_amount == 0 {
+            self.levels.remove(&price);
+            if self.max_depth_price != Price::MAX && price <= self.max_depth_price {
+                let cursor = self
+                    .levels
+                    .lower_bound(Bound::Excluded(&self.max_depth_price));
+                self.max_depth_price = match cursor.peek_next() {
+                    Some((&next_price, _)) => next_price,
+                    None => Price::MAX,
+                };
+            }
+        } else {
+            self.levels.insert(price, new_amount);
+            if current_amount == 0 {
+                if self.max_depth_price == Price::MAX {
+                    if self.levels.len() == self.subscription_rules.max_depth {
+                        self.max_depth_price = *self.levels.last_key_value().unwrap().0;
+                    }
+                } else if price < self.max_depth_price {
+                    let cursor = self
+                        .levels
+                        .lower_bound(Bound::Included(&self.max_depth_price));
+                    self.max_depth_price = *cursor.peek_prev().unwrap().0;
+                }
+            }
+        }
+        if price > self.max_depth_price {
+            return;
+        }
+        self.rebuild_aggregated_levels_from(price, previous_max_depth_price == self.max_depth_price);
     }
-    fn get_aggregated_levels(&self) -> &Vec<AggregatedLevel<Price>> {
-        return &self.aggregated_levels;
+    fn rebuild_aggregated_levels_from(self: &mut Self, price: Price, can_reuse_tail: bool) {
+        let mut index = self
+            .aggregated_levels
+            .partition_point(|level| level.last_price < price);
+        if index > 0
+            && !self
+                .subscription_rules
+                .is_level_complete(index - 1, self.aggregated_levels[index - 1].total_amount)
+        {
+            index -= 1;
+        }
+        let old_levels = self.aggregated_levels.split_off(index);
+        let old_remainders = self.remainders.split_off(index);
+
+        let mut start = Bound::Unbounded;
+        if let Some(previous_level) = self.aggregated_levels.last() {
+            let (previous_price, remainder) = (previous_level.last_price, self.remainders[index - 1]);
+            start = Bound::Excluded(previous_price);
+            Self::append_quote(
+                &mut self.aggregated_levels,
+                &mut self.remainders,
+                &self.subscription_rules,
+                previous_price,
+                remainder,
+            );
+        }
+        for (&quote_price, &amount) in self.levels.range((start, Bound::Included(self.max_depth_price))) {
+            self.cascade.rebuild += 1;
+            Self::append_quote(
+                &mut self.aggregated_levels,
+                &mut self.remainders,
+                &self.subscription_rules,
+                quote_price,
+                amount,
+            );
+            if self.aggregated_levels.len() == self.subscription_rules.max_depth
+                && self.subscription_rules.is_level_complete(
+                    self.subscription_rules.max_depth - 1,
+                    self.aggregated_levels[self.subscription_rules.max_depth - 1].total_amount,
+                )
+            {
+                return;
+            }
+            if !can_reuse_tail || quote_price < price {
+                continue;
+            }
+            // the rest depends only on the last level and the quotes after it, which are the same as before
+            let last_index = self.aggregated_levels.len() - 1;
+            let Some(old_level) = old_levels.get(last_index - index) else {
+                continue;
+            };
+            let last_level = &self.aggregated_levels[last_index];
+            if old_level.last_price == last_level.last_price
+                && old_remainders[last_index - index] == self.remainders[last_index]
+                && self.subscription_rules.is_level_complete(last_index, old_level.total_amount)
+                    == self.subscription_rules.is_level_complete(last_index, last_level.total_amount)
+            {
+                self.aggregated_levels
+                    .extend_from_slice(&old_levels[last_index - index + 1..]);
+                self.remainders
+                    .extend_from_slice(&old_remainders[last_index - index + 1..]);
+                return;
+            }
+        }
+    }
+    pub fn get_max_depth_price(&self) -> Price {
+        return self.max_depth_price;
     }
-    fn get_aggregated_levels_tuples(&self) -> Vec<(u64, u64)> {
-        let result_clone = self.aggregated_levels.clone();
-        result_clone
+    fn assert_invariants(&self) {
+        if let Err(report) = self.check_invariants() {
+            panic!("AggregatedL2 is broken after an update: {}", report);
+        }
+    }
+    /// In paranoid mode invariants are checked after every change, which makes updates O(n)
+    pub fn set_paranoid(self: &mut Self, is_paranoid: bool) {
+        self.is_paranoid = is_paranoid;
+    }
+    /// Makes cumulative amount queries O(log n) from now on, without it they walk the raw book.
+    /// Not enabled by default since the index is updated on every `set_quote`
+    pub fn enable_cumulative_index(self: &mut Self) {
+        if self.cumulative_levels.is_some() {
+            return;
+        }
+        let mut cumulative_levels = CumulativeTree::new();
+        for (&price, &amount) in self.levels.iter() {
+            cumulative_levels.set(price, amount);
+        }
+        self.cumulative_levels = Some(cumulative_levels);
+    }
+}
+
+impl<Price: OrderKey> AgregatedL2Trait<Price> for AggregatedL2<Price>
+where
+    u64: From<Price>,
+    Price: From<u64>,
+{
+    fn new(table: SubscriptionRules) -> Self {
+        Self {
+            levels: BTreeMap::new(),
+            max_depth_price: Price::MAX,
+            aggregated_levels: Vec::new(),
+            remainders: Vec::new(),
+            subscription_rules: table,
+            cumulative_levels: None,
+            is_paranoid: false,
+            cascade: CascadeLengths::default(),
+        }
+    }
+    fn from_sorted_levels(table: SubscriptionRules, levels: impl IntoIterator<Item = (u64, Amount)>) -> Self {
+        let mut solution = Self::new(table);
+        let mut sorted_levels = Vec::new();
+        let mut previous_price = None;
+        let mut levels = levels.into_iter();
+        // one pass fills the ladder like `rebuild_aggregated_levels` while collecting the raw levels
+        while let Some((price_, amount)) = levels.next() {
+            let price = Price::from(price_);
+            if previous_price >= Some(price) {
+                let loaded_levels = sorted_levels.into_iter().map(|(price, amount)| (u64::from(price), amount));
+                solution.replace_levels(loaded_levels.chain([(price_, amount)]).chain(levels));
+                return solution;
+            }
+            previous_price = Some(price);
+            if amount == 0 {
+                continue;
+            }
+            let quote_index = sorted_levels.len();
+            if quote_index < solution.subscription_rules.max_depth {
+                Self::append_quote(
+                    &mut solution.aggregated_levels,
+                    &mut solution.remainders,
+                    &solution.subscription_rules,
+                    price,
+                    amount,
+                );
+            }
+            if quote_index + 1 == solution.subscription_rules.max_depth {
+                solution.max_depth_price = price;
+            }
+            sorted_levels.push((price, amount));
+        }
+        solution.levels = sorted_levels.into_iter().collect();
+        solution
+    }
+    fn set_quote(self: &mut Self, price_: u64, new_amount: Amount) {
+        let price = Price::from(price_);
+        self.cascade = CascadeLengths::default();
+        if let Some(cumulative_levels) = &mut self.cumulative_levels {
+            cumulative_levels.set(price, new_amount);
+        }
+        if self.subscription_rules.has_maximum_amounts() {
+            self.set_quote_with_maximum_amounts(price, new_amount);
+        } else {
+            self.set_quote_without_maximum_amounts(price, new_amount);
+        }
+        if self.is_paranoid {
+            self.assert_invariants();
+        }
+    }
+    fn replace_levels(self: &mut Self, levels: impl IntoIterator<Item = (u64, Amount)>) {
+        self.cascade = CascadeLengths::default();
+        self.levels = levels
             .into_iter()
-            .map(|level| (level.last_price.into(), level.total_amount))
-            .collect()
+            .filter(|&(_, amount)| amount != 0)
+            .map(|(price, amount)| (Price::from(price), amount))
+            .collect();
+        if self.cumulative_levels.take().is_some() {
+            self.enable_cumulative_index();
+        }
+        self.rebuild_aggregated_levels();
+        if self.is_paranoid {
+            self.assert_invariants();
+        }
+    }
+    fn set_subscription_rules(self: &mut Self, subscription: SubscriptionRules) {
+        self.subscription_rules = subscription;
+        self.rebuild_aggregated_levels();
+        if self.is_paranoid {
+            self.assert_invariants();
+        }
+    }
+    fn get_subscription_rules(&self) -> &SubscriptionRules {
+        return &self.subscription_rules;
+    }
+    fn get_last_cascade(&self) -> Option<CascadeLengths> {
+        return Some(self.cascade);
+    }
+    fn raw_levels_range(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> impl DoubleEndedIterator<Item = (u64, Amount)> + Clone + '_ {
+        return btree_raw_levels_range(&self.levels, range);
+    }
+    fn get_raw_amount(&self, price: u64) -> Option<Amount> {
+        return self.levels.get(&Price::from(price)).copied();
+    }
+    fn raw_levels_count(&self) -> usize {
+        return self.levels.len();
+    }
+    fn get_aggregated_levels(&self) -> &[AggregatedLevel<Price>] {
+        return &self.aggregated_levels;
+    }
+    fn cumulative_amount_up_to(&self, price: u64) -> Amount {
+        match &self.cumulative_levels {
+            Some(cumulative_levels) => cumulative_levels.cumulative_amount_up_to(Price::from(price)),
+            None => cumulative_amount_up_to_linear(&self.levels, price),
+        }
+    }
+    fn price_for_cumulative_amount(&self, amount: Amount) -> Option<u64> {
+        match &self.cumulative_levels {
+            Some(cumulative_levels) => cumulative_levels
+                .price_for_cumulative_amount(amount)
+                .map(u64::from),
+            None => price_for_cumulative_amount_linear(&self.levels, amount),
+        }
+    }
+    /// Verifies the whole state, O(n) in the size of the raw book
+    fn check_invariants(&self) -> Result<(), InvariantReport> {
+        let mut remainders = Vec::new();
+        let mut violations = check_aggregation_invariants(
+            &self.levels,
+            &self.aggregated_levels,
+            self.max_depth_price,
+            &self.subscription_rules,
+            &mut remainders,
+        );
+        if self.subscription_rules.has_maximum_amounts() && violations.is_empty() {
+            for (index, (&expected, &actual)) in remainders.iter().zip(self.remainders.iter()).enumerate() {
+                if expected != actual {
+                    violations.push(InvariantViolation::RemainderMismatch { index, expected, actual });
+                }
+            }
+        }
+        if let Some(cumulative_levels) = &self.cumulative_levels {
+            let expected: Amount = self.levels.values().sum();
+            if cumulative_levels.total_amount() != expected {
+                violations.push(InvariantViolation::CumulativeIndexMismatch {
+                    expected,
+                    actual: cumulative_levels.total_amount(),
+                });
+            }
+        }
+        return InvariantReport::into_result(violations);
     }
 }
@@ -0,0 +1,235 @@
+use crate::common::*;
+use crate::subscription::*;
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    ZeroRawLevel { price: u64 },
+    ZeroAggregatedLevel { index: usize },
+    /// `None` means that the book is not cut by depth
+    WrongMaxDepthPrice { expected: Option<u64>, actual: Option<u64> },
+    LevelBelowMinimum { index: usize, total_amount: Amount, minimum_amount: Amount },
+    LevelAboveMaximum { index: usize, total_amount: Amount, maximum_amount: Amount },
+    /// Level reaches its minimum without the amount taken from its last raw level, so it should have ended earlier
+    LevelHasSurplus { index: usize, total_amount: Amount, last_amount: Amount, minimum_amount: Amount },
+    /// Amount of the level ends at another raw level than its `last_price`
+    LevelBoundaryMismatch { index: usize, last_price: u64, covered_until: u64 },
+    /// Raw level is divided between aggregated levels without maximum amounts
+    RawLevelSplit { index: usize, price: u64 },
+    LevelBeyondRawBook { index: usize },
+    UncoveredRawLevels { first_price: u64 },
+    CumulativeIndexMismatch { expected: Amount, actual: Amount },
+    RemainderMismatch { index: usize, expected: Amount, actual: Amount },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroRawLevel { price } => write!(f, "raw level {} has zero amount", price),
+            Self::ZeroAggregatedLevel { index } => {
+                write!(f, "aggregated level {} has zero amount", index)
+            }
+            Self::WrongMaxDepthPrice { expected, actual } => write!(
+                f,
+                "max depth price is {:?}, expected {:?}",
+                actual, expected
+            ),
+            Self::LevelBelowMinimum { index, total_amount, minimum_amount } => write!(
+                f,
+                "aggregated level {} has {} below minimum {} and is not the last one",
+                index, total_amount, minimum_amount
+            ),
+            Self::LevelAboveMaximum { index, total_amount, maximum_amount } => write!(
+                f,
+                "aggregated level {} has {} above maximum {}",
+                index, total_amount, maximum_amount
+            ),
+            Self::LevelHasSurplus { index, total_amount, last_amount, minimum_amount } => write!(
+                f,
+                "aggregated level {} has {} and reaches minimum {} without {} of its last raw level",
+                index, total_amount, minimum_amount, last_amount
+            ),
+            Self::LevelBoundaryMismatch { index, last_price, covered_until } => write!(
+                f,
+                "aggregated level {} ends at {}, but its amount covers raw levels until {}",
+                index, last_price, covered_until
+            ),
+            Self::RawLevelSplit { index, price } => write!(
+                f,
+                "raw level {} is split after aggregated level {} without maximum amounts",
+                price, index
+            ),
+            Self::LevelBeyondRawBook { index } => {
+                write!(f, "aggregated level {} has more amount than the raw book", index)
+            }
+            Self::UncoveredRawLevels { first_price } => write!(
+                f,
+                "raw levels from {} within max depth are not in aggregated levels",
+                first_price
+            ),
+            Self::CumulativeIndexMismatch { expected, actual } => write!(
+                f,
+                "cumulative index has total {}, raw book has {}",
+                actual, expected
+            ),
+            Self::RemainderMismatch { index, expected, actual } => write!(
+                f,
+                "remainder of aggregated level {} is {}, expected {}",
+                index, actual, expected
+            ),
+        }
+    }
+}
+
+/// All violations found in one state, in the order of the checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvariantReport {
+    pub violations: Vec<InvariantViolation>,
+}
+
+impl fmt::Display for InvariantReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} invariant violation(s):", self.violations.len())?;
+        for violation in self.violations.iter() {
+            writeln!(f, "  - {}", violation)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for InvariantReport {}
+
+impl InvariantReport {
+    pub fn into_result(violations: Vec<InvariantViolation>) -> Result<(), Self> {
+        if violations.is_empty() {
+            return Ok(());
+        }
+        Err(Self { violations })
+    }
+}
+
+fn depth_price_to_option<Price: OrderKey>(price: Price) -> Option<u64> {
+    if price == Price::MAX {
+        return None;
+    }
+    Some(price.into())
+}
+
+/// Checks the state shared by all implementations. `remainders` are the amounts of the last raw level
+/// of every aggregated level that went to the next levels, as computed during the check
+pub fn check_aggregation_invariants<Price: OrderKey>(
+    levels: &BTreeMap<Price, Amount>,
+    aggregated_levels: &[AggregatedLevel<Price>],
+    max_depth_price: Price,
+    subscription_rules: &SubscriptionRules,
+    remainders: &mut Vec<Amount>,
+) -> Vec<InvariantViolation> {
+    let mut violations = Vec::new();
+    for (&price, &amount) in levels.iter() {
+        if amount == 0 {
+            violations.push(InvariantViolation::ZeroRawLevel { price: price.into() });
+        }
+    }
+
+    let expected_max_depth_price = levels.keys().nth(subscription_rules.max_depth - 1).copied();
+    if expected_max_depth_price.map(Into::<u64>::into) != depth_price_to_option(max_depth_price) {
+        violations.push(InvariantViolation::WrongMaxDepthPrice {
+            expected: expected_max_depth_price.map(Into::<u64>::into),
+            actual: depth_price_to_option(max_depth_price),
+        });
+    }
+
+    for (index, level) in aggregated_levels.iter().enumerate() {
+        if level.total_amount == 0 {
+            violations.push(InvariantViolation::ZeroAggregatedLevel { index });
+        }
+        let maximum_amount = subscription_rules.get_maximum_amount(index);
+        if let Some(maximum_amount) = maximum_amount {
+            if level.total_amount > maximum_amount {
+                violations.push(InvariantViolation::LevelAboveMaximum {
+                    index,
+                    total_amount: level.total_amount,
+                    maximum_amount,
+                });
+            }
+        }
+        let is_last = index + 1 == aggregated_levels.len();
+        if !is_last && !subscription_rules.is_level_complete(index, level.total_amount) {
+            violations.push(InvariantViolation::LevelBelowMinimum {
+                index,
+                total_amount: level.total_amount,
+                minimum_amount: subscription_rules.get_amount(index),
+            });
+        }
+    }
+
+    // walk the raw book and consume it level by level
+    remainders.clear();
+    let mut raw_levels = levels
+        .iter()
+        .take(subscription_rules.max_depth)
+        .map(|(&price, &amount)| (price, amount));
+    let mut current: Option<(Price, Amount)> = None;
+    for (index, level) in aggregated_levels.iter().enumerate() {
+        let mut needed_amount = level.total_amount;
+        loop {
+            let (price, remaining_amount) = match current.take().or_else(|| raw_levels.next()) {
+                Some(raw_level) => raw_level,
+                None => {
+                    violations.push(InvariantViolation::LevelBeyondRawBook { index });
+                    return violations;
+                }
+            };
+            let taken = needed_amount.min(remaining_amount);
+            needed_amount -= taken;
+            if needed_amount > 0 {
+                continue;
+            }
+            let remainder = remaining_amount - taken;
+            if remainder > 0 {
+                current = Some((price, remainder));
+            }
+            if price != level.last_price {
+                violations.push(InvariantViolation::LevelBoundaryMismatch {
+                    index,
+                    last_price: level.last_price.into(),
+                    covered_until: price.into(),
+                });
+                return violations;
+            }
+            let minimum_amount = subscription_rules.get_amount(index);
+            if level.total_amount - taken >= minimum_amount {
+                violations.push(InvariantViolation::LevelHasSurplus {
+                    index,
+                    total_amount: level.total_amount,
+                    last_amount: taken,
+                    minimum_amount,
+                });
+            }
+            if remainder > 0 && !subscription_rules.has_maximum_amounts() {
+                violations.push(InvariantViolation::RawLevelSplit {
+                    index,
+                    price: price.into(),
+                });
+            }
+            remainders.push(remainder);
+            break;
+        }
+    }
+    // a full ladder hides the rest of the book like the depth does
+    let is_ladder_full = aggregated_levels.len() == subscription_rules.max_depth
+        && aggregated_levels
+            .last()
+            .is_some_and(|level| subscription_rules.is_level_complete(aggregated_levels.len() - 1, level.total_amount));
+    if is_ladder_full {
+        return violations;
+    }
+    if let Some((first_price, _)) = current.or_else(|| raw_levels.next()) {
+        violations.push(InvariantViolation::UncoveredRawLevels {
+            first_price: first_price.into(),
+        });
+    }
+    violations
+}
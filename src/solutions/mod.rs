@@ -1,3 +1,7 @@
+#[allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod aggregated_l2_trait;
+#[allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod fast;
+pub mod invariants;
+#[allow(clippy::needless_return, clippy::needless_arbitrary_self_type)]
 pub mod slow_for_comparisons;
@@ -1,8 +1,10 @@
 use crate::common::*;
-use crate::solutions::aggregated_l2_trait::AgregatedL2Trait;
+use crate::solutions::aggregated_l2_trait::*;
+use crate::solutions::invariants::*;
 use crate::subscription::*;
 
 use std::collections::BTreeMap;
+use std::ops::Bound;
 
 pub struct SlowAggregatedL2ForComparisons<Price: OrderKey> {
     levels: BTreeMap<Price, Amount>,
@@ -19,6 +21,13 @@ where
     pub fn get_max_depth_price(&self) -> Price {
         return self.max_depth_price;
     }
+    fn recalculate_aggregated_levels(self: &mut Self) {
+        self.aggregated_levels = aggregate_levels_linear(&self.levels, &self.subscription_rules);
+        self.max_depth_price = match self.levels.keys().nth(self.subscription_rules.max_depth - 1) {
+            Some(&price) => price,
+            None => Price::MAX,
+        };
+    }
 }
 
 impl<Price: OrderKey> AgregatedL2Trait<Price> for SlowAggregatedL2ForComparisons<Price>
@@ -30,7 +39,7 @@ where
         Self {
             levels: BTreeMap::new(),
             aggregated_levels: Vec::new(),
-            subscription_rules: subscription_rules,
+            subscription_rules,
             max_depth_price: Price::MAX,
         }
     }
@@ -50,48 +59,45 @@ where
                 }
             }
         };
-        self.aggregated_levels.clear();
-        for (quote_index, (&price, &amount)) in self.levels.iter().enumerate() {
-            debug_assert!(amount > 0);
-            if quote_index + 1 > self.subscription_rules.max_depth {
-                break;
-            }
-            self.max_depth_price = price;
-            if self.aggregated_levels.is_empty() {
-                self.aggregated_levels.push(AggregatedLevel {
-                    last_price: price,
-                    total_amount: amount,
-                });
-                continue;
-            }
-            let index = self.aggregated_levels.len() - 1;
-            if self.aggregated_levels[index].total_amount
-                >= self.subscription_rules.get_amount(index)
-            {
-                self.aggregated_levels.push(AggregatedLevel {
-                    last_price: price,
-                    total_amount: amount,
-                });
-            } else {
-                self.aggregated_levels[index].last_price = price;
-                self.aggregated_levels[index].total_amount += amount;
-            }
-        }
-        if self.levels.len() < self.subscription_rules.max_depth {
-            self.max_depth_price = Price::MAX;
-        }
+        self.recalculate_aggregated_levels();
     }
-    fn get_levels(&self) -> &BTreeMap<Price, Amount> {
-        return &self.levels;
+    fn replace_levels(self: &mut Self, levels: impl IntoIterator<Item = (u64, Amount)>) {
+        self.levels = levels
+            .into_iter()
+            .filter(|&(_, amount)| amount != 0)
+            .map(|(price, amount)| (Price::from(price), amount))
+            .collect();
+        self.recalculate_aggregated_levels();
+    }
+    fn set_subscription_rules(self: &mut Self, subscription_rules: SubscriptionRules) {
+        self.subscription_rules = subscription_rules;
+        self.recalculate_aggregated_levels();
+    }
+    fn get_subscription_rules(&self) -> &SubscriptionRules {
+        return &self.subscription_rules;
+    }
+    fn raw_levels_range(
+        &self,
+        range: (Bound<u64>, Bound<u64>),
+    ) -> impl DoubleEndedIterator<Item = (u64, Amount)> + Clone + '_ {
+        return btree_raw_levels_range(&self.levels, range);
     }
-    fn get_aggregated_levels(&self) -> &Vec<AggregatedLevel<Price>> {
+    fn get_raw_amount(&self, price: u64) -> Option<Amount> {
+        return self.levels.get(&Price::from(price)).copied();
+    }
+    fn raw_levels_count(&self) -> usize {
+        return self.levels.len();
+    }
+    fn get_aggregated_levels(&self) -> &[AggregatedLevel<Price>] {
         return &self.aggregated_levels;
     }
-    fn get_aggregated_levels_tuples(&self) -> Vec<(u64, u64)> {
-        let result_clone = self.aggregated_levels.clone();
-        result_clone
-            .into_iter()
-            .map(|level| (level.last_price.into(), level.total_amount))
-            .collect()
+    fn check_invariants(&self) -> Result<(), InvariantReport> {
+        return InvariantReport::into_result(check_aggregation_invariants(
+            &self.levels,
+            &self.aggregated_levels,
+            self.max_depth_price,
+            &self.subscription_rules,
+            &mut Vec::new(),
+        ));
     }
 }
@@ -1,28 +1,435 @@
 use crate::common::*;
 
-#[derive(Clone)]
+use serde::Deserialize;
+use std::fmt;
+
+/// Largest `max_depth` for a `maximum_schedule` that has to be checked level by level,
+/// that is when the minimum schedule keeps growing
+pub const MAX_DEPTH_LIMIT: usize = 1_000_000;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SubscriptionRulesError {
+    ZeroMinimumAmount { index: usize },
+    ZeroFallback,
+    ZeroMaxDepth,
+    /// `max_depth` is above [`MAX_DEPTH_LIMIT`] and the maximum schedule can't be checked in fewer levels
+    MaxDepthTooLarge { max_depth: usize },
+    ZeroStart,
+    ZeroCap,
+    InvalidRatio,
+    /// Both a table (`minimum_amounts`/`fallback`) and a `schedule` are given
+    ConflictingSchedule,
+    /// Neither a complete table nor a `schedule` is given
+    MissingSchedule,
+    /// A full level would close before reaching its minimum
+    MaximumBelowMinimum { index: usize, maximum_amount: Amount, minimum_amount: Amount },
+}
+
+impl fmt::Display for SubscriptionRulesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ZeroMinimumAmount { index } => {
+                write!(f, "minimum amount at index {} must be positive", index)
+            }
+            Self::ZeroFallback => write!(f, "fallback amount must be positive"),
+            Self::ZeroMaxDepth => write!(f, "max_depth must be positive"),
+            Self::MaxDepthTooLarge { max_depth } => {
+                write!(f, "max_depth {} is above the limit {}", max_depth, MAX_DEPTH_LIMIT)
+            }
+            Self::ZeroStart => write!(f, "schedule start amount must be positive"),
+            Self::ZeroCap => write!(f, "schedule cap must be positive"),
+            Self::InvalidRatio => write!(f, "geometric ratio must be finite and at least 1"),
+            Self::ConflictingSchedule => {
+                write!(f, "either minimum_amounts with fallback or schedule must be set, not both")
+            }
+            Self::MissingSchedule => {
+                write!(f, "either minimum_amounts with fallback or schedule must be set")
+            }
+            Self::MaximumBelowMinimum { index, maximum_amount, minimum_amount } => write!(
+                f,
+                "maximum amount {} at index {} is below minimum amount {}",
+                maximum_amount, index, minimum_amount
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SubscriptionRulesError {}
+
+/// Minimum amount of every aggregated level, evaluated by index on demand
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ThresholdSchedule {
+    /// Explicit amounts, `fallback` for every level after them
+    Table {
+        amounts: Vec<Amount>,
+        fallback: Amount,
+    },
+    /// `start * ratio^index`, rounded to the nearest integer
+    Geometric {
+        start: Amount,
+        ratio: f64,
+        cap: Option<Amount>,
+    },
+    /// `start + step * index`
+    Arithmetic {
+        start: Amount,
+        step: Amount,
+        cap: Option<Amount>,
+    },
+    /// Explicit amounts, then `then` continues from its own index 0
+    TableThen {
+        amounts: Vec<Amount>,
+        then: Box<ThresholdSchedule>,
+    },
+}
+
+fn apply_cap(amount: Amount, cap: Option<Amount>) -> Amount {
+    match cap {
+        Some(cap) => amount.min(cap),
+        None => amount,
+    }
+}
+
+impl ThresholdSchedule {
+    pub fn get_amount(self: &Self, index: usize) -> Amount {
+        match self {
+            Self::Table { amounts, fallback } => *amounts.get(index).unwrap_or(fallback),
+            Self::Geometric { start, ratio, cap } => {
+                // `as` saturates, so overflow ends up at u64::MAX and then at the cap
+                let amount = (*start as f64 * ratio.powi(index.min(i32::MAX as usize) as i32))
+                    .round() as Amount;
+                apply_cap(amount, *cap)
+            }
+            Self::Arithmetic { start, step, cap } => {
+                let amount = step.saturating_mul(index as Amount).saturating_add(*start);
+                apply_cap(amount, *cap)
+            }
+            Self::TableThen { amounts, then } => match amounts.get(index) {
+                Some(&amount) => amount,
+                None => then.get_amount(index - amounts.len()),
+            },
+        }
+    }
+    /// Amounts may change arbitrarily before this index and never decrease after it
+    fn irregular_prefix_len(self: &Self) -> usize {
+        match self {
+            Self::Table { amounts, .. } => amounts.len(),
+            Self::Geometric { .. } | Self::Arithmetic { .. } => 0,
+            Self::TableThen { amounts, then } => amounts.len() + then.irregular_prefix_len(),
+        }
+    }
+    /// Index from which the amount never changes, `None` if it keeps growing
+    fn constant_from(self: &Self) -> Option<usize> {
+        match self {
+            Self::Table { amounts, .. } => Some(amounts.len()),
+            Self::Geometric { ratio, .. } if *ratio == 1.0 => Some(0),
+            Self::Geometric { start, ratio, cap: Some(cap) } => {
+                // rounding may reach the cap a bit later than the logarithm says, a later index is still correct
+                let index = ((*cap as f64 / *start as f64).ln() / ratio.ln()).ceil().max(0.0);
+                Some((index as usize).saturating_add(2))
+            }
+            Self::Arithmetic { start, step, cap } => match cap {
+                _ if *step == 0 => Some(0),
+                Some(cap) => Some(cap.saturating_sub(*start).div_ceil(*step) as usize),
+                None => None,
+            },
+            Self::Geometric { .. } => None,
+            Self::TableThen { amounts, then } => then.constant_from().map(|index| amounts.len() + index),
+        }
+    }
+    pub fn validate(self: &Self) -> Result<(), SubscriptionRulesError> {
+        let validate_amounts = |amounts: &Vec<Amount>| match amounts.iter().position(|&x| x == 0) {
+            Some(index) => Err(SubscriptionRulesError::ZeroMinimumAmount { index }),
+            None => Ok(()),
+        };
+        let validate_start_and_cap = |start: Amount, cap: Option<Amount>| {
+            if start == 0 {
+                return Err(SubscriptionRulesError::ZeroStart);
+            }
+            if cap == Some(0) {
+                return Err(SubscriptionRulesError::ZeroCap);
+            }
+            Ok(())
+        };
+        match self {
+            Self::Table { amounts, fallback } => {
+                validate_amounts(amounts)?;
+                if *fallback == 0 {
+                    return Err(SubscriptionRulesError::ZeroFallback);
+                }
+                Ok(())
+            }
+            Self::Geometric { start, ratio, cap } => {
+                if !ratio.is_finite() || *ratio < 1.0 {
+                    return Err(SubscriptionRulesError::InvalidRatio);
+                }
+                validate_start_and_cap(*start, *cap)
+            }
+            Self::Arithmetic { start, cap, .. } => validate_start_and_cap(*start, *cap),
+            Self::TableThen { amounts, then } => {
+                validate_amounts(amounts)?;
+                then.validate()
+            }
+        }
+    }
+}
+
+/// Builds a schedule either from a table or from an explicit `schedule` field
+fn schedule_from_parts(
+    minimum_amounts: Option<Vec<Amount>>,
+    fallback: Option<Amount>,
+    schedule: Option<ThresholdSchedule>,
+) -> Result<ThresholdSchedule, SubscriptionRulesError> {
+    match (minimum_amounts, fallback, schedule) {
+        (None, None, Some(schedule)) => Ok(schedule),
+        (Some(amounts), Some(fallback), None) => Ok(ThresholdSchedule::Table { amounts, fallback }),
+        (None, None, None) | (Some(_), None, None) | (None, Some(_), None) => {
+            Err(SubscriptionRulesError::MissingSchedule)
+        }
+        _ => Err(SubscriptionRulesError::ConflictingSchedule),
+    }
+}
+
+/// Serialized form of the rules, validated on conversion
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscriptionRulesConfig {
+    minimum_amounts: Option<Vec<Amount>>,
+    fallback: Option<Amount>,
+    schedule: Option<ThresholdSchedule>,
+    maximum_schedule: Option<ThresholdSchedule>,
+    max_depth: usize,
+}
+
+impl TryFrom<SubscriptionRulesConfig> for SubscriptionRules {
+    type Error = SubscriptionRulesError;
+
+    fn try_from(config: SubscriptionRulesConfig) -> Result<Self, Self::Error> {
+        let schedule =
+            schedule_from_parts(config.minimum_amounts, config.fallback, config.schedule)?;
+        let rules = Self::try_with_schedule(schedule, config.max_depth)?;
+        match config.maximum_schedule {
+            Some(maximum_schedule) => rules.try_with_maximum_schedule(maximum_schedule),
+            None => Ok(rules),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "SubscriptionRulesConfig")]
 pub struct SubscriptionRules {
-    minimum_amounts: Vec<Amount>,
-    fallback: Amount,
+    schedule: ThresholdSchedule,
+    /// Level is displayed capped at its maximum, the rest goes to the next level
+    maximum_schedule: Option<ThresholdSchedule>,
     pub max_depth: usize,
 }
 
 impl SubscriptionRules {
     pub fn get_amount(self: &Self, index: usize) -> Amount {
-        if index >= self.minimum_amounts.len() {
-            return self.fallback;
-        }
-        return self.minimum_amounts[index];
+        return self.schedule.get_amount(index);
+    }
+    pub fn get_maximum_amount(self: &Self, index: usize) -> Option<Amount> {
+        return self
+            .maximum_schedule
+            .as_ref()
+            .map(|schedule| schedule.get_amount(index));
+    }
+    pub fn has_maximum_amounts(self: &Self) -> bool {
+        return self.maximum_schedule.is_some();
+    }
+    /// Level can't take more quotes: minimum is reached or it is full
+    pub fn is_level_complete(self: &Self, index: usize, total_amount: Amount) -> bool {
+        return total_amount >= self.get_amount(index)
+            || self.get_maximum_amount(index) == Some(total_amount);
+    }
+    pub fn get_schedule(self: &Self) -> &ThresholdSchedule {
+        return &self.schedule;
+    }
+    pub fn get_maximum_schedule(self: &Self) -> Option<&ThresholdSchedule> {
+        return self.maximum_schedule.as_ref();
     }
     pub fn new(minimum_amounts: Vec<Amount>, fallback: Amount, max_depth: usize) -> Self {
-        assert!(minimum_amounts.iter().all(|&x| x > 0));
-        assert!(fallback > 0);
-        assert!(max_depth > 0);
-
-        return Self {
-            minimum_amounts,
+        match Self::try_new(minimum_amounts, fallback, max_depth) {
+            Ok(rules) => rules,
+            Err(error) => panic!("invalid subscription rules: {}", error),
+        }
+    }
+    pub fn try_new(
+        minimum_amounts: Vec<Amount>,
+        fallback: Amount,
+        max_depth: usize,
+    ) -> Result<Self, SubscriptionRulesError> {
+        let schedule = ThresholdSchedule::Table {
+            amounts: minimum_amounts,
             fallback,
+        };
+        return Self::try_with_schedule(schedule, max_depth);
+    }
+    pub fn with_schedule(schedule: ThresholdSchedule, max_depth: usize) -> Self {
+        match Self::try_with_schedule(schedule, max_depth) {
+            Ok(rules) => rules,
+            Err(error) => panic!("invalid subscription rules: {}", error),
+        }
+    }
+    pub fn try_with_schedule(
+        schedule: ThresholdSchedule,
+        max_depth: usize,
+    ) -> Result<Self, SubscriptionRulesError> {
+        schedule.validate()?;
+        if max_depth == 0 {
+            return Err(SubscriptionRulesError::ZeroMaxDepth);
+        }
+
+        return Ok(Self {
+            schedule,
+            maximum_schedule: None,
             max_depth,
+        });
+    }
+    pub fn with_maximum_schedule(self: Self, maximum_schedule: ThresholdSchedule) -> Self {
+        match self.try_with_maximum_schedule(maximum_schedule) {
+            Ok(rules) => rules,
+            Err(error) => panic!("invalid subscription rules: {}", error),
+        }
+    }
+    pub fn try_with_maximum_schedule(
+        mut self: Self,
+        maximum_schedule: ThresholdSchedule,
+    ) -> Result<Self, SubscriptionRulesError> {
+        maximum_schedule.validate()?;
+        // past both prefixes the minimum stays the same and the maximum doesn't decrease, so one more index is enough
+        let checked_levels = match self.schedule.constant_from() {
+            Some(constant_from) => constant_from.max(maximum_schedule.irregular_prefix_len()) + 1,
+            None => usize::MAX,
+        };
+        let checked_levels = checked_levels.min(self.max_depth);
+        if checked_levels > MAX_DEPTH_LIMIT {
+            return Err(SubscriptionRulesError::MaxDepthTooLarge { max_depth: self.max_depth });
+        }
+        for index in 0..checked_levels {
+            let (maximum_amount, minimum_amount) = (maximum_schedule.get_amount(index), self.get_amount(index));
+            if maximum_amount < minimum_amount {
+                return Err(SubscriptionRulesError::MaximumBelowMinimum {
+                    index,
+                    maximum_amount,
+                    minimum_amount,
+                });
+            }
+        }
+        self.maximum_schedule = Some(maximum_schedule);
+        return Ok(self);
+    }
+}
+
+/// Fields to replace in shared default rules, missing ones are taken from the defaults
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionRulesOverride {
+    pub minimum_amounts: Option<Vec<Amount>>,
+    pub fallback: Option<Amount>,
+    pub schedule: Option<ThresholdSchedule>,
+    pub maximum_schedule: Option<ThresholdSchedule>,
+    pub max_depth: Option<usize>,
+}
+
+impl SubscriptionRulesOverride {
+    pub fn apply(
+        self: &Self,
+        defaults: &SubscriptionRules,
+    ) -> Result<SubscriptionRules, SubscriptionRulesError> {
+        let max_depth = self.max_depth.unwrap_or(defaults.max_depth);
+        let rules = self.apply_schedule(defaults, max_depth)?;
+        match self
+            .maximum_schedule
+            .as_ref()
+            .or(defaults.maximum_schedule.as_ref())
+        {
+            Some(maximum_schedule) => rules.try_with_maximum_schedule(maximum_schedule.clone()),
+            None => Ok(rules),
+        }
+    }
+    fn apply_schedule(
+        self: &Self,
+        defaults: &SubscriptionRules,
+        max_depth: usize,
+    ) -> Result<SubscriptionRules, SubscriptionRulesError> {
+        if self.minimum_amounts.is_none() && self.fallback.is_none() && self.schedule.is_none() {
+            return SubscriptionRules::try_with_schedule(defaults.schedule.clone(), max_depth);
+        }
+        // a partial table override takes the rest of the table from the defaults
+        let (default_amounts, default_fallback) = match &defaults.schedule {
+            ThresholdSchedule::Table { amounts, fallback } if self.schedule.is_none() => {
+                (Some(amounts.clone()), Some(*fallback))
+            }
+            _ => (None, None),
         };
+        let schedule = schedule_from_parts(
+            self.minimum_amounts.clone().or(default_amounts),
+            self.fallback.or(default_fallback),
+            self.schedule.clone(),
+        )?;
+        SubscriptionRules::try_with_schedule(schedule, max_depth)
+    }
+}
+
+/// Serialized form of the rules for both sides: top level fields are shared defaults,
+/// optional `bid` and `ask` objects override them for one side
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BookRulesConfig {
+    minimum_amounts: Option<Vec<Amount>>,
+    fallback: Option<Amount>,
+    schedule: Option<ThresholdSchedule>,
+    maximum_schedule: Option<ThresholdSchedule>,
+    max_depth: usize,
+    #[serde(default)]
+    bid: SubscriptionRulesOverride,
+    #[serde(default)]
+    ask: SubscriptionRulesOverride,
+}
+
+impl TryFrom<BookRulesConfig> for BookRules {
+    type Error = SubscriptionRulesError;
+
+    fn try_from(config: BookRulesConfig) -> Result<Self, Self::Error> {
+        let defaults = SubscriptionRules::try_from(SubscriptionRulesConfig {
+            minimum_amounts: config.minimum_amounts,
+            fallback: config.fallback,
+            schedule: config.schedule,
+            maximum_schedule: config.maximum_schedule,
+            max_depth: config.max_depth,
+        })?;
+        Self::with_overrides(&defaults, &config.bid, &config.ask)
+    }
+}
+
+/// Independent rules for the bid and ask ladders of one book
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "BookRulesConfig")]
+pub struct BookRules {
+    pub bid: SubscriptionRules,
+    pub ask: SubscriptionRules,
+}
+
+impl BookRules {
+    pub fn new(bid: SubscriptionRules, ask: SubscriptionRules) -> Self {
+        Self { bid, ask }
+    }
+    pub fn symmetric(rules: SubscriptionRules) -> Self {
+        Self {
+            bid: rules.clone(),
+            ask: rules,
+        }
+    }
+    pub fn with_overrides(
+        defaults: &SubscriptionRules,
+        bid: &SubscriptionRulesOverride,
+        ask: &SubscriptionRulesOverride,
+    ) -> Result<Self, SubscriptionRulesError> {
+        Ok(Self {
+            bid: bid.apply(defaults)?,
+            ask: ask.apply(defaults)?,
+        })
     }
 }
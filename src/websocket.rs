@@ -0,0 +1,467 @@
+use crate::common::*;
+use crate::feed::*;
+use crate::server::*;
+use crate::solutions::aggregated_l2_trait::aggregate_levels_linear;
+use crate::subscription::*;
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender, SyncSender, TryRecvError, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+use tungstenite::Message;
+
+/// Text messages from a client
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ClientRequest {
+    /// Replaces the previous subscription to the same instrument
+    Subscribe { instrument: String, rules: BookRules },
+    Unsubscribe { instrument: String },
+}
+
+/// Text messages to a client, the same as `ServerMessage` of the tcp server plus the instrument
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WebSocketMessage {
+    Snapshot {
+        instrument: String,
+        seq: u64,
+        bids: Vec<(u64, Amount)>,
+        asks: Vec<(u64, Amount)>,
+    },
+    Update {
+        instrument: String,
+        seq: u64,
+        side: Side,
+        from_index: usize,
+        levels: Vec<(u64, Amount)>,
+    },
+    EndOfFeed { instrument: String, seq: u64 },
+    Error { message: String },
+}
+
+impl WebSocketMessage {
+    pub fn new(instrument: &str, message: ServerMessage) -> Self {
+        let instrument = instrument.to_string();
+        match message {
+            ServerMessage::Snapshot { seq, bids, asks } => Self::Snapshot { instrument, seq, bids, asks },
+            ServerMessage::Update { seq, side, from_index, levels } => Self::Update {
+                instrument,
+                seq,
+                side,
+                from_index,
+                levels,
+            },
+            ServerMessage::EndOfFeed { seq } => Self::EndOfFeed { instrument, seq },
+        }
+    }
+    /// Instrument and the message for its `BookReplica`, `None` for errors
+    pub fn into_server_message(self) -> Option<(String, ServerMessage)> {
+        match self {
+            Self::Snapshot { instrument, seq, bids, asks } => {
+                Some((instrument, ServerMessage::Snapshot { seq, bids, asks }))
+            }
+            Self::Update { instrument, seq, side, from_index, levels } => Some((
+                instrument,
+                ServerMessage::Update { seq, side, from_index, levels },
+            )),
+            Self::EndOfFeed { instrument, seq } => Some((instrument, ServerMessage::EndOfFeed { seq })),
+            Self::Error { .. } => None,
+        }
+    }
+}
+
+type ClientId = u64;
+
+enum HubEvent {
+    Line { instrument: String, line: String },
+    FeedEnded,
+    FeedFailed { instrument: String, error: io::Error },
+    Connected { client: ClientId, messages: SyncSender<String> },
+    Request { client: ClientId, request: ClientRequest },
+    Disconnected { client: ClientId },
+}
+
+/// Aggregated ladders for one set of rules, shared by all clients subscribed with these rules
+struct Ladder {
+    rules: BookRules,
+    publisher: LadderPublisher,
+    clients: Vec<ClientId>,
+}
+
+impl Ladder {
+    /// Aggregates the raw book of the instrument again, walking at most `max_depth` raw levels per side
+    fn take_updates(
+        &mut self,
+        raw_bids: &BTreeMap<BidKey, Amount>,
+        raw_asks: &BTreeMap<AskKey, Amount>,
+    ) -> Vec<ServerMessage> {
+        let bids = aggregated_tuples(aggregate_levels_linear(raw_bids, &self.rules.bid));
+        let asks = aggregated_tuples(aggregate_levels_linear(raw_asks, &self.rules.ask));
+        [(Side::Bid, bids), (Side::Ask, asks)]
+            .into_iter()
+            .filter_map(|(side, ladder)| self.publisher.publish(side, ladder))
+            .collect()
+    }
+}
+
+fn aggregated_tuples<Price: OrderKey>(levels: Vec<AggregatedLevel<Price>>) -> Vec<(u64, Amount)> {
+    levels.into_iter().map(|level| (level.last_price.into(), level.total_amount)).collect()
+}
+
+/// The only copy of the raw book of one instrument, ladders of every set of rules are aggregated from it
+struct Instrument {
+    /// Quotes of the unfinished transaction, so a new ladder never starts from half of one
+    pending: Vec<(Side, u64, Amount)>,
+    raw_asks: BTreeMap<AskKey, Amount>,
+    raw_bids: BTreeMap<BidKey, Amount>,
+    ladders: Vec<Ladder>,
+    /// Sent to new subscribers once reading the feed has failed, the book stays at the last finished transaction
+    feed_error: Option<String>,
+}
+
+fn set_raw_level<Price: OrderKey>(raw_levels: &mut BTreeMap<Price, Amount>, price: u64, amount: Amount) {
+    if amount == 0 {
+        raw_levels.remove(&Price::from(price));
+    } else {
+        raw_levels.insert(Price::from(price), amount);
+    }
+}
+
+fn raw_levels_from<Price: OrderKey>(levels: ScaledLevels) -> BTreeMap<Price, Amount> {
+    levels
+        .into_iter()
+        .filter(|&(price, amount)| price > 0 && amount > 0)
+        .map(|(price, amount)| (Price::from(price), amount))
+        .collect()
+}
+
+impl Instrument {
+    fn apply_pending(&mut self) {
+        for (side, price, amount) in self.pending.drain(..) {
+            match side {
+                Side::Ask => set_raw_level(&mut self.raw_asks, price, amount),
+                Side::Bid => set_raw_level(&mut self.raw_bids, price, amount),
+            }
+        }
+    }
+    /// A snapshot replaces the whole book, quotes of the unfinished transaction before it are dropped
+    fn apply_snapshot(&mut self, bids: ScaledLevels, asks: ScaledLevels) {
+        self.pending.clear();
+        self.raw_bids = raw_levels_from(bids);
+        self.raw_asks = raw_levels_from(asks);
+    }
+    /// Updates of every ladder for the finished transactions, with the clients to send them to
+    fn take_updates(&mut self, instrument_name: &str) -> Vec<(ClientId, WebSocketMessage)> {
+        let mut outgoing = Vec::new();
+        for ladder in self.ladders.iter_mut() {
+            for update in ladder.take_updates(&self.raw_bids, &self.raw_asks) {
+                let message = WebSocketMessage::new(instrument_name, update);
+                outgoing.extend(ladder.clients.iter().map(|&client| (client, message.clone())));
+            }
+        }
+        outgoing
+    }
+}
+
+/// Owns all books, clients only talk to it through `HubEvent`s
+struct Hub {
+    instruments: BTreeMap<String, Instrument>,
+    clients: BTreeMap<ClientId, SyncSender<String>>,
+}
+
+impl Hub {
+    fn send(&mut self, client: ClientId, message: &WebSocketMessage) {
+        let Some(messages) = self.clients.get(&client) else {
+            return;
+        };
+        let text = serde_json::to_string(message).expect("message is always serializable");
+        match messages.try_send(text) {
+            Ok(()) => (),
+            // dropping the channel makes the client thread close the connection once it sends what is queued
+            Err(TrySendError::Full(_)) => {
+                eprintln!("Disconnecting a client {} messages behind", CLIENT_QUEUE_FRAMES);
+                self.disconnect(client);
+            }
+            // a closed channel is cleaned up by its `Disconnected` event
+            Err(TrySendError::Disconnected(_)) => (),
+        }
+    }
+
+    fn apply_line(&mut self, instrument_name: &str, line: &str) {
+        let message = match parse_feed_message(line) {
+            Ok(message) => message,
+            Err(error) => {
+                eprintln!("Skipping invalid line of {} {:?}: {}", instrument_name, line, error);
+                return;
+            }
+        };
+        let instrument = self.instruments.get_mut(instrument_name).expect("feeds are registered on start");
+        match &message {
+            FeedMessage::Delta(trade) => {
+                let Some((price, amount)) = trade.try_scaled() else {
+                    eprintln!("Skipping line of {} with invalid values {:?}", instrument_name, line);
+                    return;
+                };
+                instrument.pending.push((trade.side, price, amount));
+                if !trade.is_eot {
+                    return;
+                }
+                instrument.apply_pending();
+            }
+            FeedMessage::Snapshot(snapshot) => {
+                let Some((bids, asks)) = snapshot.try_scaled() else {
+                    eprintln!("Skipping snapshot of {} with invalid values {:?}", instrument_name, line);
+                    return;
+                };
+                instrument.apply_snapshot(bids, asks);
+            }
+        }
+        for (client, message) in instrument.take_updates(instrument_name) {
+            self.send(client, &message);
+        }
+    }
+
+    fn unsubscribe(&mut self, client: ClientId, instrument_name: &str) {
+        if let Some(instrument) = self.instruments.get_mut(instrument_name) {
+            for ladder in instrument.ladders.iter_mut() {
+                ladder.clients.retain(|&other| other != client);
+            }
+            instrument.ladders.retain(|ladder| !ladder.clients.is_empty());
+        }
+    }
+
+    fn subscribe(&mut self, client: ClientId, instrument_name: String, rules: BookRules) {
+        self.unsubscribe(client, &instrument_name);
+        let Some(instrument) = self.instruments.get_mut(&instrument_name) else {
+            let message = WebSocketMessage::Error {
+                message: format!("unknown instrument {:?}", instrument_name),
+            };
+            self.send(client, &message);
+            return;
+        };
+        if let Some(message) = instrument.feed_error.clone() {
+            self.send(client, &WebSocketMessage::Error { message });
+            return;
+        }
+        let index = match instrument.ladders.iter().position(|ladder| ladder.rules == rules) {
+            Some(index) => index,
+            None => {
+                let mut ladder = Ladder {
+                    rules,
+                    publisher: LadderPublisher::default(),
+                    clients: Vec::new(),
+                };
+                // nobody has seen the ladder yet, the first snapshot is built from these updates
+                ladder.take_updates(&instrument.raw_bids, &instrument.raw_asks);
+                instrument.ladders.push(ladder);
+                instrument.ladders.len() - 1
+            }
+        };
+        let ladder = &mut instrument.ladders[index];
+        ladder.clients.push(client);
+        let snapshot = WebSocketMessage::new(&instrument_name, ladder.publisher.snapshot());
+        self.send(client, &snapshot);
+    }
+
+    fn disconnect(&mut self, client: ClientId) {
+        self.clients.remove(&client);
+        let names: Vec<String> = self.instruments.keys().cloned().collect();
+        for name in names {
+            self.unsubscribe(client, &name);
+        }
+    }
+
+    /// Subscribers get an error instead of `EndOfFeed` and are unsubscribed, so a truncated feed never looks finished
+    fn fail_feed(&mut self, instrument_name: &str, error: io::Error) {
+        eprintln!("Cannot read the feed of {}: {}", instrument_name, error);
+        let instrument = self.instruments.get_mut(instrument_name).expect("feeds are registered on start");
+        instrument.pending.clear();
+        let clients: Vec<ClientId> = instrument.ladders.drain(..).flat_map(|ladder| ladder.clients).collect();
+        let message = format!("feed of {:?} failed: {}", instrument_name, error);
+        instrument.feed_error = Some(message.clone());
+        let message = WebSocketMessage::Error { message };
+        for client in clients {
+            self.send(client, &message);
+        }
+    }
+
+    fn end_of_feed(&mut self) {
+        let mut outgoing = Vec::new();
+        for (name, instrument) in self.instruments.iter_mut() {
+            // a feed may end in the middle of a transaction
+            instrument.apply_pending();
+            outgoing.extend(instrument.take_updates(name));
+            for ladder in instrument.ladders.iter_mut() {
+                let message = WebSocketMessage::new(name, ladder.publisher.end_of_feed());
+                outgoing.extend(ladder.clients.iter().map(|&client| (client, message.clone())));
+            }
+        }
+        for (client, message) in outgoing {
+            self.send(client, &message);
+        }
+    }
+}
+
+fn is_timeout(error: &tungstenite::Error) -> bool {
+    matches!(error, tungstenite::Error::Io(error)
+        if matches!(error.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut))
+}
+
+/// Reads requests and writes queued messages in turns, `poll_interval` bounds the delay of a message
+fn serve_client(stream: TcpStream, client: ClientId, events: Sender<HubEvent>, poll_interval: Duration) {
+    let Ok(mut socket) = tungstenite::accept(stream) else {
+        return;
+    };
+    if socket.get_ref().set_read_timeout(Some(poll_interval)).is_err() {
+        return;
+    }
+    let (messages, receiver) = mpsc::sync_channel(CLIENT_QUEUE_FRAMES);
+    if events.send(HubEvent::Connected { client, messages }).is_err() {
+        return;
+    }
+    'serving: loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let event = match serde_json::from_str::<ClientRequest>(&text) {
+                    Ok(request) => HubEvent::Request { client, request },
+                    Err(error) => {
+                        let message = WebSocketMessage::Error { message: error.to_string() };
+                        let text = serde_json::to_string(&message).expect("message is always serializable");
+                        if socket.send(Message::text(text)).is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                if events.send(event).is_err() {
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => (),
+            Err(error) if is_timeout(&error) => (),
+            Err(_) => break,
+        }
+        loop {
+            match receiver.try_recv() {
+                Ok(text) => {
+                    if socket.send(Message::text(text)).is_err() {
+                        break 'serving;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    break 'serving;
+                }
+            }
+        }
+    }
+    let _ = events.send(HubEvent::Disconnected { client });
+}
+
+/// Serves aggregated books of several instruments over WebSocket until all feeds end.
+/// Every client subscribes with its own rules, clients with equal rules share one ladder.
+/// A client more than `CLIENT_QUEUE_FRAMES` messages behind is disconnected. If reading a feed fails,
+/// its subscribers get an `Error` instead of `EndOfFeed` and the other feeds go on
+pub fn run_websocket_server(
+    listener: TcpListener,
+    feeds: Vec<(String, Box<dyn BufRead + Send>)>,
+    poll_interval: Duration,
+) -> io::Result<()> {
+    let (events, receiver) = mpsc::channel();
+    let mut hub = Hub {
+        instruments: BTreeMap::new(),
+        clients: BTreeMap::new(),
+    };
+    let mut running_feeds = feeds.len();
+    for (instrument, feed) in feeds {
+        hub.instruments.insert(
+            instrument.clone(),
+            Instrument {
+                pending: Vec::new(),
+                raw_asks: BTreeMap::new(),
+                raw_bids: BTreeMap::new(),
+                ladders: Vec::new(),
+                feed_error: None,
+            },
+        );
+        let events = events.clone();
+        std::thread::spawn(move || {
+            for line in feed.lines() {
+                let event = match line {
+                    Ok(line) => HubEvent::Line { instrument: instrument.clone(), line },
+                    Err(error) => {
+                        let _ = events.send(HubEvent::FeedFailed { instrument, error });
+                        return;
+                    }
+                };
+                if events.send(event).is_err() {
+                    return;
+                }
+            }
+            let _ = events.send(HubEvent::FeedEnded);
+        });
+    }
+
+    listener.set_nonblocking(true)?;
+    let is_stopped = Arc::new(AtomicBool::new(false));
+    let acceptor = {
+        let events = events.clone();
+        let is_stopped = is_stopped.clone();
+        std::thread::spawn(move || {
+            let mut next_client = 0;
+            while !is_stopped.load(Ordering::Relaxed) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let events = events.clone();
+                        next_client += 1;
+                        let client = next_client;
+                        std::thread::spawn(move || {
+                            if stream.set_nonblocking(false).is_ok() {
+                                serve_client(stream, client, events, poll_interval);
+                            }
+                        });
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::WouldBlock => std::thread::sleep(poll_interval),
+                    Err(error) => {
+                        eprintln!("Cannot accept: {}", error);
+                        std::thread::sleep(poll_interval);
+                    }
+                }
+            }
+        })
+    };
+    drop(events);
+
+    while running_feeds > 0 {
+        match receiver.recv().expect("feed threads hold senders until they end") {
+            HubEvent::Line { instrument, line } => hub.apply_line(&instrument, &line),
+            HubEvent::FeedEnded => running_feeds -= 1,
+            HubEvent::FeedFailed { instrument, error } => {
+                hub.fail_feed(&instrument, error);
+                running_feeds -= 1;
+            }
+            HubEvent::Connected { client, messages } => {
+                hub.clients.insert(client, messages);
+            }
+            HubEvent::Request { client, request } => match request {
+                ClientRequest::Subscribe { instrument, rules } => hub.subscribe(client, instrument, rules),
+                ClientRequest::Unsubscribe { instrument } => hub.unsubscribe(client, &instrument),
+            },
+            HubEvent::Disconnected { client } => hub.disconnect(client),
+        }
+    }
+    hub.end_of_feed();
+    // dropping the channels makes client threads close their connections
+    hub.clients.clear();
+    is_stopped.store(true, Ordering::Relaxed);
+    let _ = acceptor.join();
+    Ok(())
+}
@@ -0,0 +1,31 @@
+//! Fixtures shared by the test binaries, every binary uses only some of them
+#![allow(dead_code)]
+
+use market_data_aggregator::subscription::*;
+
+use std::io::{self, Read};
+
+/// Same rules for both sides, levels of 1 and then 5 in the units of the feed
+pub fn rules() -> BookRules {
+    BookRules::symmetric(SubscriptionRules::new(vec![1e8 as u64], 5e8 as u64, 40))
+}
+
+/// Coarser and deeper asks, so that a mix up of the sides shows
+pub fn asymmetric_rules() -> BookRules {
+    BookRules::new(
+        SubscriptionRules::new(vec![1e8 as u64], 5e8 as u64, 40),
+        SubscriptionRules::new(vec![2e8 as u64, 1e9 as u64], 3e9 as u64, 60),
+    )
+}
+
+/// Fails instead of ending, like a feed connection that drops
+pub struct FailingAtEnd<R>(pub R);
+
+impl<R: Read> Read for FailingAtEnd<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf)? {
+            0 if !buf.is_empty() => Err(io::Error::new(io::ErrorKind::ConnectionReset, "feed connection lost")),
+            read => Ok(read),
+        }
+    }
+}
@@ -0,0 +1,158 @@
+pub use market_data_aggregator::conflation::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::server::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+mod common;
+use common::rules;
+
+use std::time::Duration;
+
+type Publisher = ConflatingPublisher<AggregatedL2<AskKey>, AggregatedL2<BidKey>, SimulatedClock>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_finished_transactions_are_published() {
+        let clock = SimulatedClock::default();
+        let mut publisher = Publisher::new(rules(), clock.clone());
+        let id = publisher.subscribe(Duration::from_millis(10));
+        assert_eq!(publisher.next_delivery(id), Some(Duration::ZERO));
+        let mut replica = BookReplica::default();
+        for message in publisher.poll(id) {
+            replica.apply(message).unwrap();
+        }
+        assert_eq!(replica.seq, Some(0));
+        assert_eq!(publisher.next_delivery(id), None);
+
+        publisher.set_quote(Side::Bid, 100, 3e8 as u64);
+        clock.advance(Duration::from_millis(20));
+        assert!(publisher.poll(id).is_empty());
+        publisher.set_quote(Side::Ask, 110, 2e8 as u64);
+        publisher.end_transaction();
+        assert_eq!(publisher.next_delivery(id), Some(Duration::from_millis(10)));
+        for message in publisher.poll(id) {
+            replica.apply(message).unwrap();
+        }
+        assert_eq!(replica.bids, [(100, 3e8 as u64)]);
+        assert_eq!(replica.asks, [(110, 2e8 as u64)]);
+
+        // rate limited until 30ms, then both transactions arrive as one state
+        publisher.set_quote(Side::Bid, 100, 4e8 as u64);
+        publisher.end_transaction();
+        clock.advance(Duration::from_millis(5));
+        publisher.set_quote(Side::Bid, 100, 6e8 as u64);
+        publisher.end_transaction();
+        assert!(publisher.poll(id).is_empty());
+        assert_eq!(publisher.next_delivery(id), Some(Duration::from_millis(30)));
+        clock.advance(Duration::from_millis(5));
+        let updates = publisher.poll(id);
+        assert_eq!(updates.len(), 1);
+        for message in updates {
+            replica.apply(message).unwrap();
+        }
+        assert_eq!(replica.bids, [(100, 6e8 as u64)]);
+
+        // changes that cancel each other out are merged into nothing
+        publisher.set_quote(Side::Ask, 110, 0);
+        publisher.end_transaction();
+        publisher.set_quote(Side::Ask, 110, 2e8 as u64);
+        publisher.end_transaction();
+        clock.advance(Duration::from_millis(10));
+        assert!(publisher.poll(id).is_empty());
+        assert_eq!(publisher.next_delivery(id), None);
+
+        for message in publisher.end_of_feed(id) {
+            replica.apply(message).unwrap();
+        }
+        assert!(replica.is_finished);
+        let stats = publisher.stats(id).unwrap();
+        assert_eq!(
+            *stats,
+            ConflationStats {
+                transactions: 5,
+                deliveries: 2,
+                conflated: 3,
+            }
+        );
+        assert!(publisher.unsubscribe(id));
+        assert!(publisher.poll(id).is_empty());
+    }
+
+    #[test]
+    fn test_unscalable_trade_still_ends_the_transaction() {
+        let clock = SimulatedClock::default();
+        let mut publisher = Publisher::new(rules(), clock.clone());
+        let id = publisher.subscribe(Duration::ZERO);
+        publisher.poll(id);
+        let trade = |price, amount, is_eot| Trade {
+            platform_time: 1,
+            exchange_time: 1,
+            seq_no: None,
+            side: Side::Bid,
+            price,
+            amount,
+            is_eot,
+        };
+        assert!(publisher.apply_trade(&trade(100.0, 3.0, false)));
+        assert!(!publisher.apply_trade(&trade(101.0, 1e30, true)));
+        assert_eq!(publisher.book().get_aggregated_levels_tuples(Side::Bid), [(100e8 as u64, 3e8 as u64)]);
+        assert_eq!(publisher.stats(id).unwrap().transactions, 1);
+        assert_eq!(publisher.poll(id).len(), 1);
+    }
+
+    #[test]
+    fn test_slow_subscriber_gets_the_latest_state() {
+        let trades = read_trades("l2.json");
+        let clock = SimulatedClock::default();
+        let mut publisher = Publisher::new(rules(), clock.clone());
+        let interval = Duration::from_millis(10);
+        let fast = publisher.subscribe(Duration::ZERO);
+        let slow = publisher.subscribe(interval);
+        let mut replicas = [BookReplica::default(), BookReplica::default()];
+        let mut slow_deliveries = Vec::new();
+
+        for trade in trades.iter() {
+            publisher.apply_trade(trade);
+            if !trade.is_eot {
+                continue;
+            }
+            clock.advance(Duration::from_millis(1));
+            for (id, replica) in [fast, slow].into_iter().zip(replicas.iter_mut()) {
+                let messages = publisher.poll(id);
+                if messages.is_empty() {
+                    continue;
+                }
+                if id == slow {
+                    slow_deliveries.push(clock.now());
+                }
+                for message in messages {
+                    replica.apply(message).unwrap();
+                }
+                // every delivery is a state after a whole transaction, not a mix of older ones
+                assert_eq!(replica.bids, publisher.book().get_aggregated_levels_tuples(Side::Bid));
+                assert_eq!(replica.asks, publisher.book().get_aggregated_levels_tuples(Side::Ask));
+            }
+        }
+        for pair in slow_deliveries.windows(2) {
+            assert!(pair[1] - pair[0] >= interval);
+        }
+        publisher.end_transaction();
+        for (id, replica) in [fast, slow].into_iter().zip(replicas.iter_mut()) {
+            for message in publisher.end_of_feed(id) {
+                replica.apply(message).unwrap();
+            }
+            assert!(replica.is_finished);
+            assert_eq!(replica.bids, publisher.book().get_aggregated_levels_tuples(Side::Bid));
+            assert_eq!(replica.asks, publisher.book().get_aggregated_levels_tuples(Side::Ask));
+            let stats = publisher.stats(id).unwrap();
+            assert_eq!(stats.deliveries + stats.conflated, stats.transactions);
+        }
+        assert_eq!(publisher.stats(fast).unwrap().conflated, 0);
+        let slow_stats = publisher.stats(slow).unwrap();
+        assert!(slow_stats.conflated > 0);
+        assert!(slow_stats.deliveries < publisher.stats(fast).unwrap().deliveries);
+    }
+}
@@ -0,0 +1,149 @@
+pub use market_data_aggregator::csv_feed::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::ingest::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+const TARDIS: &str = "\
+exchange,symbol,timestamp,local_timestamp,is_snapshot,side,price,amount
+binance,BTCUSDT,1000,1005,true,bid,100.5,2
+binance,BTCUSDT,1000,1005,true,ask,101,1.5
+binance,BTCUSDT,2000,2003,false,ask,101,0
+binance,BTCUSDT,2000,2003,false,ask,101.25,3
+binance,BTCUSDT,2500,2510,false,bid,100.75,0.125
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_positional_layout_matches_json() {
+        let trades = read_trades("l2.json");
+        let mut csv = String::new();
+        for trade in trades.iter() {
+            let seq_no = trade.seq_no.map_or(String::new(), |seq_no| seq_no.to_string());
+            csv += &format!(
+                "{},{},{},{:?},{},{},{}\n",
+                trade.platform_time, trade.exchange_time, seq_no, trade.side, trade.price, trade.amount, trade.is_eot
+            );
+        }
+        let rows: Vec<CsvRow> = CsvFeedReader::new(csv.as_bytes(), CsvLayout::positional())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(rows.len(), trades.len());
+        for (row, trade) in rows.iter().zip(trades.iter()) {
+            assert_eq!(format_trade(&row.trade), format_trade(trade));
+            assert!(!row.is_snapshot);
+        }
+    }
+
+    #[test]
+    fn test_tardis_layout() {
+        let rows: Vec<CsvRow> = CsvFeedReader::new(TARDIS.as_bytes(), CsvLayout::tardis())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let summary: Vec<(u64, u64, Side, f64, f64, bool, bool)> = rows
+            .iter()
+            .map(|row| {
+                let trade = &row.trade;
+                (
+                    trade.platform_time,
+                    trade.exchange_time,
+                    trade.side,
+                    trade.price,
+                    trade.amount,
+                    trade.is_eot,
+                    row.is_snapshot,
+                )
+            })
+            .collect();
+        // transactions are rows with the same local timestamp, the snapshot is one of them
+        assert_eq!(
+            summary,
+            [
+                (1_005_000, 1_000_000, Side::Bid, 100.5, 2.0, false, true),
+                (1_005_000, 1_000_000, Side::Ask, 101.0, 1.5, true, true),
+                (2_003_000, 2_000_000, Side::Ask, 101.0, 0.0, false, false),
+                (2_003_000, 2_000_000, Side::Ask, 101.25, 3.0, true, false),
+                (2_510_000, 2_500_000, Side::Bid, 100.75, 0.125, true, false),
+            ]
+        );
+        assert!(rows.iter().all(|row| row.trade.seq_no.is_none()));
+    }
+
+    #[test]
+    fn test_tardis_messages() {
+        let messages: Vec<FeedMessage> = CsvFeedReader::new(TARDIS.as_bytes(), CsvLayout::tardis())
+            .unwrap()
+            .messages()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let lines: Vec<String> = messages.iter().map(format_feed_message).collect();
+        assert_eq!(
+            lines,
+            [
+                r#"{"snapshot":{"platform_time":1005000,"exchange_time":1000000,"seq_no":null,"bids":[[100.5,2.0]],"asks":[[101.0,1.5]]}}"#,
+                r#"[2003000,2000000,null,"Ask",101.0,0.0,false]"#,
+                r#"[2003000,2000000,null,"Ask",101.25,3.0,true]"#,
+                r#"[2510000,2500000,null,"Bid",100.75,0.125,true]"#,
+            ]
+        );
+
+        let mut ingest = FeedIngest::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(BookRules::symmetric(
+            SubscriptionRules::new(vec![1e8 as u64], 1e8 as u64, 10),
+        ));
+        for message in messages.iter() {
+            ingest.apply_message(message);
+        }
+        assert_eq!(ingest.stats().snapshots, 1);
+        assert!(ingest.book().bid().raw_levels().eq([(10_075_000_000, 12_500_000), (10_050_000_000, 200_000_000)]));
+        assert!(ingest.book().ask().raw_levels().eq([(10_125_000_000, 300_000_000)]));
+    }
+
+    #[test]
+    fn test_custom_layout_from_json() {
+        let layout: CsvLayout = serde_json::from_str(
+            r#"{
+                "columns": {"platform_time": "ts", "seq_no": "id", "side": 1, "price": "px", "amount": "qty"},
+                "delimiter": ";",
+                "time_unit": "millis",
+                "bid_values": ["B"],
+                "ask_values": ["S"]
+            }"#,
+        )
+        .unwrap();
+        let csv = "ts;dir;px;qty;id\n7;b;10.5;1;41\n7;S;11;2;42\n9;B;10.5;0;\n";
+        let rows: Vec<CsvRow> = CsvFeedReader::new(csv.as_bytes(), layout.clone())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let trades: Vec<String> = rows.iter().map(|row| format_trade(&row.trade)).collect();
+        assert_eq!(
+            trades,
+            [
+                r#"[7000000,7000000,41,"Bid",10.5,1.0,false]"#,
+                r#"[7000000,7000000,42,"Ask",11.0,2.0,true]"#,
+                r#"[9000000,9000000,null,"Bid",10.5,0.0,true]"#,
+            ]
+        );
+
+        let mut reader = CsvFeedReader::new("ts;dir;px;qty;id\n7;x;10.5;1;41\n".as_bytes(), layout.clone()).unwrap();
+        let error = reader.next().unwrap().unwrap_err();
+        assert!(
+            matches!(&error, CsvError::InvalidValue { line: 2, field: "side", value } if value == "x"),
+            "{}",
+            error
+        );
+        assert!(reader.next().is_none());
+
+        let error = CsvFeedReader::new("ts;dir;price;qty;id\n".as_bytes(), layout.clone()).err().unwrap();
+        assert!(matches!(error, CsvError::MissingColumn { name } if name == "px"));
+        let no_header = CsvLayout {
+            has_header: false,
+            ..layout
+        };
+        assert!(matches!(CsvFeedReader::new("".as_bytes(), no_header), Err(CsvError::NoHeader)));
+    }
+}
@@ -0,0 +1,72 @@
+pub use market_data_aggregator::common::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cumulative_views() {
+        let table = SubscriptionRules::new([3, 5, 15].into(), 1, 999);
+        let mut solution = AggregatedL2::<BidKey>::new(table);
+        for (price, amount) in [(7, 2), (6, 2), (4, 1), (3, 4), (2, 8), (1, 10)] {
+            solution.set_quote(price, amount);
+        }
+        assert_eq!(solution.get_cumulative_aggregated_levels(), [(6, 4), (3, 9), (1, 27)]);
+        assert_eq!(
+            solution.get_cumulative_levels().collect::<Vec<_>>(),
+            [(7, 2), (6, 4), (4, 5), (3, 9), (2, 17), (1, 27)]
+        );
+        solution.enable_cumulative_index();
+        assert_eq!(solution.cumulative_amount_up_to(5), 4);
+        assert_eq!(solution.cumulative_amount_up_to(8), 0);
+        assert_eq!(solution.price_for_cumulative_amount(5), Some(4));
+        assert_eq!(solution.price_for_cumulative_amount(6), Some(3));
+        assert_eq!(solution.price_for_cumulative_amount(28), None);
+    }
+
+    fn run_stress<Price: OrderKey>()
+    where
+        u64: From<Price>,
+    {
+        let table = SubscriptionRules::new(vec![2, 6, 15], 12, 30);
+        let mut fast_solution = AggregatedL2::<Price>::new(table.clone());
+        let mut slow_solution = SlowAggregatedL2ForComparisons::<Price>::new(table);
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        for step in 0..30000 {
+            if step == 1000 {
+                fast_solution.enable_cumulative_index();
+            }
+            let price = rng.gen_range(1..=200);
+            let amount: u64 = if rng.gen_bool(0.3) { 0 } else { rng.gen_range(1..=17) };
+            fast_solution.set_quote(price, amount);
+            slow_solution.set_quote(price, amount);
+
+            let query_price = rng.gen_range(0..=201);
+            assert_eq!(
+                fast_solution.cumulative_amount_up_to(query_price),
+                slow_solution.cumulative_amount_up_to(query_price)
+            );
+            let query_amount = rng.gen_range(0..=800);
+            assert_eq!(
+                fast_solution.price_for_cumulative_amount(query_amount),
+                slow_solution.price_for_cumulative_amount(query_amount)
+            );
+        }
+    }
+
+    #[test]
+    fn test_stress_ask() {
+        run_stress::<AskKey>();
+    }
+
+    #[test]
+    fn test_stress_bid() {
+        run_stress::<BidKey>();
+    }
+}
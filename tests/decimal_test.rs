@@ -0,0 +1,94 @@
+pub use market_data_aggregator::common::*;
+pub use market_data_aggregator::decimal::*;
+pub use market_data_aggregator::feed::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_and_parse() {
+        let cases = [
+            (0, "0"),
+            (1, "0.00000001"),
+            (150_000_000, "1.5"),
+            (165_911_000_000, "1659.11"),
+            (100_000_000_000, "1000"),
+            (u64::MAX, "184467440737.09551615"),
+        ];
+        for (value, text) in cases {
+            assert_eq!(format_scaled(value), text);
+            assert_eq!(parse_scaled(text), Ok(value));
+        }
+        assert_eq!(parse_scaled("1.50"), Ok(150_000_000));
+        assert_eq!(parse_scaled("007"), Ok(700_000_000));
+
+        assert_eq!(parse_scaled(""), Err(DecimalError::Empty));
+        assert_eq!(parse_scaled("1.2.3"), Err(DecimalError::InvalidCharacter { position: 3 }));
+        assert_eq!(parse_scaled("-1"), Err(DecimalError::InvalidCharacter { position: 0 }));
+        assert_eq!(parse_scaled("1."), Err(DecimalError::InvalidCharacter { position: 2 }));
+        assert_eq!(parse_scaled(".5"), Err(DecimalError::InvalidCharacter { position: 0 }));
+        assert_eq!(parse_scaled("1e5"), Err(DecimalError::InvalidCharacter { position: 1 }));
+        assert_eq!(
+            parse_scaled("0.000000001"),
+            Err(DecimalError::TooManyDecimals { decimals: 9, max: 8 })
+        );
+        assert_eq!(parse_scaled("184467440737.09551616"), Err(DecimalError::Overflow));
+        assert_eq!(parse_scaled("99999999999999999999999"), Err(DecimalError::Overflow));
+    }
+
+    #[test]
+    fn test_other_scales() {
+        let cents = Scale::new(2);
+        assert_eq!(cents.format(12345), "123.45");
+        assert_eq!(cents.format(12340), "123.4");
+        assert_eq!(cents.parse("123.4"), Ok(12340));
+        assert_eq!(cents.parse("1.234"), Err(DecimalError::TooManyDecimals { decimals: 3, max: 2 }));
+        let integers = Scale::new(0);
+        assert_eq!(integers.format(42), "42");
+        assert_eq!(integers.parse("42.0"), Err(DecimalError::TooManyDecimals { decimals: 1, max: 0 }));
+        assert_eq!(Scale::FEED.ratio() as f64, SCALE_RATIO);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(AskKey::from(165_911_000_000).to_string(), "1659.11");
+        assert_eq!(format!("{:>8}|{:<6}|", BidKey::from(150_000_000), AskKey::from(0)), "     1.5|0     |");
+        let level = AggregatedLevel {
+            last_price: BidKey::from(165_900_000_000),
+            total_amount: 186_328_064,
+        };
+        assert_eq!(level.to_string(), "1.86328064 @ 1659");
+        assert_eq!(
+            Scale::FEED.format_levels([(165_911_000_000, 1)]),
+            [("1659.11".to_string(), "0.00000001".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        for _ in 0..100000 {
+            let value: u64 = rng.gen();
+            assert_eq!(parse_scaled(&format_scaled(value)), Ok(value));
+        }
+        // too large for multiplying by 1e8 in floating point, scaled through the decimal text instead
+        let trade = parse_trade("[0, 0, null, \"Ask\", 2733013.5789196, 7061392.41604605, true]").unwrap();
+        assert_eq!(trade.try_scaled(), Some((273_301_357_891_960, 706_139_241_604_605)));
+        // values the feed can carry exactly also survive the json parser of the feed
+        for _ in 0..100000 {
+            let price = rng.gen_range(1..1u64 << 50);
+            let amount = rng.gen_range(0..1u64 << 50);
+            let line = format!(
+                "[0, 0, null, \"Bid\", {}, {}, true]",
+                format_scaled(price),
+                format_scaled(amount)
+            );
+            let trade = parse_trade(&line).unwrap();
+            assert_eq!(trade.try_scaled(), Some((price, amount)), "{}", line);
+        }
+    }
+}
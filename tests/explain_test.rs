@@ -0,0 +1,111 @@
+pub use market_data_aggregator::common::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Sources have to tile the raw book within max depth, sharing at most one raw level between neighbours
+fn check_sources<Price: OrderKey, Solution: AgregatedL2Trait<Price>>(solution: &Solution) {
+    let mut covered: Vec<(u64, Amount)> = Vec::new();
+    for index in 0..solution.get_aggregated_levels().len() {
+        let source = solution.get_aggregated_level_source(index).unwrap();
+        assert_eq!(source.index, index);
+        assert_eq!(source.last_price(), Some(solution.get_aggregated_levels()[index].last_price.into()));
+        let total_amount = source.total_amount;
+        let raw_levels: Vec<(u64, Amount)> = source.collect();
+        let whole_amount: Amount = raw_levels.iter().map(|&(_, amount)| amount).sum();
+        if solution.get_subscription_rules().has_maximum_amounts() {
+            assert!(whole_amount >= total_amount);
+        } else {
+            assert_eq!(whole_amount, total_amount);
+        }
+        let mut raw_levels = raw_levels.into_iter().peekable();
+        if covered.last() == raw_levels.peek() {
+            raw_levels.next();
+        }
+        for (price, _) in raw_levels.clone() {
+            assert_eq!(solution.find_aggregated_level_index(price), Some(index));
+        }
+        covered.extend(raw_levels);
+    }
+    let expected: Vec<(u64, Amount)> = solution
+        .raw_levels()
+        .take(solution.get_subscription_rules().max_depth)
+        .collect();
+    assert_eq!(covered, expected);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explain_levels() {
+        let table = SubscriptionRules::new(vec![20], 20, 5);
+        let mut solution = AggregatedL2::<AskKey>::new(table);
+        for price in 1..=6 {
+            solution.set_quote(price, 10);
+        }
+        let source = solution.get_aggregated_level_source(1).unwrap();
+        assert_eq!((source.first_price(), source.last_price()), (Some(3), Some(4)));
+        assert_eq!(source.collect::<Vec<_>>(), [(3, 10), (4, 10)]);
+        assert_eq!(solution.get_aggregated_level_source(2).unwrap().collect::<Vec<_>>(), [(5, 10)]);
+        assert!(solution.get_aggregated_level_source(3).is_none());
+
+        assert_eq!(solution.find_aggregated_level_index(1), Some(0));
+        assert_eq!(solution.find_aggregated_level_index(4), Some(1));
+        // beyond max depth and absent prices
+        assert_eq!(solution.find_aggregated_level_index(6), None);
+        assert_eq!(solution.find_aggregated_level_index(7), None);
+    }
+
+    #[test]
+    fn test_explain_split_levels() {
+        let table = SubscriptionRules::new(vec![], 10, 10).with_maximum_schedule(ThresholdSchedule::Table {
+            amounts: vec![],
+            fallback: 15,
+        });
+        let mut solution = SlowAggregatedL2ForComparisons::<BidKey>::new(table);
+        solution.set_quote(9, 25);
+        solution.set_quote(8, 10);
+        assert_eq!(solution.get_aggregated_levels_tuples(), [(9, 15), (9, 10), (8, 10)]);
+        assert_eq!(solution.get_aggregated_level_source(0).unwrap().collect::<Vec<_>>(), [(9, 25)]);
+        assert_eq!(solution.get_aggregated_level_source(1).unwrap().collect::<Vec<_>>(), [(9, 25)]);
+        assert_eq!(solution.get_aggregated_level_source(2).unwrap().collect::<Vec<_>>(), [(8, 10)]);
+        assert_eq!(solution.find_aggregated_level_index(9), Some(0));
+        assert_eq!(solution.find_aggregated_level_index(8), Some(2));
+    }
+
+    fn run_stress<Price: OrderKey>(table: SubscriptionRules)
+    where
+        u64: From<Price>,
+    {
+        let mut fast_solution = AggregatedL2::<Price>::new(table.clone());
+        let mut slow_solution = SlowAggregatedL2ForComparisons::<Price>::new(table);
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        for step in 0..5000 {
+            let price = rng.gen_range(1..=60);
+            let amount: u64 = if rng.gen_bool(0.3) { 0 } else { rng.gen_range(1..=17) };
+            fast_solution.set_quote(price, amount);
+            slow_solution.set_quote(price, amount);
+            if step % 10 == 0 {
+                check_sources(&fast_solution);
+                check_sources(&slow_solution);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stress_sources() {
+        run_stress::<AskKey>(SubscriptionRules::new(vec![2, 6, 15], 12, 30));
+        run_stress::<BidKey>(SubscriptionRules::new(vec![2, 6, 15], 12, 30));
+        let capped = SubscriptionRules::new(vec![5], 8, 30).with_maximum_schedule(ThresholdSchedule::Arithmetic {
+            start: 8,
+            step: 2,
+            cap: Some(20),
+        });
+        run_stress::<AskKey>(capped.clone());
+        run_stress::<BidKey>(capped);
+    }
+}
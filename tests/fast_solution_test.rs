@@ -1,5 +1,6 @@
 #![feature(btree_cursors)]
 #![feature(map_try_insert)]
+#![allow(unused_features)]
 
 pub use market_data_aggregator::common::*;
 pub use market_data_aggregator::solutions::fast::*;
@@ -7,6 +8,7 @@ pub use market_data_aggregator::solutions::slow_for_comparisons::*;
 
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
+use std::ops::Bound;
 
 #[cfg(test)]
 mod tests {
@@ -27,6 +29,25 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_borrowing_iterators() {
+        let table = SubscriptionRules::new([3, 5, 15].into(), 1, 999);
+        let mut solution = AggregatedL2::<BidKey>::new(table);
+        for (price, amount) in [(7, 2), (6, 2), (4, 1), (3, 4), (2, 8), (1, 10)] {
+            solution.set_quote(price, amount);
+        }
+        assert_eq!(solution.best_price(), Some(7));
+        assert_eq!(solution.raw_levels_count(), 6);
+        assert_eq!(solution.get_raw_amount(3), Some(4));
+        assert_eq!(solution.get_raw_amount(5), None);
+        // bounds follow the book order, for bids from the higher price
+        let range = solution.raw_levels_range((Bound::Excluded(6), Bound::Included(3)));
+        assert_eq!(range.clone().collect::<Vec<_>>(), [(4, 1), (3, 4)]);
+        assert_eq!(range.rev().collect::<Vec<_>>(), [(3, 4), (4, 1)]);
+        assert_eq!(solution.aggregated_levels().len(), 3);
+        assert_eq!(solution.aggregated_levels().collect::<Vec<_>>(), [(6, 4), (3, 5), (1, 18)]);
+    }
+
     #[test]
     fn test_simple_with_removes() {
         let table = SubscriptionRules::new([2, 5, 3].into(), 1, 2);
@@ -46,11 +67,10 @@ mod tests {
         assert_eq!(solution.get_aggregated_levels_tuples(), [(3, 2)]);
     }
 
-    fn run_stress<Price: OrderKey>()
+    fn run_stress<Price: OrderKey>(table: SubscriptionRules)
     where
         u64: From<Price>,
     {
-        let table = SubscriptionRules::new(vec![2, 6, 15, 8, 80], 12, 30);
         let mut fast_solution = AggregatedL2::<Price>::new(table.clone());
         let mut slow_solution = SlowAggregatedL2ForComparisons::<Price>::new(table.clone());
 
@@ -67,7 +87,7 @@ mod tests {
             fast_solution.set_quote(price, amount);
             slow_solution.set_quote(price, amount);
 
-            assert!(*fast_solution.get_levels() == *slow_solution.get_levels());
+            assert!(fast_solution.raw_levels().eq(slow_solution.raw_levels()));
             assert!(
                 *fast_solution.get_aggregated_levels() == *slow_solution.get_aggregated_levels()
             );
@@ -75,13 +95,173 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_change_rules() {
+        let mut fast_solution = AggregatedL2::<BidKey>::new(SubscriptionRules::new(vec![2, 6], 12, 30));
+        let mut slow_solution =
+            SlowAggregatedL2ForComparisons::<BidKey>::new(SubscriptionRules::new(vec![2, 6], 12, 30));
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+
+        for step in 0..20000 {
+            if step % 1000 == 0 {
+                let depth = rng.gen_range(1..=40);
+                let table = SubscriptionRules::new(vec![rng.gen_range(1..=20); 3], rng.gen_range(1..=20), depth);
+                fast_solution.set_subscription_rules(table.clone());
+                slow_solution.set_subscription_rules(table);
+            }
+            let price = rng.gen_range(1..=42);
+            let amount: u64 = rng.gen_range(0..=17);
+            fast_solution.set_quote(price, amount);
+            slow_solution.set_quote(price, amount);
+
+            assert!(*fast_solution.get_aggregated_levels() == *slow_solution.get_aggregated_levels());
+            assert!(fast_solution.get_max_depth_price() == slow_solution.get_max_depth_price());
+            fast_solution
+                .check_invariants()
+                .unwrap_or_else(|report| panic!("{}", report));
+        }
+    }
+
     #[test]
     fn test_stress_ask() {
-        run_stress::<AskKey>();
+        run_stress::<AskKey>(SubscriptionRules::new(vec![2, 6, 15, 8, 80], 12, 30));
     }
 
     #[test]
     fn test_stress_bid() {
-        run_stress::<BidKey>();
+        run_stress::<BidKey>(SubscriptionRules::new(vec![2, 6, 15, 8, 80], 12, 30));
+    }
+
+    #[test]
+    fn test_maximum_amounts_split_levels() {
+        let maximum = ThresholdSchedule::Table {
+            amounts: vec![4, 10],
+            fallback: 6,
+        };
+        let table = SubscriptionRules::new([3, 5, 6].into(), 1, 999).with_maximum_schedule(maximum);
+        let mut solution = AggregatedL2::<AskKey>::new(table);
+        for (price, amount) in [(1, 2), (2, 2), (4, 1), (5, 4), (6, 8), (7, 10)] {
+            solution.set_quote(price, amount);
+        }
+        assert_eq!(
+            solution.get_aggregated_levels_tuples(),
+            [(2, 4), (5, 5), (6, 6), (6, 2), (7, 6), (7, 4)]
+        );
+        solution.set_quote(1, 30);
+        assert_eq!(
+            solution.get_aggregated_levels_tuples(),
+            [(1, 4), (1, 10), (1, 6), (1, 6), (1, 4), (2, 2), (4, 1), (5, 4), (6, 6), (6, 2), (7, 6), (7, 4)]
+        );
+    }
+
+    #[test]
+    fn test_maximum_amounts_stop_at_max_depth() {
+        let maximum = ThresholdSchedule::Table {
+            amounts: vec![],
+            fallback: 2,
+        };
+        let table = SubscriptionRules::new(vec![1], 1, 3).with_maximum_schedule(maximum);
+        let mut solution = AggregatedL2::<AskKey>::new(table.clone());
+        let mut slow_solution = SlowAggregatedL2ForComparisons::<AskKey>::new(table);
+        for (price, amount) in [(5, 1_000_000_000), (1, 3), (1, 0)] {
+            solution.set_quote(price, amount);
+            slow_solution.set_quote(price, amount);
+            assert_eq!(solution.get_aggregated_levels(), slow_solution.get_aggregated_levels());
+            solution.check_invariants().unwrap();
+        }
+        assert_eq!(solution.get_aggregated_levels_tuples(), [(5, 2), (5, 2), (5, 2)]);
+    }
+
+    #[test]
+    fn test_stress_maximum_amounts() {
+        let maximum = ThresholdSchedule::Table {
+            amounts: vec![3, 20, 15, 9, 85],
+            fallback: 15,
+        };
+        run_stress::<AskKey>(SubscriptionRules::new(vec![2, 6, 15, 8, 80], 12, 30).with_maximum_schedule(maximum));
+        let maximum = ThresholdSchedule::Arithmetic {
+            start: 2,
+            step: 3,
+            cap: Some(25),
+        };
+        run_stress::<BidKey>(SubscriptionRules::new(vec![2, 3, 4, 5, 6], 12, 30).with_maximum_schedule(maximum));
+    }
+
+    #[test]
+    fn test_stress_schedule() {
+        let schedule = ThresholdSchedule::TableThen {
+            amounts: vec![2, 6],
+            then: Box::new(ThresholdSchedule::Geometric {
+                start: 3,
+                ratio: 1.7,
+                cap: Some(40),
+            }),
+        };
+        run_stress::<AskKey>(SubscriptionRules::with_schedule(schedule, 30));
+        let schedule = ThresholdSchedule::Arithmetic {
+            start: 1,
+            step: 4,
+            cap: None,
+        };
+        run_stress::<BidKey>(SubscriptionRules::with_schedule(schedule, 35));
+    }
+
+    fn run_from_sorted_levels<Price: OrderKey>(table: SubscriptionRules)
+    where
+        u64: From<Price>,
+    {
+        let mut rng = ChaCha8Rng::seed_from_u64(9);
+        for _ in 0..100 {
+            let mut built = AggregatedL2::<Price>::new(table.clone());
+            for _ in 0..rng.gen_range(0..80) {
+                built.set_quote(rng.gen_range(1..=200), rng.gen_range(0..=17));
+            }
+            // zero amounts in the input are skipped like removals
+            let levels: Vec<(u64, Amount)> = built
+                .raw_levels()
+                .map(|(price, amount)| (price, if rng.gen_bool(0.1) { 0 } else { amount }))
+                .collect();
+            let mut expected = AggregatedL2::<Price>::new(table.clone());
+            for &(price, amount) in levels.iter() {
+                expected.set_quote(price, amount);
+            }
+            let loaded = AggregatedL2::<Price>::from_sorted_levels(table.clone(), levels.iter().copied());
+            loaded.check_invariants().unwrap();
+            assert!(loaded.raw_levels().eq(expected.raw_levels()));
+            assert_eq!(loaded.get_aggregated_levels(), expected.get_aggregated_levels());
+            assert_eq!(loaded.get_max_depth_price(), expected.get_max_depth_price());
+            let slow = SlowAggregatedL2ForComparisons::<Price>::from_sorted_levels(table.clone(), levels);
+            assert_eq!(slow.get_aggregated_levels(), expected.get_aggregated_levels());
+        }
+    }
+
+    #[test]
+    fn test_from_sorted_levels() {
+        run_from_sorted_levels::<AskKey>(SubscriptionRules::new(vec![2, 6, 15], 12, 30));
+        run_from_sorted_levels::<BidKey>(SubscriptionRules::new(vec![2, 6, 15], 12, 5));
+        let maximum = ThresholdSchedule::Arithmetic {
+            start: 2,
+            step: 3,
+            cap: Some(25),
+        };
+        let table = SubscriptionRules::new(vec![2, 3, 4, 5, 6], 12, 30).with_maximum_schedule(maximum);
+        run_from_sorted_levels::<BidKey>(table);
+    }
+
+    #[test]
+    fn test_from_unsorted_levels() {
+        let table = SubscriptionRules::new(vec![2], 5, 3);
+        let levels = [(1, 3), (4, 2), (3, 0), (2, 6), (5, 1)];
+        let mut expected = AggregatedL2::<AskKey>::new(table.clone());
+        for (price, amount) in levels {
+            expected.set_quote(price, amount);
+        }
+        let loaded = AggregatedL2::<AskKey>::from_sorted_levels(table.clone(), levels);
+        loaded.check_invariants().unwrap();
+        assert!(loaded.raw_levels().eq(expected.raw_levels()));
+        assert_eq!(loaded.get_aggregated_levels(), expected.get_aggregated_levels());
+        assert_eq!(loaded.get_max_depth_price(), expected.get_max_depth_price());
+        let slow = SlowAggregatedL2ForComparisons::<AskKey>::from_sorted_levels(table, levels);
+        assert_eq!(slow.get_aggregated_levels(), expected.get_aggregated_levels());
     }
 }
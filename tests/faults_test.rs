@@ -0,0 +1,179 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::faults::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::generator::*;
+pub use market_data_aggregator::ingest::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+mod common;
+use common::rules;
+
+type Book = AggregatedBook<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+type Ingest = FeedIngest<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+
+fn clean_feed(transactions: usize) -> Vec<Trade> {
+    let model = MarketModel {
+        seed: 11,
+        transactions,
+        ..MarketModel::default()
+    };
+    FeedGenerator::new(model).unwrap().collect()
+}
+
+fn assert_same_books(actual: &Book, expected: &Book) {
+    assert!(actual.ask().raw_levels().eq(expected.ask().raw_levels()));
+    assert!(actual.bid().raw_levels().eq(expected.bid().raw_levels()));
+    assert_eq!(actual.ask().get_aggregated_levels(), expected.ask().get_aggregated_levels());
+    assert_eq!(actual.bid().get_aggregated_levels(), expected.bid().get_aggregated_levels());
+}
+
+/// Feeds the faulty stream, resyncing from the clean exchange state every time the ingest notices a problem
+fn run_with_resyncs(config: FaultConfig, feed: &[Trade]) -> (Ingest, InjectedFaults, Vec<IngestEvent>) {
+    let lines = feed.iter().map(format_trade).collect::<Vec<_>>();
+    let mut injector = FaultInjector::new(lines.into_iter(), config).unwrap();
+    let mut exchange = Book::new(rules());
+    let mut exchange_position = 0;
+    let mut ingest = Ingest::new(rules());
+    let mut problems = Vec::new();
+
+    let mut take_snapshot = |exchange: &mut Book, position: u64| {
+        while exchange_position < position {
+            exchange.apply_trade(&feed[exchange_position as usize]);
+            exchange_position += 1;
+        }
+        BookSnapshot::from_book(exchange, position)
+    };
+    while let Some(line) = injector.next() {
+        let event = ingest.apply_line(&line);
+        if event.is_problem() {
+            assert!(ingest.is_stale());
+            problems.push(event);
+            let snapshot = take_snapshot(&mut exchange, injector.source_position());
+            ingest.resync(&snapshot);
+            assert_same_books(ingest.book(), &exchange);
+        }
+    }
+    // a lost tail is only visible against the exchange sequence
+    if ingest.expected_seq_no() != Some(feed.len() as u64 + 1) {
+        ingest.resync(&take_snapshot(&mut exchange, feed.len() as u64));
+    }
+    take_snapshot(&mut exchange, feed.len() as u64);
+    assert!(!ingest.is_stale());
+    assert_same_books(ingest.book(), &exchange);
+    let injected = injector.injected().clone();
+    (ingest, injected, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_faults_pass_through() {
+        let feed = clean_feed(500);
+        let lines: Vec<String> = feed.iter().map(format_trade).collect();
+        let injector = FaultInjector::new(lines.clone().into_iter(), FaultConfig::default()).unwrap();
+        assert_eq!(injector.collect::<Vec<_>>(), lines);
+
+        let (ingest, injected, problems) = run_with_resyncs(FaultConfig::default(), &feed);
+        assert_eq!(injected.total(), 0);
+        assert!(problems.is_empty());
+        assert_eq!(ingest.stats().applied, feed.len() as u64);
+    }
+
+    #[test]
+    fn test_every_fault_is_detected() {
+        let feed = clean_feed(300);
+        let lines: Vec<String> = feed.iter().map(format_trade).collect();
+
+        let mut dropped = lines.clone();
+        dropped.remove(10);
+        let mut duplicated = lines.clone();
+        duplicated.insert(10, lines[10].clone());
+        let mut reordered = lines.clone();
+        reordered.swap(10, 11);
+        let mut corrupted = lines.clone();
+        corrupted[10].truncate(20);
+        let mut jumped = feed.clone();
+        jumped[10].seq_no = Some(50);
+
+        let first_event = |lines: &[String]| {
+            let mut ingest = Ingest::new(rules());
+            lines
+                .iter()
+                .map(|line| ingest.apply_line(line))
+                .find(|event| *event != IngestEvent::Applied)
+        };
+        assert_eq!(first_event(&dropped), Some(IngestEvent::Gap { expected: 11, received: 12 }));
+        assert_eq!(first_event(&duplicated), Some(IngestEvent::Duplicate { seq_no: 11 }));
+        assert_eq!(first_event(&reordered), Some(IngestEvent::Gap { expected: 11, received: 12 }));
+        assert!(matches!(first_event(&corrupted), Some(IngestEvent::Corrupt { .. })));
+        let jumped: Vec<String> = jumped.iter().map(format_trade).collect();
+        assert_eq!(first_event(&jumped), Some(IngestEvent::Gap { expected: 11, received: 50 }));
+
+        let mut negative = feed[0].clone();
+        negative.amount = -1.0;
+        assert!(Ingest::new(rules()).apply_trade(&negative).is_problem());
+
+        // numbering starts over after the last seq_no
+        let mut ingest = Ingest::new(rules());
+        let mut last = feed[0].clone();
+        last.seq_no = Some(u64::MAX);
+        assert_eq!(ingest.apply_trade(&last), IngestEvent::Applied);
+        assert_eq!(ingest.expected_seq_no(), None);
+        let mut first = feed[1].clone();
+        first.seq_no = Some(0);
+        assert_eq!(ingest.apply_trade(&first), IngestEvent::Applied);
+    }
+
+    #[test]
+    fn test_invalid_fault_config() {
+        let config = FaultConfig {
+            corrupt_probability: 1.5,
+            ..FaultConfig::default()
+        };
+        assert_eq!(
+            FaultInjector::new(Vec::new().into_iter(), config).err(),
+            Some(FaultConfigError::InvalidProbability { field: "corrupt_probability" })
+        );
+        let config = FaultConfig {
+            drop_probability: f64::NAN,
+            ..FaultConfig::default()
+        };
+        assert!(FaultInjector::new(Vec::new().into_iter(), config).is_err());
+    }
+
+    #[test]
+    fn test_duplicates_keep_the_book_in_sync() {
+        let config = FaultConfig {
+            seed: 1,
+            duplicate_probability: 0.1,
+            ..FaultConfig::default()
+        };
+        let feed = clean_feed(2000);
+        let (ingest, injected, problems) = run_with_resyncs(config, &feed);
+        assert!(injected.duplicated > 0);
+        assert!(problems.is_empty());
+        assert_eq!(ingest.stats().duplicates, injected.duplicated);
+    }
+
+    #[test]
+    fn test_recovers_after_resync() {
+        for seed in 0..5 {
+            let config = FaultConfig {
+                seed,
+                drop_probability: 0.01,
+                duplicate_probability: 0.01,
+                reorder_probability: 0.01,
+                corrupt_probability: 0.01,
+                seq_gap_probability: 0.01,
+            };
+            let feed = clean_feed(3000);
+            let (ingest, injected, problems) = run_with_resyncs(config, &feed);
+            assert!(injected.dropped > 0 && injected.reordered > 0 && injected.corrupted > 0 && injected.seq_gaps > 0);
+            assert!(!problems.is_empty());
+            assert!(ingest.stats().resyncs >= problems.len() as u64);
+            assert_eq!(ingest.stats().gaps + ingest.stats().corrupt, problems.len() as u64);
+        }
+    }
+}
@@ -0,0 +1,102 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::generator::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small_model(seed: u64) -> MarketModel {
+        MarketModel {
+            seed,
+            transactions: 2000,
+            sweep_probability: 0.05,
+            ..MarketModel::default()
+        }
+    }
+
+    fn generate_lines(model: MarketModel) -> Vec<String> {
+        FeedGenerator::new(model).unwrap().map(|trade| format_trade(&trade)).collect()
+    }
+
+    #[test]
+    fn test_same_seed_same_feed() {
+        assert_eq!(generate_lines(small_model(1)), generate_lines(small_model(1)));
+        assert_ne!(generate_lines(small_model(1)), generate_lines(small_model(2)));
+    }
+
+    #[test]
+    fn test_transactions_and_sequence_numbers() {
+        let trades: Vec<Trade> = FeedGenerator::new(small_model(3)).unwrap().collect();
+        assert_eq!(trades.iter().filter(|trade| trade.is_eot).count(), 2000);
+        assert!(trades.last().unwrap().is_eot);
+        for (index, trade) in trades.iter().enumerate() {
+            assert_eq!(trade.seq_no, Some(index as u64 + 1));
+            assert!(trade.platform_time > trade.exchange_time);
+        }
+        for pair in trades.windows(2) {
+            assert!(pair[0].exchange_time <= pair[1].exchange_time);
+        }
+    }
+
+    #[test]
+    fn test_lines_parse_back() {
+        for line in generate_lines(small_model(4)) {
+            let trade = parse_trade(&line).unwrap();
+            assert_eq!(format_trade(&trade), line);
+            assert_eq!(trade.scaled_price() % 1_000_000, 0);
+            assert_eq!(trade.scaled_amount() % 10_000, 0);
+        }
+    }
+
+    #[test]
+    fn test_replay_is_never_crossed() {
+        let rules = BookRules::symmetric(SubscriptionRules::new(vec![5e7 as u64, 1e8 as u64], 3e8 as u64, 100));
+        let mut fast = AggregatedBook::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(rules.clone());
+        let mut slow = AggregatedBook::<SlowAggregatedL2ForComparisons<AskKey>, SlowAggregatedL2ForComparisons<BidKey>>::new(
+            rules,
+        );
+        for trade in FeedGenerator::new(small_model(5)).unwrap() {
+            fast.apply_trade(&trade);
+            slow.apply_trade(&trade);
+            if !trade.is_eot {
+                continue;
+            }
+            assert!(!fast.is_crossed());
+            for side in [Side::Bid, Side::Ask] {
+                assert_eq!(fast.get_aggregated_levels_tuples(side), slow.get_aggregated_levels_tuples(side));
+            }
+        }
+        assert!(fast.ask().raw_levels_count() > 10);
+        assert!(fast.bid().raw_levels_count() > 10);
+        fast.ask().check_invariants().unwrap();
+        fast.bid().check_invariants().unwrap();
+    }
+
+    #[test]
+    fn test_invalid_models() {
+        let error = |model: MarketModel| FeedGenerator::new(model).err().unwrap();
+        assert_eq!(
+            error(MarketModel { tick_size: 0.0, ..MarketModel::default() }),
+            MarketModelError::NotPositive { field: "tick_size" }
+        );
+        assert_eq!(
+            error(MarketModel { lot_size: 1e-9, ..MarketModel::default() }),
+            MarketModelError::NotScalable { field: "lot_size" }
+        );
+        assert_eq!(
+            error(MarketModel { initial_mid: 1660.005, ..MarketModel::default() }),
+            MarketModelError::MidOffTick
+        );
+        assert_eq!(
+            error(MarketModel { cancel_probability: 1.5, ..MarketModel::default() }),
+            MarketModelError::InvalidProbability { field: "cancel_probability" }
+        );
+
+        let model: MarketModel = serde_json::from_str(r#"{"seed": 7, "transactions": 10}"#).unwrap();
+        assert_eq!(model, MarketModel { seed: 7, transactions: 10, ..MarketModel::default() });
+        assert!(serde_json::from_str::<MarketModel>(r#"{"sead": 7}"#).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::golden::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+use std::path::Path;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A golden with a single level per side would not notice broken aggregation
+    fn has_several_levels_per_side(snapshots: &str) -> bool {
+        snapshots.split("# transaction ").any(|transaction| {
+            let count = |side: &str| transaction.lines().filter(|line| line.starts_with(side)).count();
+            count("Bid ") > 1 && count("Ask ") > 1
+        })
+    }
+
+    /// Both implementations have to produce exactly the recorded output
+    fn check_feed(feed: &str, rules: BookRules, golden_file: &str) {
+        let trades = read_trades(feed);
+        let path = Path::new("tests/golden").join(golden_file);
+
+        let fast = render_snapshots::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(&trades, rules.clone());
+        assert!(has_several_levels_per_side(&fast), "{} never has several levels per side", golden_file);
+        let slow = render_snapshots::<SlowAggregatedL2ForComparisons<AskKey>, SlowAggregatedL2ForComparisons<BidKey>>(
+            &trades, rules,
+        );
+        // checked before the golden, so that updating it never records one of two diverging outputs
+        if let Some(diff) = diff_snapshots(&fast, &slow) {
+            panic!("fast and slow solutions differ (- fast, + slow):\n{}", diff);
+        }
+        check_golden(&path, &fast).unwrap_or_else(|diff| panic!("{}", diff));
+    }
+
+    #[test]
+    fn test_l2_symmetric() {
+        let rules = BookRules::symmetric(SubscriptionRules::new(
+            vec![1e8 as u64, 5e8 as u64, 3e8 as u64, 2e8 as u64],
+            4e8 as u64,
+            10,
+        ));
+        check_feed("l2.json", rules, "l2_symmetric.txt");
+    }
+
+    #[test]
+    fn test_l2_asymmetric_with_maximum() {
+        let bid = SubscriptionRules::new(vec![2e8 as u64], 6e8 as u64, 10);
+        let ask = SubscriptionRules::with_schedule(
+            ThresholdSchedule::Geometric {
+                start: 1e8 as u64,
+                ratio: 1.5,
+                cap: Some(1e9 as u64),
+            },
+            15,
+        )
+        .with_maximum_schedule(ThresholdSchedule::Arithmetic {
+            start: 3e8 as u64,
+            step: 3e8 as u64,
+            cap: Some(2e9 as u64),
+        });
+        check_feed("l2.json", BookRules::new(bid, ask), "l2_asymmetric_with_maximum.txt");
+    }
+
+    #[test]
+    fn test_diff_is_readable() {
+        let expected = "# transaction 1, lines 1..=1\nBid 5 1\n# transaction 2, lines 2..=2\nBid 5 1\nAsk 7 2\n";
+        let actual = "# transaction 1, lines 1..=1\nBid 5 1\n# transaction 2, lines 2..=2\nBid 5 1\nAsk 7 3\n";
+        assert_eq!(diff_snapshots(expected, expected), None);
+        assert_eq!(
+            diff_snapshots(expected, actual).unwrap(),
+            "  # transaction 2, lines 2..=2\n  Bid 5 1\n- Ask 7 2\n+ Ask 7 3\n\n1 transaction(s) differ\n"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::hot_reload::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+mod common;
+use common::FailingAtEnd;
+
+use std::io::BufReader;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Aggregator = HotReloadAggregator<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+
+    const CONFIG: &str = r#"{"subscriptions": {
+        "client_a": {"minimum_amounts": [3, 5, 15], "fallback": 1, "max_depth": 999},
+        "client_b": {"minimum_amounts": [1], "fallback": 1, "max_depth": 2}
+    }}"#;
+
+    fn filled_aggregator() -> Aggregator {
+        let mut aggregator = Aggregator::new();
+        aggregator.apply_rules(RulesConfig::parse(CONFIG).unwrap());
+        for (price, amount) in [(1, 2), (2, 2), (4, 1), (5, 4), (6, 8), (7, 10)] {
+            aggregator.set_quote(Side::Ask, price, amount);
+        }
+        aggregator
+    }
+
+    #[test]
+    fn test_invalid_configs_are_rejected() {
+        assert!(RulesConfig::parse(r#"{"subscriptions": {"a": {"minimum_amounts": [0], "fallback": 1, "max_depth": 1}}}"#).is_err());
+        assert!(RulesConfig::parse(r#"{"subscriptions": {"a": {"minimum_amounts": [1], "fallback": 1, "max_depth": 0}}}"#).is_err());
+        assert!(RulesConfig::parse(r#"{"subscriptions": {"a": {"minimum_amounts": [1], "fallback": 1}}}"#).is_err());
+        assert!(RulesConfig::parse("{").is_err());
+    }
+
+    #[test]
+    fn test_only_changed_subscriptions_are_refreshed() {
+        let mut aggregator = filled_aggregator();
+        let ask = &aggregator.get_subscription("client_a").unwrap().ask();
+        assert_eq!(ask.get_aggregated_levels_tuples(), [(2, 4), (5, 5), (7, 18)]);
+
+        let config = RulesConfig::parse(r#"{"subscriptions": {
+            "client_a": {"minimum_amounts": [5], "fallback": 20, "max_depth": 999},
+            "client_b": {"minimum_amounts": [1], "fallback": 1, "max_depth": 2}
+        }}"#);
+        let refreshes = aggregator.apply_rules(config.unwrap());
+        assert_eq!(
+            refreshes,
+            [
+                LadderRefresh {
+                    subscription: "client_a".into(),
+                    side: Side::Bid,
+                    levels: vec![],
+                },
+                LadderRefresh {
+                    subscription: "client_a".into(),
+                    side: Side::Ask,
+                    levels: vec![(4, 5), (7, 22)],
+                },
+            ]
+        );
+        let ask = &aggregator.get_subscription("client_b").unwrap().ask();
+        assert_eq!(ask.get_aggregated_levels_tuples(), [(1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn test_new_subscription_is_built_from_current_book() {
+        let mut aggregator = filled_aggregator();
+        let config = RulesConfig::parse(r#"{"subscriptions": {
+            "client_c": {"minimum_amounts": [3, 5, 15], "fallback": 1, "max_depth": 999}
+        }}"#);
+        let refreshes = aggregator.apply_rules(config.unwrap());
+        assert_eq!(refreshes[1].levels, [(2, 4), (5, 5), (7, 18)]);
+        assert!(aggregator.get_subscription("client_a").is_none());
+    }
+
+    #[test]
+    fn test_side_overrides() {
+        let config = RulesConfig::parse(r#"{"subscriptions": {"client": {
+            "minimum_amounts": [3, 5, 15], "fallback": 1, "max_depth": 999,
+            "bid": {"minimum_amounts": [1]}
+        }}}"#);
+        let rules = &config.unwrap().subscriptions["client"];
+        assert_eq!(rules.ask, SubscriptionRules::new(vec![3, 5, 15], 1, 999));
+        assert_eq!(rules.bid, SubscriptionRules::new(vec![1], 1, 999));
+
+        let mut aggregator = filled_aggregator();
+        let config = CONFIG.replace(r#""max_depth": 2}"#, r#""max_depth": 2, "ask": {"max_depth": 1}}"#);
+        let refreshes = aggregator.apply_rules(RulesConfig::parse(&config).unwrap());
+        assert_eq!(
+            refreshes,
+            [LadderRefresh {
+                subscription: "client_b".into(),
+                side: Side::Ask,
+                levels: vec![(1, 2)],
+            }]
+        );
+        assert!(RulesConfig::parse(&config.replace(r#""max_depth": 1"#, r#""max_depth": 0"#)).is_err());
+        assert!(RulesConfig::parse(&config.replace(r#""max_depth": 1"#, r#""depth": 1"#)).is_err());
+    }
+
+    #[test]
+    fn test_watcher_keeps_rules_after_invalid_change() {
+        let path = std::env::temp_dir().join(format!("rules_{}.json", std::process::id()));
+        std::fs::write(&path, CONFIG).unwrap();
+        let mut watcher = RulesWatcher::new(&path);
+        let mut aggregator = Aggregator::new();
+        aggregator.apply_rules(watcher.poll().unwrap().unwrap());
+        assert!(watcher.poll().is_none());
+
+        std::fs::write(&path, CONFIG.replace("\"max_depth\": 2", "\"max_depth\": 0")).unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+        assert!(watcher.poll().is_none());
+        aggregator.set_quote(Side::Bid, 5, 3);
+        let bid = &aggregator.get_subscription("client_b").unwrap().bid();
+        assert_eq!(bid.get_subscription_rules().max_depth, 2);
+        assert_eq!(bid.get_aggregated_levels_tuples(), [(5, 3)]);
+
+        std::fs::write(&path, CONFIG.replace("\"max_depth\": 2", "\"max_depth\": 3")).unwrap();
+        let refreshes = aggregator.apply_rules(watcher.poll().unwrap().unwrap());
+        assert_eq!(refreshes.len(), 2);
+        assert!(refreshes.iter().all(|refresh| refresh.subscription == "client_b"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_watcher_reports_missing_file() {
+        let path = std::env::temp_dir().join(format!("missing_rules_{}.json", std::process::id()));
+        let mut watcher = RulesWatcher::new(&path);
+        assert!(matches!(watcher.poll(), Some(Err(RulesConfigError::Io(_)))));
+        assert!(matches!(watcher.poll(), Some(Err(RulesConfigError::Io(_)))));
+
+        std::fs::write(&path, CONFIG).unwrap();
+        assert!(watcher.poll().unwrap().is_ok());
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(watcher.poll(), Some(Err(RulesConfigError::Io(_)))));
+        assert!(watcher.poll().is_none());
+    }
+
+    #[test]
+    fn test_unscalable_trade_is_skipped() {
+        let mut aggregator = filled_aggregator();
+        let trade = parse_trade("[0, 0, null, \"Ask\", -1.0, 1.0, true]").unwrap();
+        assert!(!aggregator.apply_trade(&trade));
+        let trade = parse_trade("[0, 0, null, \"Ask\", 1e-8, 1e-9, true]").unwrap();
+        assert!(!aggregator.apply_trade(&trade));
+        let ask = &aggregator.get_subscription("client_a").unwrap().ask();
+        assert_eq!(ask.get_aggregated_levels_tuples(), [(2, 4), (5, 5), (7, 18)]);
+    }
+
+    #[test]
+    fn test_feed_error_is_returned() {
+        let path = std::env::temp_dir().join(format!("failing_feed_rules_{}.json", std::process::id()));
+        std::fs::write(&path, CONFIG).unwrap();
+        let feed = BufReader::new(FailingAtEnd("[0, 0, null, \"Ask\", 1.0, 1.0, true]\n".as_bytes()));
+        let mut output = Vec::new();
+        let result = run_hot_reload::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+            path.to_str().unwrap(),
+            feed,
+            &mut output,
+            Duration::from_millis(1),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(HotReloadError::Feed(_))));
+    }
+
+    #[test]
+    fn test_feed_snapshot_replaces_raw_levels() {
+        let mut aggregator = filled_aggregator();
+        let snapshot = |asks| {
+            let line = format!(
+                r#"{{"snapshot": {{"platform_time": 0, "exchange_time": 0, "seq_no": null, "bids": [[5e-8, 3e-8]], "asks": {}}}}}"#,
+                asks
+            );
+            match parse_feed_message(&line).unwrap() {
+                FeedMessage::Snapshot(snapshot) => snapshot,
+                FeedMessage::Delta(_) => unreachable!(),
+            }
+        };
+        assert!(!aggregator.apply_feed_snapshot(&snapshot("[[-1.0, 1.0]]")));
+        assert!(aggregator.apply_feed_snapshot(&snapshot("[[4e-8, 0.0], [2e-8, 6e-8]]")));
+        let book = aggregator.get_subscription("client_a").unwrap();
+        assert_eq!(book.ask().get_aggregated_levels_tuples(), [(2, 6)]);
+        assert_eq!(book.bid().get_aggregated_levels_tuples(), [(5, 3)]);
+
+        // a new subscription starts from the snapshot, not from the levels before it
+        let config = CONFIG.replace("\"client_b\"", "\"client_c\"");
+        aggregator.apply_rules(RulesConfig::parse(&config).unwrap());
+        let ask = &aggregator.get_subscription("client_c").unwrap().ask();
+        assert_eq!(ask.get_aggregated_levels_tuples(), [(2, 6)]);
+    }
+}
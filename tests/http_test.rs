@@ -0,0 +1,126 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::decimal::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::http::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+mod common;
+use common::{rules, FailingAtEnd};
+
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+
+type Registry = BookRegistry<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+
+fn json(response: &HttpResponse) -> serde_json::Value {
+    serde_json::from_str(&response.body).unwrap()
+}
+
+/// Registry with `l2.json` fully ingested as BTC
+fn ingested_registry() -> Arc<Registry> {
+    let registry = Arc::new(Registry::new());
+    let feed = BufReader::new(File::open("l2.json").unwrap());
+    registry.spawn_feed("BTC", rules(), feed).join().unwrap();
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_book_and_stats() {
+        let registry = ingested_registry();
+        let trades = read_trades("l2.json");
+        let mut expected = AggregatedBook::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(rules());
+        for trade in trades.iter() {
+            expected.apply_trade(trade);
+        }
+
+        let response = handle_request(&registry, "GET", "/books/BTC?depth=3");
+        assert_eq!(response.status, 200);
+        let book = registry.book("BTC", 3).unwrap();
+        assert_eq!(json(&response), serde_json::to_value(&book).unwrap());
+        assert_eq!(book.raw.bids.len(), 3);
+        assert_eq!(book.raw.asks[0].0, Scale::FEED.format(expected.ask().best_price().unwrap()));
+        let aggregated_bids = Scale::FEED.format_levels(expected.get_aggregated_levels_tuples(Side::Bid));
+        assert_eq!(book.aggregated.bids, aggregated_bids);
+        assert_eq!(book.seq_no, None);
+        // exact decimals in the units of the feed, never rounded through f64
+        assert_eq!(json(&response)["raw"]["asks"][0][0], serde_json::json!("1659.27"));
+
+        let stats = json(&handle_request(&registry, "GET", "/stats"));
+        let stats = &stats["BTC"];
+        assert_eq!(stats["applied"], trades.len());
+        assert_eq!(stats["transactions"], trades.iter().filter(|trade| trade.is_eot).count());
+        assert_eq!(stats["raw_asks"], expected.ask().raw_levels_count());
+        assert_eq!(stats["aggregated_bids"], aggregated_bids.len());
+        assert_eq!(stats["is_feed_running"], false);
+        assert_eq!(stats["feed_error"], serde_json::Value::Null);
+
+        assert_eq!(handle_request(&registry, "GET", "/health").status, 200);
+        assert_eq!(handle_request(&registry, "GET", "/books/ETH").status, 404);
+        assert_eq!(handle_request(&registry, "GET", "/orders").status, 404);
+        assert_eq!(handle_request(&registry, "GET", "/books/BTC?depth=all").status, 400);
+        assert_eq!(handle_request(&registry, "POST", "/stats").status, 405);
+    }
+
+    #[test]
+    fn test_gap_makes_health_fail() {
+        let registry = Arc::new(Registry::new());
+        let feed = "[1, 1, 1, \"Bid\", 10.5, 1.0, true]\n[2, 2, 3, \"Bid\", 10.5, 2.0, true]\n";
+        registry.spawn_feed("ETH", rules(), feed.as_bytes()).join().unwrap();
+        let response = handle_request(&registry, "GET", "/health");
+        assert_eq!(response.status, 503);
+        assert_eq!(json(&response)["stale"], serde_json::json!(["ETH"]));
+        let book = registry.book("ETH", usize::MAX).unwrap();
+        assert!(book.is_stale);
+        assert_eq!(book.seq_no, Some(1));
+        assert_eq!(book.raw.bids, [("10.5".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn test_feed_error_makes_health_fail() {
+        let registry = Arc::new(Registry::new());
+        let feed = "[1, 1, 1, \"Bid\", 10.5, 1.0, true]\n[2, 2, 2, \"Bid\", 11.5, 2.0, false]\n";
+        registry.spawn_feed("ETH", rules(), BufReader::new(FailingAtEnd(feed.as_bytes()))).join().unwrap();
+        let response = handle_request(&registry, "GET", "/health");
+        assert_eq!(response.status, 503);
+        assert_eq!(json(&response)["failed"], serde_json::json!(["ETH"]));
+        assert_eq!(json(&response)["stale"], serde_json::json!([]));
+        let stats = json(&handle_request(&registry, "GET", "/stats"));
+        assert_eq!(stats["ETH"]["feed_error"], "feed connection lost");
+        assert_eq!(stats["ETH"]["transactions"], 1);
+        // the unfinished second transaction is never applied
+        let book = registry.book("ETH", usize::MAX).unwrap();
+        assert_eq!(book.seq_no, Some(1));
+        assert_eq!(book.raw.bids, [("10.5".to_string(), "1".to_string())]);
+    }
+
+    #[test]
+    fn test_over_http() {
+        let registry = ingested_registry();
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0").unwrap());
+        let address = server.server_addr().to_ip().unwrap();
+        let handle = {
+            let (server, registry) = (server.clone(), registry.clone());
+            std::thread::spawn(move || run_http_server(&server, &registry))
+        };
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET /books/BTC?depth=1 HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("application/json"));
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let book: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(book["instrument"], "BTC");
+        assert_eq!(book["raw"]["bids"].as_array().unwrap().len(), 1);
+
+        server.unblock();
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,133 @@
+pub use market_data_aggregator::common::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+use std::collections::BTreeMap;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(last_price: u64, total_amount: Amount) -> AggregatedLevel<AskKey> {
+        AggregatedLevel {
+            last_price: AskKey::from(last_price),
+            total_amount,
+        }
+    }
+
+    fn check(
+        levels: &[(u64, Amount)],
+        aggregated_levels: &[AggregatedLevel<AskKey>],
+        max_depth_price: AskKey,
+        rules: &SubscriptionRules,
+    ) -> Vec<InvariantViolation> {
+        let levels: BTreeMap<AskKey, Amount> = levels
+            .iter()
+            .map(|&(price, amount)| (AskKey::from(price), amount))
+            .collect();
+        check_aggregation_invariants(&levels, aggregated_levels, max_depth_price, rules, &mut Vec::new())
+    }
+
+    #[test]
+    fn test_valid_state() {
+        let rules = SubscriptionRules::new([3, 5, 15].into(), 1, 999);
+        let levels = [(1, 2), (2, 2), (4, 1), (5, 4), (6, 8), (7, 10)];
+        let aggregated_levels = [level(2, 4), level(5, 5), level(7, 18)];
+        assert_eq!(check(&levels, &aggregated_levels, AskKey::MAX, &rules), []);
+    }
+
+    #[test]
+    fn test_violations_are_reported() {
+        let rules = SubscriptionRules::new([3, 5, 15].into(), 1, 5);
+        let levels = [(1, 2), (2, 2), (4, 1), (5, 4), (6, 8), (7, 10)];
+
+        let violations = check(&levels, &[level(1, 2), level(6, 15)], AskKey::MAX, &rules);
+        assert_eq!(
+            violations,
+            [
+                InvariantViolation::WrongMaxDepthPrice {
+                    expected: Some(6),
+                    actual: None,
+                },
+                InvariantViolation::LevelBelowMinimum {
+                    index: 0,
+                    total_amount: 2,
+                    minimum_amount: 3,
+                },
+                InvariantViolation::LevelHasSurplus {
+                    index: 1,
+                    total_amount: 15,
+                    last_amount: 8,
+                    minimum_amount: 5,
+                },
+            ]
+        );
+
+        let violations = check(&levels, &[level(2, 4), level(4, 5)], AskKey::from(6), &rules);
+        assert_eq!(
+            violations,
+            [InvariantViolation::LevelBoundaryMismatch {
+                index: 1,
+                last_price: 4,
+                covered_until: 5,
+            }]
+        );
+
+        let violations = check(&levels, &[level(2, 4), level(5, 5)], AskKey::from(6), &rules);
+        assert_eq!(violations, [InvariantViolation::UncoveredRawLevels { first_price: 6 }]);
+
+        let violations = check(&levels, &[level(2, 4), level(5, 5), level(6, 9)], AskKey::from(6), &rules);
+        assert_eq!(violations, [InvariantViolation::LevelBeyondRawBook { index: 2 }]);
+
+        let violations = check(&levels, &[level(2, 3), level(2, 1), level(6, 13)], AskKey::from(6), &rules);
+        assert_eq!(
+            violations,
+            [
+                InvariantViolation::LevelBelowMinimum {
+                    index: 1,
+                    total_amount: 1,
+                    minimum_amount: 5,
+                },
+                InvariantViolation::RawLevelSplit { index: 0, price: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_over_merged_level_is_reported() {
+        let rules = SubscriptionRules::new([1].into(), 1, 999);
+        let levels = [(1, 2), (2, 2)];
+        assert_eq!(check(&levels, &[level(1, 2), level(2, 2)], AskKey::MAX, &rules), []);
+        assert_eq!(
+            check(&levels, &[level(2, 4)], AskKey::MAX, &rules),
+            [InvariantViolation::LevelHasSurplus {
+                index: 0,
+                total_amount: 4,
+                last_amount: 2,
+                minimum_amount: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_report_is_readable() {
+        let rules = SubscriptionRules::new([3].into(), 1, 5);
+        let violations = check(&[(1, 0)], &[level(1, 0)], AskKey::MAX, &rules);
+        let report = InvariantReport::into_result(violations).unwrap_err();
+        assert_eq!(
+            report.to_string(),
+            "2 invariant violation(s):\n  - raw level 1 has zero amount\n  - aggregated level 0 has zero amount\n"
+        );
+    }
+
+    #[test]
+    fn test_paranoid_mode() {
+        let table = SubscriptionRules::new([2, 5, 3].into(), 1, 2);
+        let mut solution = AggregatedL2::<BidKey>::new(table);
+        solution.set_paranoid(true);
+        for (price, amount) in [(1, 2), (3, 2), (3, 7), (2, 4), (2, 5), (3, 1), (1, 0), (2, 1)] {
+            solution.set_quote(price, amount);
+        }
+        solution.set_subscription_rules(SubscriptionRules::new([1].into(), 1, 1));
+        assert!(solution.check_invariants().is_ok());
+    }
+}
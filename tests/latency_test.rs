@@ -0,0 +1,62 @@
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::latency::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_and_buckets() {
+        assert_eq!(UpdateKind::classify(0, 5), UpdateKind::Insert);
+        assert_eq!(UpdateKind::classify(3, 5), UpdateKind::Increase);
+        assert_eq!(UpdateKind::classify(5, 3), UpdateKind::Decrease);
+        assert_eq!(UpdateKind::classify(5, 0), UpdateKind::Remove);
+        assert_eq!(UpdateKind::classify(0, 0), UpdateKind::Unchanged);
+        assert_eq!(UpdateKind::classify(5, 5), UpdateKind::Unchanged);
+
+        let buckets: Vec<usize> = [0, 1, 2, 3, 4, 7, 8].iter().map(|&length| cascade_bucket(length)).collect();
+        assert_eq!(buckets, [0, 1, 2, 2, 3, 3, 4]);
+        let names: Vec<String> = (0..5).map(cascade_bucket_name).collect();
+        assert_eq!(names, ["0", "1", "2-3", "4-7", "8-15"]);
+    }
+
+    #[test]
+    fn test_cascade_length() {
+        let rules = SubscriptionRules::new(vec![], 10, 100);
+        let mut fast = AggregatedL2::<AskKey>::new(rules.clone());
+        let mut slow = SlowAggregatedL2ForComparisons::<AskKey>::new(rules);
+        for price in 1..=5 {
+            fast.set_quote(price, 10);
+            slow.set_quote(price, 10);
+        }
+        // every level takes the first quote of the next one
+        fast.set_quote(1, 0);
+        assert_eq!(fast.get_last_cascade_length(), Some(4));
+        fast.set_quote(3, 15);
+        assert_eq!(fast.get_last_cascade_length(), Some(0));
+        slow.set_quote(1, 0);
+        assert_eq!(slow.get_last_cascade_length(), None);
+    }
+
+    #[test]
+    fn test_report_counts_every_update() {
+        let trades = read_trades("l2.json");
+        let rules = BookRules::symmetric(SubscriptionRules::new(vec![1e8 as u64], 5e8 as u64, 50));
+        let report = measure_latency::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(&trades, &rules, 2);
+
+        let total = 2 * trades.len() as u64;
+        assert_eq!(report.all.count, total);
+        assert_eq!(report.by_kind.iter().map(|summary| summary.count).sum::<u64>(), total);
+        assert_eq!(report.by_cascade_length.iter().map(|summary| summary.count).sum::<u64>(), total);
+        assert!(report.by_cascade_length.len() > 1);
+        for summary in report.by_kind.iter() {
+            assert!(summary.min <= summary.p50 && summary.p50 <= summary.p99 && summary.p99 <= summary.max);
+        }
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["all"]["count"], total);
+        assert!(json["by_kind"].as_array().unwrap().iter().any(|summary| summary["name"] == "insert"));
+    }
+}
@@ -0,0 +1,114 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::http::*;
+pub use market_data_aggregator::ingest::*;
+pub use market_data_aggregator::metrics::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+type Book = AggregatedBook<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+
+/// Value of the sample with exactly these name and labels
+fn sample(metrics: &str, series: &str) -> Option<u64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' ')?.parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cascades_and_book_sizes() {
+        let mut book = Book::new(BookRules::symmetric(SubscriptionRules::new(vec![], 10, 100)));
+        let mut metrics = PipelineMetrics::new();
+        for price in 1..=5 {
+            book.set_quote(Side::Ask, price, 10);
+            metrics.observe_set_quote("BTC", &book, Side::Ask);
+        }
+        // every level takes the first quote of the next one
+        book.set_quote(Side::Ask, 1, 0);
+        assert_eq!(
+            book.ask().get_last_cascade(),
+            Some(CascadeLengths {
+                surplus: 0,
+                shortage: 4,
+                rebuild: 0,
+            })
+        );
+        metrics.observe_set_quote("BTC", &book, Side::Ask);
+
+        let text = metrics.render();
+        assert_eq!(sample(&text, r#"market_data_updates_total{instrument="BTC",side="Ask"}"#), Some(6));
+        assert_eq!(sample(&text, r#"market_data_updates_total{instrument="BTC",side="Bid"}"#), Some(0));
+        assert_eq!(sample(&text, r#"market_data_raw_levels{instrument="BTC",side="Ask"}"#), Some(4));
+        assert_eq!(sample(&text, r#"market_data_aggregated_levels{instrument="BTC",side="Ask"}"#), Some(4));
+        // each insert after the first pushes one quote to a new level
+        for (direction, sum) in [("surplus", 4), ("shortage", 4), ("rebuild", 0)] {
+            let labels = format!(r#"instrument="BTC",side="Ask",direction="{}""#, direction);
+            assert_eq!(sample(&text, &format!("market_data_cascade_length_count{{{}}}", labels)), Some(6));
+            assert_eq!(sample(&text, &format!("market_data_cascade_length_sum{{{}}}", labels)), Some(sum));
+        }
+        let bucket = |direction: &str, le: &str| {
+            let labels = format!(r#"instrument="BTC",side="Ask",direction="{}",le="{}""#, direction, le);
+            sample(&text, &format!("market_data_cascade_length_bucket{{{}}}", labels))
+        };
+        assert_eq!(bucket("surplus", "0"), Some(2));
+        assert_eq!(bucket("surplus", "1"), Some(6));
+        assert_eq!(bucket("shortage", "2"), Some(5));
+        assert_eq!(bucket("shortage", "4"), Some(6));
+        assert_eq!(bucket("shortage", "+Inf"), Some(6));
+        assert!(text.contains("# TYPE market_data_cascade_length histogram"));
+    }
+
+    #[test]
+    fn test_crossed_books_and_gaps() {
+        let mut book = Book::new(BookRules::symmetric(SubscriptionRules::new(vec![], 10, 100)));
+        let mut metrics = PipelineMetrics::new();
+        let instrument = "odd \"name\"";
+        for (side, price, amount) in [
+            (Side::Bid, 10, 5),
+            (Side::Ask, 9, 5),
+            (Side::Ask, 8, 5),
+            (Side::Ask, 9, 0),
+            (Side::Ask, 8, 0),
+            (Side::Ask, 10, 5),
+        ] {
+            book.set_quote(side, price, amount);
+            metrics.observe_end_of_transaction(instrument, &book);
+        }
+        metrics.observe_ingest_event(instrument, &IngestEvent::Gap { expected: 3, received: 5 });
+        metrics.observe_ingest_event(instrument, &IngestEvent::Duplicate { seq_no: 2 });
+
+        let text = metrics.render();
+        // crossed twice, staying crossed is not a new event
+        let series = r#"market_data_crossed_book_events_total{instrument="odd \"name\""}"#;
+        assert_eq!(sample(&text, series), Some(2));
+        assert_eq!(sample(&text, r#"market_data_seq_gaps_total{instrument="odd \"name\""}"#), Some(1));
+    }
+
+    #[test]
+    fn test_metrics_endpoint() {
+        let registry = Arc::new(BookRegistry::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new());
+        let rules = BookRules::symmetric(SubscriptionRules::new(vec![1e8 as u64], 5e8 as u64, 40));
+        let feed = BufReader::new(File::open("l2.json").unwrap());
+        registry.spawn_feed("BTC", rules, feed).join().unwrap();
+
+        let response = handle_request(&registry, "GET", "/metrics");
+        assert_eq!(response.status, 200);
+        assert!(response.content_type.starts_with("text/plain"));
+        let trades = read_trades("l2.json");
+        for side in [Side::Bid, Side::Ask] {
+            let series = format!(r#"market_data_updates_total{{instrument="BTC",side="{:?}"}}"#, side);
+            let expected = trades.iter().filter(|trade| trade.side == side).count() as u64;
+            assert_eq!(sample(&response.body, &series), Some(expected));
+        }
+        let stats = &registry.stats()["BTC"];
+        let series = r#"market_data_aggregated_levels{instrument="BTC",side="Bid"}"#;
+        assert_eq!(sample(&response.body, series), Some(stats.aggregated_bids as u64));
+    }
+}
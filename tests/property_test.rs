@@ -0,0 +1,266 @@
+pub use market_data_aggregator::common::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Random rules plus a sequence of `set_quote(price, amount)` calls
+#[derive(Clone, Debug)]
+struct Case {
+    rules: SubscriptionRules,
+    updates: Vec<(u64, Amount)>,
+}
+
+fn random_schedule(rng: &mut ChaCha8Rng, max_amount: Amount) -> ThresholdSchedule {
+    let cap = if rng.gen_bool(0.5) {
+        Some(rng.gen_range(1..=max_amount * 4))
+    } else {
+        None
+    };
+    match rng.gen_range(0..4) {
+        0 => ThresholdSchedule::Table {
+            amounts: (0..rng.gen_range(0..8))
+                .map(|_| rng.gen_range(1..=max_amount * 2))
+                .collect(),
+            fallback: rng.gen_range(1..=max_amount * 2),
+        },
+        1 => ThresholdSchedule::Geometric {
+            start: rng.gen_range(1..=max_amount),
+            ratio: rng.gen_range(1.0..2.5),
+            cap,
+        },
+        2 => ThresholdSchedule::Arithmetic {
+            start: rng.gen_range(1..=max_amount),
+            step: rng.gen_range(0..=max_amount),
+            cap,
+        },
+        _ => ThresholdSchedule::TableThen {
+            amounts: (0..rng.gen_range(0..4))
+                .map(|_| rng.gen_range(1..=max_amount * 2))
+                .collect(),
+            then: Box::new(random_schedule(rng, max_amount)),
+        },
+    }
+}
+
+fn random_case(rng: &mut ChaCha8Rng) -> Case {
+    // amounts distribution: small uniform, heavy tailed or mostly deletions
+    let amount_kind = rng.gen_range(0..3);
+    let max_amount: Amount = [20, 1000, 20][amount_kind];
+    let zero_probability = [0.05, 0.2, 0.6][amount_kind];
+    let mut rules =
+        SubscriptionRules::with_schedule(random_schedule(rng, max_amount), rng.gen_range(1..=40));
+    if rng.gen_bool(0.3) {
+        // most random maximums fall below some minimum, retry a few times to keep the case capped
+        for _ in 0..20 {
+            if let Ok(capped) = rules.clone().try_with_maximum_schedule(random_schedule(rng, max_amount)) {
+                rules = capped;
+                break;
+            }
+        }
+    }
+
+    // prices distribution: narrow, wide, or clustered near the touch
+    let price_kind = rng.gen_range(0..3);
+    let max_price: u64 = [10, 500, 60][price_kind];
+    let updates = (0..rng.gen_range(1..=1500))
+        .map(|_| {
+            let price = match price_kind {
+                2 => max_price - (rng.gen_range(1.0f64..max_price as f64).ln() * 12.0) as u64,
+                _ => rng.gen_range(1..=max_price),
+            };
+            let amount = if rng.gen_bool(zero_probability) {
+                0
+            } else if amount_kind == 1 {
+                (rng.gen_range(0.0f64..1.0).powi(4) * max_amount as f64) as Amount + 1
+            } else {
+                rng.gen_range(1..=max_amount)
+            };
+            (price, amount)
+        })
+        .collect();
+    Case { rules, updates }
+}
+
+/// Index of the first update after which `Solution` disagrees with the reference, or panics
+fn find_mismatch<Price: OrderKey, Solution: AgregatedL2Trait<Price>>(
+    case: &Case,
+    prepare: &dyn Fn(&mut Solution),
+) -> Option<usize>
+where
+    u64: From<Price>,
+{
+    let result = catch_unwind(AssertUnwindSafe(|| {
+        let mut solution = Solution::new(case.rules.clone());
+        prepare(&mut solution);
+        let mut reference = SlowAggregatedL2ForComparisons::<Price>::new(case.rules.clone());
+        for (step, &(price, amount)) in case.updates.iter().enumerate() {
+            solution.set_quote(price, amount);
+            reference.set_quote(price, amount);
+            if !solution.raw_levels().eq(reference.raw_levels())
+                || solution.get_aggregated_levels() != reference.get_aggregated_levels()
+                || solution.cumulative_amount_up_to(price) != reference.cumulative_amount_up_to(price)
+                || solution.price_for_cumulative_amount(amount) != reference.price_for_cumulative_amount(amount)
+                || solution.check_invariants().is_err()
+            {
+                return Some(step);
+            }
+        }
+        None
+    }));
+    // a panic is a failure too, shrinking doesn't need to know where exactly it happened
+    result.unwrap_or(Some(case.updates.len()))
+}
+
+fn without_maximum(rules: &SubscriptionRules) -> SubscriptionRules {
+    SubscriptionRules::with_schedule(rules.get_schedule().clone(), rules.max_depth)
+}
+
+fn with_max_depth(rules: &SubscriptionRules, max_depth: usize) -> SubscriptionRules {
+    let result = SubscriptionRules::with_schedule(rules.get_schedule().clone(), max_depth);
+    match rules.get_maximum_schedule() {
+        Some(maximum_schedule) => result.with_maximum_schedule(maximum_schedule.clone()),
+        None => result,
+    }
+}
+
+/// Simpler cases first, so that the greedy search below ends up small
+fn shrink_candidates(case: &Case) -> Vec<Case> {
+    let mut candidates = Vec::new();
+    let length = case.updates.len();
+    let mut chunk = length / 2;
+    while chunk > 0 {
+        for start in (0..length).step_by(chunk) {
+            let mut updates = case.updates.clone();
+            updates.drain(start..(start + chunk).min(length));
+            candidates.push(Case {
+                rules: case.rules.clone(),
+                updates,
+            });
+        }
+        chunk /= 2;
+    }
+    if case.rules.has_maximum_amounts() {
+        candidates.push(Case {
+            rules: without_maximum(&case.rules),
+            updates: case.updates.clone(),
+        });
+    }
+    for max_depth in [1, case.rules.max_depth / 2, case.rules.max_depth.saturating_sub(1)] {
+        if max_depth > 0 && max_depth < case.rules.max_depth {
+            candidates.push(Case {
+                rules: with_max_depth(&case.rules, max_depth),
+                updates: case.updates.clone(),
+            });
+        }
+    }
+    for index in 0..length {
+        let (price, amount) = case.updates[index];
+        for smaller in [(price, amount / 2), (price, 1), (price / 2, amount), (price - 1, amount)] {
+            if smaller.0 > 0 && smaller != (price, amount) && (smaller.1 > 0 || amount == 0) {
+                let mut updates = case.updates.clone();
+                updates[index] = smaller;
+                candidates.push(Case {
+                    rules: case.rules.clone(),
+                    updates,
+                });
+            }
+        }
+    }
+    candidates
+}
+
+fn shrink(mut case: Case, fails: &dyn Fn(&Case) -> Option<usize>) -> Case {
+    loop {
+        if let Some(step) = fails(&case) {
+            case.updates.truncate(step + 1);
+        }
+        match shrink_candidates(&case)
+            .into_iter()
+            .find(|candidate| fails(candidate).is_some())
+        {
+            Some(candidate) => case = candidate,
+            None => return case,
+        }
+    }
+}
+
+fn case_count() -> u64 {
+    std::env::var("PROPERTY_CASES")
+        .ok()
+        .and_then(|cases| cases.parse().ok())
+        .unwrap_or(150)
+}
+
+fn check_against_reference<Price: OrderKey, Solution: AgregatedL2Trait<Price>>(
+    name: &str,
+    prepare: &dyn Fn(&mut Solution),
+) where
+    u64: From<Price>,
+{
+    let seed: u64 = std::env::var("PROPERTY_SEED")
+        .ok()
+        .and_then(|seed| seed.parse().ok())
+        .unwrap_or(0);
+    for case_index in 0..case_count() {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed.wrapping_add(case_index));
+        let case = random_case(&mut rng);
+        let fails = |case: &Case| find_mismatch::<Price, Solution>(case, prepare);
+        if fails(&case).is_none() {
+            continue;
+        }
+        let minimal = shrink(case, &fails);
+        panic!(
+            "{} differs from the reference, seed {}\nminimal case: {:#?}",
+            name,
+            seed.wrapping_add(case_index),
+            minimal
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fast_ask() {
+        check_against_reference::<AskKey, AggregatedL2<AskKey>>("AggregatedL2<AskKey>", &|_| ());
+    }
+
+    #[test]
+    fn test_fast_bid() {
+        check_against_reference::<BidKey, AggregatedL2<BidKey>>("AggregatedL2<BidKey>", &|_| ());
+    }
+
+    #[test]
+    fn test_fast_with_cumulative_index() {
+        check_against_reference::<AskKey, AggregatedL2<AskKey>>(
+            "AggregatedL2<AskKey> with cumulative index",
+            &|solution| solution.enable_cumulative_index(),
+        );
+    }
+
+    #[test]
+    fn test_fast_paranoid() {
+        check_against_reference::<BidKey, AggregatedL2<BidKey>>(
+            "paranoid AggregatedL2<BidKey>",
+            &|solution| solution.set_paranoid(true),
+        );
+    }
+
+    #[test]
+    fn test_shrink_finds_minimal_case() {
+        // a fake bug: every level with price 7 is "wrong"
+        let fails = |case: &Case| case.updates.iter().position(|&(price, amount)| price == 7 && amount > 0);
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let mut case = random_case(&mut rng);
+        case.updates.push((7, 100));
+        let minimal = shrink(case, &fails);
+        assert_eq!(minimal.updates, [(7, 1)]);
+        assert_eq!(minimal.rules.max_depth, 1);
+        assert!(!minimal.rules.has_maximum_amounts());
+    }
+}
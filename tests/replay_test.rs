@@ -0,0 +1,118 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::replay::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+use std::fs::File;
+use std::io::BufReader;
+
+fn converted_fixture() -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let reader = BufReader::new(File::open("l2.json").unwrap());
+    let records = convert_json_lines(reader, &mut bytes).unwrap();
+    assert_eq!(records as usize, read_trades("l2.json").len());
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_round_trip() {
+        let records = [
+            ReplayRecord {
+                platform_time: 1,
+                exchange_time: 2,
+                seq_no: None,
+                side: Side::Ask,
+                price: 165_911_000_000,
+                amount: 186_328_064,
+                is_eot: false,
+            },
+            ReplayRecord {
+                platform_time: u64::MAX,
+                exchange_time: 0,
+                seq_no: Some(0),
+                side: Side::Bid,
+                price: 1,
+                amount: 0,
+                is_eot: true,
+            },
+        ];
+        let mut bytes = Vec::new();
+        {
+            let mut writer = ReplayWriter::new(&mut bytes).unwrap();
+            for record in records.iter() {
+                writer.write(record).unwrap();
+            }
+            assert_eq!(writer.finish().unwrap(), 2);
+        }
+        assert_eq!(bytes.len(), HEADER_SIZE + 2 * RECORD_SIZE);
+        let view = ReplayView::new(&bytes).unwrap();
+        assert_eq!(view.len(), 2);
+        assert!(view.records().eq(records.iter().copied()));
+        assert_eq!(view.get(1), Some(records[1]));
+        assert_eq!(view.get(2), None);
+        assert_eq!(ReplayRecord::from_trade(&records[0].to_trade()), Some(records[0]));
+    }
+
+    #[test]
+    fn test_converted_feed_gives_the_same_book() {
+        let bytes = converted_fixture();
+        let trades = read_trades("l2.json");
+        let view = ReplayView::new(&bytes).unwrap();
+        assert!(view.records().eq(trades.iter().map(|trade| ReplayRecord::from_trade(trade).unwrap())));
+
+        let rules = BookRules::symmetric(SubscriptionRules::new(vec![1e8 as u64], 5e8 as u64, 40));
+        let mut expected = AggregatedBook::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(rules.clone());
+        for trade in trades.iter() {
+            expected.apply_trade(trade);
+        }
+        let path = std::env::temp_dir().join(format!("replay_test_{}.bin", std::process::id()));
+        std::fs::write(&path, &bytes).unwrap();
+        let replay = MappedReplay::open(&path).unwrap();
+        let mut book = AggregatedBook::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(rules);
+        replay.view().apply_to(&mut book);
+        drop(replay);
+        std::fs::remove_file(&path).unwrap();
+        for side in [Side::Bid, Side::Ask] {
+            assert_eq!(book.get_aggregated_levels_tuples(side), expected.get_aggregated_levels_tuples(side));
+        }
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let bytes = converted_fixture();
+        assert!(matches!(ReplayView::new(&bytes[..10]), Err(ReplayError::BadMagic)));
+        assert!(matches!(ReplayView::new(b"L2REPLAX\x01\0\0\0\x30\0\0\0"), Err(ReplayError::BadMagic)));
+
+        let mut other_version = bytes.clone();
+        other_version[8] = 2;
+        assert!(matches!(
+            ReplayView::new(&other_version),
+            Err(ReplayError::UnsupportedVersion { version: 2 })
+        ));
+        assert!(matches!(
+            ReplayView::new(&bytes[..bytes.len() - 1]),
+            Err(ReplayError::Truncated { .. })
+        ));
+        let mut unknown_flags = bytes.clone();
+        unknown_flags[HEADER_SIZE + 3 * RECORD_SIZE + 40] |= 0x80;
+        assert!(matches!(
+            ReplayView::new(&unknown_flags),
+            Err(ReplayError::UnknownFlags { index: 3, .. })
+        ));
+        let mut zero_price = bytes.clone();
+        zero_price[HEADER_SIZE + 5 * RECORD_SIZE + 24..HEADER_SIZE + 5 * RECORD_SIZE + 32].fill(0);
+        assert!(matches!(ReplayView::new(&zero_price), Err(ReplayError::ZeroPrice { index: 5 })));
+
+        let feed = "[1, 1, null, \"Bid\", 10.5, 1.0, true]\n[1, 1, null, \"Bid\", 10.123456789, 1.0, true]\n";
+        let error = convert_json_lines(feed.as_bytes(), Vec::new()).unwrap_err();
+        assert!(matches!(error, ReplayError::InvalidLine { line: 2, .. }));
+        let feed = "[1, 1, null, \"Bid\", 10.5, 1.0, true]\n\
+            {\"snapshot\": {\"platform_time\": 2, \"exchange_time\": 2, \"seq_no\": null, \"bids\": [], \"asks\": []}}\n";
+        let error = convert_json_lines(feed.as_bytes(), Vec::new()).unwrap_err();
+        assert!(matches!(error, ReplayError::SnapshotLine { line: 2 }));
+    }
+}
@@ -0,0 +1,234 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::server::*;
+pub use market_data_aggregator::solutions::fast::*;
+
+mod common;
+use common::{asymmetric_rules, FailingAtEnd};
+
+use std::io::{self, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+/// Reads frames until the end of the feed
+fn replicate(stream: TcpStream) -> BookReplica {
+    let mut reader = BufReader::new(stream);
+    let mut replica = BookReplica::default();
+    while let Some(message) = read_frame(&mut reader).unwrap() {
+        replica.apply(message).unwrap();
+    }
+    replica
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_round_trip() {
+        let messages = [
+            ServerMessage::Snapshot {
+                seq: 3,
+                bids: vec![(10, 5)],
+                asks: vec![],
+            },
+            ServerMessage::Update {
+                seq: 4,
+                side: Side::Ask,
+                from_index: 0,
+                levels: vec![(11, 2), (12, 7)],
+            },
+            ServerMessage::EndOfFeed { seq: 5 },
+        ];
+        let mut buffer = Vec::new();
+        for message in messages.iter() {
+            write_frame(&mut buffer, message).unwrap();
+        }
+        let mut reader = buffer.as_slice();
+        for message in messages.iter() {
+            assert_eq!(read_frame(&mut reader).unwrap().as_ref(), Some(message));
+        }
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+
+        let mut truncated = &buffer[..buffer.len() - 1];
+        for _ in 0..2 {
+            read_frame(&mut truncated).unwrap();
+        }
+        assert!(read_frame(&mut truncated).is_err());
+    }
+
+    #[test]
+    fn test_ladder_update_and_replica() {
+        assert_eq!(ladder_update(&[(1, 2), (3, 4)], &[(1, 2), (3, 4)]), None);
+        assert_eq!(ladder_update(&[(1, 2), (3, 4)], &[(1, 2), (3, 5), (6, 1)]), Some((1, vec![(3, 5), (6, 1)])));
+        assert_eq!(ladder_update(&[(1, 2), (3, 4)], &[(1, 2)]), Some((1, vec![])));
+        assert_eq!(ladder_update(&[], &[(1, 2)]), Some((0, vec![(1, 2)])));
+
+        let mut replica = BookReplica::default();
+        let update = |seq, from_index| ServerMessage::Update {
+            seq,
+            side: Side::Bid,
+            from_index,
+            levels: vec![(9, 1)],
+        };
+        assert_eq!(replica.apply(update(1, 0)), Err(ReplicaError::UpdateBeforeSnapshot));
+        replica
+            .apply(ServerMessage::Snapshot {
+                seq: 7,
+                bids: vec![(10, 5)],
+                asks: vec![],
+            })
+            .unwrap();
+        assert_eq!(replica.apply(update(9, 1)), Err(ReplicaError::SeqGap { expected: 8, received: 9 }));
+        assert_eq!(
+            replica.apply(update(8, 2)),
+            Err(ReplicaError::IndexOutOfRange { from_index: 2, length: 1 })
+        );
+        replica.apply(update(8, 1)).unwrap();
+        assert_eq!(replica.bids, [(10, 5), (9, 1)]);
+    }
+
+    #[test]
+    fn test_invalid_values_are_skipped() {
+        let feed = [
+            "[1, 1, null, \"Bid\", 100.5, 2.0, false]",
+            "[2, 2, null, \"Ask\", -101.0, 1.0, false]",
+            "[3, 3, null, \"Ask\", 101.0, 1e30, true]",
+            "[4, 4, null, \"Ask\", 101.0, 3.0, true]",
+        ]
+        .join("\n");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (feed_reader, mut feed_writer) = std::io::pipe().unwrap();
+        let server = std::thread::spawn(move || {
+            run_tcp_server::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+                listener,
+                BufReader::new(feed_reader),
+                asymmetric_rules(),
+                Duration::from_millis(1),
+            )
+        });
+        let client = TcpStream::connect(address).unwrap();
+        let replica = std::thread::spawn(move || replicate(client));
+        std::thread::sleep(Duration::from_millis(20));
+        writeln!(feed_writer, "{}", feed).unwrap();
+        drop(feed_writer);
+
+        server.join().unwrap().unwrap();
+        let replica = replica.join().unwrap();
+        assert!(replica.is_finished);
+        assert_eq!(replica.bids, [(10_050_000_000, 200_000_000)]);
+        assert_eq!(replica.asks, [(10_100_000_000, 300_000_000)]);
+    }
+
+    #[test]
+    fn test_snapshot_lines_replace_the_book() {
+        let feed = [
+            "[1, 1, null, \"Bid\", 100.5, 2.0, false]",
+            "[2, 2, null, \"Ask\", 101.0, 3.0, true]",
+            r#"{"snapshot": {"platform_time": 3, "exchange_time": 3, "seq_no": null, "bids": [[99.0, 4.0]], "asks": [[102.0, 5.0]]}}"#,
+        ]
+        .join("\n");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (feed_reader, mut feed_writer) = std::io::pipe().unwrap();
+        let server = std::thread::spawn(move || {
+            run_tcp_server::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+                listener,
+                BufReader::new(feed_reader),
+                asymmetric_rules(),
+                Duration::from_millis(1),
+            )
+        });
+        let client = TcpStream::connect(address).unwrap();
+        let replica = std::thread::spawn(move || replicate(client));
+        std::thread::sleep(Duration::from_millis(20));
+        writeln!(feed_writer, "{}", feed).unwrap();
+        drop(feed_writer);
+
+        server.join().unwrap().unwrap();
+        let replica = replica.join().unwrap();
+        assert!(replica.is_finished);
+        assert_eq!(replica.bids, [(9_900_000_000, 400_000_000)]);
+        assert_eq!(replica.asks, [(10_200_000_000, 500_000_000)]);
+    }
+
+    #[test]
+    fn test_feed_error_is_not_end_of_feed() {
+        let feed = [
+            "[1, 1, null, \"Bid\", 100.5, 2.0, true]",
+            "[2, 2, null, \"Ask\", 101.0, 3.0, false]",
+        ]
+        .join("\n");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (feed_reader, mut feed_writer) = std::io::pipe().unwrap();
+        let server = std::thread::spawn(move || {
+            run_tcp_server::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+                listener,
+                BufReader::new(FailingAtEnd(feed_reader)),
+                asymmetric_rules(),
+                Duration::from_millis(1),
+            )
+        });
+        let client = TcpStream::connect(address).unwrap();
+        let replica = std::thread::spawn(move || replicate(client));
+        std::thread::sleep(Duration::from_millis(20));
+        writeln!(feed_writer, "{}", feed).unwrap();
+        drop(feed_writer);
+
+        assert_eq!(server.join().unwrap().unwrap_err().kind(), io::ErrorKind::ConnectionReset);
+        let replica = replica.join().unwrap();
+        assert!(!replica.is_finished);
+        // the unfinished transaction is not published
+        assert_eq!(replica.bids, [(10_050_000_000, 200_000_000)]);
+        assert_eq!(replica.asks, []);
+    }
+
+    #[test]
+    fn test_loopback_clients_see_the_final_book() {
+        let lines: Vec<String> = std::fs::read_to_string("l2.json")
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        let mut expected = AggregatedBook::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(asymmetric_rules());
+        for line in lines.iter() {
+            expected.apply_trade(&parse_trade(line).unwrap());
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (feed, mut feed_writer) = std::io::pipe().unwrap();
+        let server = std::thread::spawn(move || {
+            run_tcp_server::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>(
+                listener,
+                BufReader::new(feed),
+                asymmetric_rules(),
+                Duration::from_millis(1),
+            )
+        });
+
+        let early_client = TcpStream::connect(address).unwrap();
+        let early = std::thread::spawn(move || replicate(early_client));
+        let (first_half, second_half) = lines.split_at(lines.len() / 2);
+        for line in first_half {
+            writeln!(feed_writer, "{}", line).unwrap();
+        }
+        // joins in the middle of the feed, starting from a snapshot
+        let late_client = TcpStream::connect(address).unwrap();
+        let late = std::thread::spawn(move || replicate(late_client));
+        std::thread::sleep(Duration::from_millis(20));
+        for line in second_half {
+            writeln!(feed_writer, "{}", line).unwrap();
+        }
+        drop(feed_writer);
+
+        server.join().unwrap().unwrap();
+        for replica in [early.join().unwrap(), late.join().unwrap()] {
+            assert!(replica.is_finished);
+            assert_eq!(replica.bids, expected.get_aggregated_levels_tuples(Side::Bid));
+            assert_eq!(replica.asks, expected.get_aggregated_levels_tuples(Side::Ask));
+        }
+    }
+}
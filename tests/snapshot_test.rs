@@ -0,0 +1,131 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::ingest::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::solutions::slow_for_comparisons::*;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+type Ingest = FeedIngest<AggregatedL2<AskKey>, AggregatedL2<BidKey>>;
+
+fn random_levels(rng: &mut ChaCha8Rng) -> Vec<(u64, Amount)> {
+    let count = rng.gen_range(0..60);
+    let mut levels: Vec<(u64, Amount)> =
+        (0..count).map(|_| (rng.gen_range(1..100), rng.gen_range(0..20))).collect();
+    levels.sort_by_key(|&(price, _)| price);
+    levels.dedup_by_key(|&mut (price, _)| price);
+    levels
+}
+
+/// `replace_levels` on a used ladder must give the same state as `set_quote`s on a fresh one
+fn run_replace<Price: OrderKey>(rules: SubscriptionRules)
+where
+    u64: From<Price>,
+{
+    let mut rng = ChaCha8Rng::seed_from_u64(4);
+    let mut fast = AggregatedL2::<Price>::new(rules.clone());
+    fast.set_paranoid(true);
+    let mut slow = SlowAggregatedL2ForComparisons::<Price>::new(rules.clone());
+    for round in 0..200 {
+        for _ in 0..20 {
+            let (price, amount) = (rng.gen_range(1..100), rng.gen_range(0..20));
+            fast.set_quote(price, amount);
+            slow.set_quote(price, amount);
+        }
+        let levels = random_levels(&mut rng);
+        if round % 2 == 0 {
+            fast.enable_cumulative_index();
+        }
+        fast.replace_levels(levels.iter().rev().copied());
+        slow.replace_levels(levels.iter().copied());
+
+        let mut expected = AggregatedL2::<Price>::new(rules.clone());
+        for &(price, amount) in levels.iter() {
+            expected.set_quote(price, amount);
+        }
+        assert!(fast.raw_levels().eq(expected.raw_levels()));
+        assert_eq!(fast.get_aggregated_levels(), expected.get_aggregated_levels());
+        assert_eq!(fast.get_max_depth_price(), expected.get_max_depth_price());
+        assert_eq!(fast.get_aggregated_levels_tuples(), slow.get_aggregated_levels_tuples());
+        let price = rng.gen_range(1..100);
+        assert_eq!(fast.cumulative_amount_up_to(price), expected.cumulative_amount_up_to(price));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_levels_matches_fresh_book() {
+        run_replace::<AskKey>(SubscriptionRules::new(vec![2, 6, 15], 12, 30));
+        run_replace::<BidKey>(SubscriptionRules::new(vec![2, 6, 15], 12, 30));
+        let capped = SubscriptionRules::new(vec![5], 8, 30).with_maximum_schedule(ThresholdSchedule::Arithmetic {
+            start: 8,
+            step: 2,
+            cap: Some(20),
+        });
+        run_replace::<AskKey>(capped.clone());
+        run_replace::<BidKey>(capped);
+    }
+
+    #[test]
+    fn test_feed_messages_round_trip() {
+        let snapshot = FeedSnapshot {
+            platform_time: 1,
+            exchange_time: 2,
+            seq_no: Some(7),
+            bids: vec![(99.5, 1.0), (99.0, 2.5)],
+            asks: vec![(100.0, 0.25)],
+        };
+        let line = format_feed_message(&FeedMessage::Snapshot(snapshot.clone()));
+        match parse_feed_message(&line).unwrap() {
+            FeedMessage::Snapshot(parsed) => assert_eq!(parsed, snapshot),
+            FeedMessage::Delta(_) => panic!("snapshot parsed as a delta"),
+        }
+        let delta = parse_feed_message("[3, 3, 8, \"Bid\", 99.5, 0.0, true]").unwrap();
+        assert!(matches!(delta, FeedMessage::Delta(Trade { seq_no: Some(8), .. })));
+        assert!(parse_feed_message("{\"snapshot\": {\"bids\": []}}").is_err());
+    }
+
+    #[test]
+    fn test_snapshot_message_recovers_from_gap() {
+        let rules = BookRules::symmetric(SubscriptionRules::new(vec![1e8 as u64], 2e8 as u64, 10));
+        let mut ingest = Ingest::new(rules);
+        assert_eq!(ingest.apply_line("[0, 0, 1, \"Bid\", 99.0, 1.0, true]"), IngestEvent::Applied);
+        let gap = ingest.apply_line("[0, 0, 3, \"Bid\", 98.0, 1.0, true]");
+        assert_eq!(gap, IngestEvent::Gap { expected: 2, received: 3 });
+        assert_eq!(ingest.apply_line("[0, 0, 4, \"Ask\", 101.0, 1.0, true]"), IngestEvent::Skipped);
+
+        let snapshot = r#"{"snapshot": {"platform_time": 5, "exchange_time": 5, "seq_no": 4,
+            "bids": [[98.0, 1.0], [97.0, 3.0]], "asks": [[101.0, 1.0]]}}"#;
+        assert_eq!(ingest.apply_line(snapshot), IngestEvent::Snapshot);
+        assert!(!ingest.is_stale());
+        assert_eq!(ingest.expected_seq_no(), Some(5));
+        // the level at 99 is not in the snapshot, so it is gone
+        let bids = ingest.book().bid().raw_levels().collect::<Vec<_>>();
+        assert_eq!(bids, [(98e8 as u64, 1e8 as u64), (97e8 as u64, 3e8 as u64)]);
+        assert_eq!(ingest.apply_line(snapshot), IngestEvent::Duplicate { seq_no: 4 });
+        assert_eq!(ingest.apply_line("[6, 6, 5, \"Ask\", 101.0, 0.0, true]"), IngestEvent::Applied);
+        assert_eq!(ingest.book().ask().raw_levels_count(), 0);
+        assert_eq!(ingest.stats().snapshots, 1);
+    }
+
+    #[test]
+    fn test_replace_side_keeps_other_side() {
+        let rules = BookRules::symmetric(SubscriptionRules::new(vec![], 3, 10));
+        let mut book = AggregatedBook::<AggregatedL2<AskKey>, AggregatedL2<BidKey>>::new(rules);
+        book.set_quote(Side::Bid, 1, 7);
+        book.set_quote(Side::Ask, 5, 1);
+        book.replace_side(Side::Bid, [(3, 2), (4, 0), (2, 1)]);
+        assert_eq!(book.get_aggregated_levels_tuples(Side::Bid), [(2, 3)]);
+        assert_eq!(book.get_aggregated_levels_tuples(Side::Ask), [(5, 1)]);
+
+        // price 0 is the bid sentinel, it never gets into the book
+        book.apply_snapshot([(0, 5), (3, 3)], [(0, 5)]);
+        assert!(book.bid().raw_levels().eq([(3, 3)]));
+        assert_eq!(book.ask().raw_levels_count(), 0);
+        book.bid().check_invariants().unwrap();
+    }
+}
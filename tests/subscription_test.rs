@@ -0,0 +1,185 @@
+pub use market_data_aggregator::subscription::*;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn amounts(rules: &SubscriptionRules, count: usize) -> Vec<u64> {
+        (0..count).map(|index| rules.get_amount(index)).collect()
+    }
+
+    #[test]
+    fn test_schedules() {
+        let table = SubscriptionRules::new(vec![3, 5], 1, 10);
+        assert_eq!(amounts(&table, 4), [3, 5, 1, 1]);
+
+        let geometric = ThresholdSchedule::Geometric {
+            start: 10,
+            ratio: 1.5,
+            cap: Some(50),
+        };
+        let rules = SubscriptionRules::with_schedule(geometric, 10);
+        assert_eq!(amounts(&rules, 6), [10, 15, 23, 34, 50, 50]);
+        assert_eq!(rules.get_amount(usize::MAX), 50);
+
+        let arithmetic = ThresholdSchedule::Arithmetic {
+            start: 4,
+            step: 3,
+            cap: None,
+        };
+        let rules = SubscriptionRules::with_schedule(arithmetic, 10);
+        assert_eq!(amounts(&rules, 4), [4, 7, 10, 13]);
+        assert_eq!(rules.get_amount(usize::MAX), u64::MAX);
+
+        let table_then = ThresholdSchedule::TableThen {
+            amounts: vec![100, 1],
+            then: Box::new(ThresholdSchedule::Geometric {
+                start: 2,
+                ratio: 2.0,
+                cap: None,
+            }),
+        };
+        let rules = SubscriptionRules::with_schedule(table_then, 10);
+        assert_eq!(amounts(&rules, 5), [100, 1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        let geometric = |ratio| ThresholdSchedule::Geometric {
+            start: 1,
+            ratio,
+            cap: None,
+        };
+        assert_eq!(
+            SubscriptionRules::try_with_schedule(geometric(0.5), 1),
+            Err(SubscriptionRulesError::InvalidRatio)
+        );
+        assert_eq!(
+            SubscriptionRules::try_with_schedule(geometric(f64::NAN), 1),
+            Err(SubscriptionRulesError::InvalidRatio)
+        );
+        let capped = ThresholdSchedule::Arithmetic {
+            start: 1,
+            step: 1,
+            cap: Some(0),
+        };
+        assert_eq!(
+            SubscriptionRules::try_with_schedule(capped, 1),
+            Err(SubscriptionRulesError::ZeroCap)
+        );
+        let nested = ThresholdSchedule::TableThen {
+            amounts: vec![1],
+            then: Box::new(ThresholdSchedule::Table {
+                amounts: vec![2, 0],
+                fallback: 1,
+            }),
+        };
+        assert_eq!(
+            SubscriptionRules::try_with_schedule(nested, 1),
+            Err(SubscriptionRulesError::ZeroMinimumAmount { index: 1 })
+        );
+        let maximum = ThresholdSchedule::Table {
+            amounts: vec![4],
+            fallback: 6,
+        };
+        assert_eq!(
+            SubscriptionRules::new(vec![3, 5, 8], 2, 10).try_with_maximum_schedule(maximum.clone()),
+            Err(SubscriptionRulesError::MaximumBelowMinimum {
+                index: 2,
+                maximum_amount: 6,
+                minimum_amount: 8,
+            })
+        );
+        // levels beyond the max depth are never built
+        assert!(SubscriptionRules::new(vec![3, 5, 8], 2, 2).try_with_maximum_schedule(maximum).is_ok());
+        let growing = ThresholdSchedule::Arithmetic {
+            start: 1,
+            step: 2,
+            cap: None,
+        };
+        let maximum = ThresholdSchedule::Arithmetic {
+            start: 10,
+            step: 1,
+            cap: None,
+        };
+        assert_eq!(
+            SubscriptionRules::with_schedule(growing, 100).try_with_maximum_schedule(maximum),
+            Err(SubscriptionRulesError::MaximumBelowMinimum {
+                index: 10,
+                maximum_amount: 20,
+                minimum_amount: 21,
+            })
+        );
+    }
+
+    #[test]
+    fn test_huge_max_depth() {
+        let growing = ThresholdSchedule::Arithmetic {
+            start: 1,
+            step: 1,
+            cap: None,
+        };
+        let maximum = ThresholdSchedule::Arithmetic {
+            start: 10,
+            step: 2,
+            cap: None,
+        };
+        // only a maximum schedule needs the bound, and only while the minimum keeps growing
+        let deep = SubscriptionRules::with_schedule(growing.clone(), usize::MAX);
+        assert_eq!(
+            deep.try_with_maximum_schedule(maximum.clone()),
+            Err(SubscriptionRulesError::MaxDepthTooLarge { max_depth: usize::MAX })
+        );
+        let table = ThresholdSchedule::Table {
+            amounts: vec![20],
+            fallback: 30,
+        };
+        assert!(SubscriptionRules::new(vec![5, 10], 20, usize::MAX).try_with_maximum_schedule(table).is_ok());
+        let config = r#"{"schedule": {"kind": "arithmetic", "start": 1, "step": 1},
+            "maximum_schedule": {"kind": "arithmetic", "start": 10, "step": 2}, "max_depth": 18446744073709551615}"#;
+        assert!(serde_json::from_str::<BookRules>(config).is_err());
+
+        // the deepest accepted rules still validate quickly when neither schedule becomes constant
+        let start = std::time::Instant::now();
+        let rules = SubscriptionRules::with_schedule(growing, MAX_DEPTH_LIMIT);
+        assert!(rules.try_with_maximum_schedule(maximum).is_ok());
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        let rules: SubscriptionRules = serde_json::from_str(
+            r#"{"schedule": {"kind": "arithmetic", "start": 5, "step": 5, "cap": 12}, "max_depth": 7}"#,
+        )
+        .unwrap();
+        assert_eq!(amounts(&rules, 3), [5, 10, 12]);
+
+        let both = r#"{"minimum_amounts": [1], "fallback": 1, "schedule": {"kind": "arithmetic", "start": 5, "step": 5}, "max_depth": 7}"#;
+        assert!(serde_json::from_str::<SubscriptionRules>(both).is_err());
+        assert!(serde_json::from_str::<SubscriptionRules>(r#"{"minimum_amounts": [1], "max_depth": 7}"#).is_err());
+
+        let book: BookRules = serde_json::from_str(
+            r#"{"minimum_amounts": [3, 5], "fallback": 1, "max_depth": 7,
+                "bid": {"fallback": 2},
+                "ask": {"schedule": {"kind": "geometric", "start": 1, "ratio": 3.0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(amounts(&book.bid, 3), [3, 5, 2]);
+        assert_eq!(amounts(&book.ask, 3), [1, 3, 9]);
+        assert_eq!(book.ask.max_depth, 7);
+
+        let capped: BookRules = serde_json::from_str(
+            r#"{"minimum_amounts": [3], "fallback": 1, "max_depth": 7,
+                "maximum_schedule": {"kind": "table", "amounts": [4], "fallback": 2},
+                "ask": {"maximum_schedule": {"kind": "arithmetic", "start": 10, "step": 0}}}"#,
+        )
+        .unwrap();
+        assert_eq!(capped.bid.get_maximum_amount(1), Some(2));
+        assert_eq!(capped.ask.get_maximum_amount(1), Some(10));
+        assert!(!SubscriptionRules::new(vec![1], 1, 1).has_maximum_amounts());
+
+        let partial_table_over_schedule = r#"{"schedule": {"kind": "arithmetic", "start": 5, "step": 5}, "max_depth": 7,
+            "bid": {"fallback": 2}}"#;
+        assert!(serde_json::from_str::<BookRules>(partial_table_over_schedule).is_err());
+    }
+}
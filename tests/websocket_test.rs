@@ -0,0 +1,235 @@
+pub use market_data_aggregator::book::*;
+pub use market_data_aggregator::feed::*;
+pub use market_data_aggregator::server::*;
+pub use market_data_aggregator::solutions::fast::*;
+pub use market_data_aggregator::websocket::*;
+
+use std::io::{BufRead, BufReader, PipeWriter, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+mod common;
+use common::FailingAtEnd;
+
+const FINE_RULES: &str = r#"{"minimum_amounts": [100000000], "fallback": 500000000, "max_depth": 40}"#;
+const COARSE_RULES: &str =
+    r#"{"minimum_amounts": [200000000, 1000000000], "fallback": 3000000000, "max_depth": 60, "bid": {"max_depth": 5}}"#;
+
+type Socket = WebSocket<TcpStream>;
+
+fn feed_lines() -> Vec<String> {
+    std::fs::read_to_string("l2.json")
+        .unwrap()
+        .lines()
+        .map(str::to_string)
+        .collect()
+}
+
+fn expected_book(rules: &str, lines: &[String]) -> AggregatedBook<AggregatedL2<AskKey>, AggregatedL2<BidKey>> {
+    let mut book = AggregatedBook::new(serde_json::from_str(rules).unwrap());
+    for line in lines {
+        book.apply_trade(&parse_trade(line).unwrap());
+    }
+    book
+}
+
+/// Server with a piped feed per instrument
+fn start_server(instruments: &[&str]) -> (SocketAddr, Vec<PipeWriter>, JoinHandle<std::io::Result<()>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let mut feeds: Vec<(String, Box<dyn BufRead + Send>)> = Vec::new();
+    let mut writers = Vec::new();
+    for instrument in instruments {
+        let (feed, writer) = std::io::pipe().unwrap();
+        feeds.push((instrument.to_string(), Box::new(BufReader::new(feed))));
+        writers.push(writer);
+    }
+    let server = std::thread::spawn(move || {
+        run_websocket_server(listener, feeds, Duration::from_millis(1))
+    });
+    (address, writers, server)
+}
+
+fn connect(address: SocketAddr) -> Socket {
+    let stream = TcpStream::connect(address).unwrap();
+    tungstenite::client(format!("ws://{}/", address), stream).unwrap().0
+}
+
+fn subscribe(socket: &mut Socket, instrument: &str, rules: &str) {
+    let request = format!(r#"{{"type": "subscribe", "instrument": "{}", "rules": {}}}"#, instrument, rules);
+    socket.send(Message::text(request)).unwrap();
+}
+
+/// `None` once the server closes the connection
+fn next_message(socket: &mut Socket) -> Option<WebSocketMessage> {
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => return Some(serde_json::from_str(&text).unwrap()),
+            Ok(Message::Close(_)) | Err(_) => return None,
+            Ok(_) => (),
+        }
+    }
+}
+
+/// Applies everything up to the end of the connection, checking that all messages are for `instrument`
+fn replicate(mut socket: Socket, instrument: &'static str, mut replica: BookReplica) -> JoinHandle<BookReplica> {
+    std::thread::spawn(move || {
+        while let Some(message) = next_message(&mut socket) {
+            let (received, message) = message.into_server_message().unwrap();
+            assert_eq!(received, instrument);
+            replica.apply(message).unwrap();
+        }
+        replica
+    })
+}
+
+/// Waits for the snapshot, so the subscription is known to be active
+fn subscribed_replica(socket: &mut Socket, instrument: &str) -> BookReplica {
+    let (received, snapshot) = next_message(socket).unwrap().into_server_message().unwrap();
+    assert_eq!(received, instrument);
+    assert!(matches!(snapshot, ServerMessage::Snapshot { .. }));
+    let mut replica = BookReplica::default();
+    replica.apply(snapshot).unwrap();
+    replica
+}
+
+fn write_lines(writer: &mut PipeWriter, lines: &[String]) {
+    for line in lines {
+        writeln!(writer, "{}", line).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clients_with_different_rules() {
+        let lines = feed_lines();
+        let (address, mut writers, server) = start_server(&["BTC"]);
+
+        let mut fine = connect(address);
+        subscribe(&mut fine, "BTC", FINE_RULES);
+        let fine_replica = subscribed_replica(&mut fine, "BTC");
+        let mut coarse = connect(address);
+        subscribe(&mut coarse, "BTC", COARSE_RULES);
+        let coarse_replica = subscribed_replica(&mut coarse, "BTC");
+        subscribe(&mut coarse, "DOGE", FINE_RULES);
+        assert!(matches!(next_message(&mut coarse), Some(WebSocketMessage::Error { .. })));
+        coarse.send(Message::text("not a request")).unwrap();
+        assert!(matches!(next_message(&mut coarse), Some(WebSocketMessage::Error { .. })));
+        let fine = replicate(fine, "BTC", fine_replica);
+        let coarse = replicate(coarse, "BTC", coarse_replica);
+
+        let (first_half, second_half) = lines.split_at(lines.len() / 2);
+        write_lines(&mut writers[0], first_half);
+        // shares the ladder of the first client, starting from the middle of the feed
+        let mut late = connect(address);
+        subscribe(&mut late, "BTC", FINE_RULES);
+        let late_replica = subscribed_replica(&mut late, "BTC");
+        let late = replicate(late, "BTC", late_replica);
+        write_lines(&mut writers[0], second_half);
+        writers.clear();
+
+        server.join().unwrap().unwrap();
+        let fine_book = expected_book(FINE_RULES, &lines);
+        let coarse_book = expected_book(COARSE_RULES, &lines);
+        assert_ne!(
+            fine_book.get_aggregated_levels_tuples(Side::Bid),
+            coarse_book.get_aggregated_levels_tuples(Side::Bid)
+        );
+        for (replica, book) in [(fine, &fine_book), (late, &fine_book), (coarse, &coarse_book)] {
+            let replica = replica.join().unwrap();
+            assert!(replica.is_finished);
+            assert_eq!(replica.bids, book.get_aggregated_levels_tuples(Side::Bid));
+            assert_eq!(replica.asks, book.get_aggregated_levels_tuples(Side::Ask));
+        }
+    }
+
+    #[test]
+    fn test_feed_error_is_not_end_of_feed() {
+        let lines = feed_lines();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (feed, mut writer) = std::io::pipe().unwrap();
+        let feeds: Vec<(String, Box<dyn BufRead + Send>)> =
+            vec![("BTC".to_string(), Box::new(BufReader::new(FailingAtEnd(feed))))];
+        let server = std::thread::spawn(move || {
+            run_websocket_server(listener, feeds, Duration::from_millis(1))
+        });
+
+        let mut socket = connect(address);
+        subscribe(&mut socket, "BTC", FINE_RULES);
+        let mut replica = subscribed_replica(&mut socket, "BTC");
+        let is_eot: Vec<bool> = lines.iter().map(|line| parse_trade(line).unwrap().is_eot).collect();
+        let finished = (lines.len() / 2..lines.len()).find(|&index| is_eot[index - 1] && !is_eot[index]).unwrap();
+        // the feed breaks in the middle of a transaction
+        write_lines(&mut writer, &lines[..finished + 1]);
+        drop(writer);
+
+        let mut is_error_received = false;
+        while let Some(message) = next_message(&mut socket) {
+            match message.into_server_message() {
+                Some((_, message)) => replica.apply(message).unwrap(),
+                None => is_error_received = true,
+            }
+        }
+        server.join().unwrap().unwrap();
+        assert!(is_error_received);
+        assert!(!replica.is_finished);
+        let book = expected_book(FINE_RULES, &lines[..finished]);
+        assert_eq!(replica.bids, book.get_aggregated_levels_tuples(Side::Bid));
+        assert_eq!(replica.asks, book.get_aggregated_levels_tuples(Side::Ask));
+    }
+
+    #[test]
+    fn test_snapshot_lines_replace_the_book() {
+        let lines = feed_lines();
+        let (address, mut writers, server) = start_server(&["BTC"]);
+        let mut socket = connect(address);
+        subscribe(&mut socket, "BTC", FINE_RULES);
+        let replica = subscribed_replica(&mut socket, "BTC");
+        let replica = replicate(socket, "BTC", replica);
+
+        write_lines(&mut writers[0], &lines);
+        let snapshot = r#"{"snapshot": {"platform_time": 1, "exchange_time": 1, "seq_no": null, "bids": [[99.0, 4.0]], "asks": [[102.0, 5.0]]}}"#;
+        write_lines(&mut writers[0], &[snapshot.to_string()]);
+        writers.clear();
+
+        server.join().unwrap().unwrap();
+        let replica = replica.join().unwrap();
+        assert!(replica.is_finished);
+        assert_eq!(replica.bids, [(9_900_000_000, 400_000_000)]);
+        assert_eq!(replica.asks, [(10_200_000_000, 500_000_000)]);
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let lines = feed_lines();
+        let (address, mut writers, server) = start_server(&["BTC", "ETH"]);
+
+        let mut socket = connect(address);
+        subscribe(&mut socket, "BTC", FINE_RULES);
+        subscribed_replica(&mut socket, "BTC");
+        let request = r#"{"type": "unsubscribe", "instrument": "BTC"}"#;
+        socket.send(Message::text(request)).unwrap();
+        // requests are handled in order, so BTC is unsubscribed once this snapshot arrives
+        subscribe(&mut socket, "ETH", COARSE_RULES);
+        let replica = subscribed_replica(&mut socket, "ETH");
+        let replica = replicate(socket, "ETH", replica);
+
+        for writer in writers.iter_mut() {
+            write_lines(writer, &lines);
+        }
+        writers.clear();
+
+        server.join().unwrap().unwrap();
+        let book = expected_book(COARSE_RULES, &lines);
+        let replica = replica.join().unwrap();
+        assert!(replica.is_finished);
+        assert_eq!(replica.bids, book.get_aggregated_levels_tuples(Side::Bid));
+        assert_eq!(replica.asks, book.get_aggregated_levels_tuples(Side::Ask));
+    }
+}